@@ -8,7 +8,7 @@ fn main() -> Result<(), GDError> {
     let mut level = GDLevel::from_gmd("test_gmds/level.gmd")?;
 
     // Get level data, which is None only if it hasn't been initialized.
-    if let Some(data) = level.get_decrypted_data_ref() {
+    if let Some(data) = level.get_decrypted_data_ref()? {
         // Add group 42 to all objects
         for obj in data.objects.iter_mut() {
             obj.config.add_group(Group::Regular(42));
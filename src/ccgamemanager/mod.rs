@@ -1,17 +1,23 @@
 //! This module covers everything in the CCGameManager.dat file.
 
-use std::{array, collections::HashMap, hash::BuildHasherDefault, io::Cursor};
+use std::{collections::HashMap, hash::BuildHasherDefault, str::FromStr};
 
 use nohash_hasher::NoHashHasher;
 use plist::{Dictionary, Value};
 
 use crate::{
     cclocallevels::gdlevel::{GDLevel, PLIST_HEADER},
-    core::{GDError, get_ccgamemanager_path, io::decrypt_file, proper_plist_tags},
+    core::{
+        GDError, gdplist, get_ccgamemanager_path,
+        io::decrypt_file,
+        parse::PlistParser,
+        values::{as_bool, as_dict, as_float, as_int, as_str, describe, invalid, parse_str},
+    },
     repr_t,
 };
 
 type IntMap<V> = HashMap<i32, V, BuildHasherDefault<NoHashHasher<i32>>>;
+type FieldParser<T> = fn(&mut T, &str) -> Result<(), GDError>;
 
 /// Container struct for the CCGameManager.dat file
 #[derive(Debug, Default, Clone)]
@@ -39,57 +45,84 @@ impl CCGameManager {
     /// Parses the local CCGameManager.dat file if it exists and is a valid file.
     pub fn from_local() -> Result<Self, GDError> {
         let path = get_ccgamemanager_path().ok_or(GDError::MissingSavefile)?;
-        Self::from_raw_string(decrypt_file(path).unwrap())
+        Self::from_raw_string(decrypt_file(path)?)
     }
 
     /// Parses a raw plist to this object
+    ///
+    /// The root dictionary is read one entry at a time, so the document is never held as a whole `plist::Value`.
     pub fn from_raw_string(s: String) -> Result<Self, GDError> {
         if !s.starts_with(PLIST_HEADER) {
             return Err(GDError::CorruptedSavefile("Savefile header does not match the expected header. This may be due to a corrupted savefile or a savefile from a previous version of GD.".into()));
         };
 
-        let xmltree = match Value::from_reader_xml(Cursor::new(proper_plist_tags(s)?.as_bytes())) {
-            Ok(v) => v.into_dictionary().unwrap(),
-            Err(e) => return Err(GDError::BadPlist(e)),
-        };
+        gdplist::parse_with_fallback(
+            &s,
+            PlistParser::Native,
+            |xml| {
+                let root = gdplist::root_value(xml)?;
+                if gdplist::dict_content(xml, root.clone()).is_none() {
+                    return Err(not_a_dict(&gdplist::parse_value(xml, root)?));
+                }
+                let mut this = Self::default();
+                gdplist::for_each_entry(xml, root, |k, v| this.parse_entry(k, v))?;
+                Ok(this)
+            },
+            |root| {
+                let Value::Dictionary(dict) = root else {
+                    return Err(not_a_dict(&root));
+                };
+                let mut this = Self::default();
+                for (k, v) in dict {
+                    this.parse_entry(&k, v)?;
+                }
+                Ok(this)
+            },
+        )
+    }
 
-        let mut this = Self::default();
-        if let None = this.parse_dict(xmltree) {
-            return Err(GDError::CorruptedSavefile(
-                "Unable to parse corrupted savefile.".into(),
-            ));
+    // stores `v` in `other_properties` if `k` is not a known key
+    fn parse_entry(&mut self, k: &str, v: Value) -> Result<(), GDError> {
+        if !self.parse_key(k, &v).map_err(|e| e.at(k))? {
+            self.other_properties.insert(k.to_owned(), v);
         }
-
-        Ok(this)
+        Ok(())
     }
 
-    fn parse_dict(&mut self, dict: Dictionary) -> Option<()> {
-        let mut d = dict;
+    // returns whether `k` is a known key
+    fn parse_key(&mut self, k: &str, v: &Value) -> Result<bool, GDError> {
         // string
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("playerUDID", &mut self.player_info.udid),
                 ("playerName", &mut self.player_info.username),
                 ("GJA_001", &mut self.account.username),
             ],
-            |v| v.as_string().map(|v| v.to_string()),
-        )?;
+            |v| as_str(v).map(str::to_owned),
+        )? {
+            return Ok(true);
+        }
 
         // opt. string
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("GJA_002", &mut self.account.plaintext_password),
                 ("GJA_004", &mut self.account.session_id),
                 ("GJA_005", &mut self.account.hashed_password),
             ],
-            |v| v.as_string().map(|v| Some(v.to_string())),
-        )?;
+            |v| as_str(v).map(|v| Some(v.to_owned())),
+        )? {
+            return Ok(true);
+        }
 
         // i32
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("playerUserID", &mut self.player_info.user_id),
                 ("playerFrame", &mut self.player_info.icon_cube),
@@ -116,12 +149,15 @@ impl CCGameManager {
                 ("GLM_11", &mut self.temp_state.current_daily_level),
                 ("GLM_17", &mut self.temp_state.current_weekly_level),
             ],
-            |v| v.as_signed_integer().map(|v| v as i32),
-        )?;
+            as_int,
+        )? {
+            return Ok(true);
+        }
 
         // bool
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("playerGlow", &mut self.player_info.using_glow),
                 ("hasRP", &mut self.player_info.is_moderator),
@@ -138,12 +174,15 @@ impl CCGameManager {
                 ),
                 ("hasRatedGame", &mut self.config.has_rated_game),
             ],
-            |v| v.as_boolean(),
-        )?;
+            as_bool,
+        )? {
+            return Ok(true);
+        }
 
         // f32
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("bgVolume", &mut self.config.bgm_volume),
                 ("sfxVolume", &mut self.config.sfx_volume),
@@ -152,57 +191,32 @@ impl CCGameManager {
                 ("practiceOpacity", &mut self.config.practice_ui_opacity),
                 ("customFPSTarget", &mut self.config.fps_target),
             ],
-            |v| v.as_real().map(|v| v as f32),
-        )?;
-
-        for i in 0..5 {
-            parse_val(&mut d, &format!("dpad0{}", i + 1), |v| {
-                self.config.dpads[i] = GDPlatformerUI::from_str(v.as_string().unwrap());
-                Some(())
-            })?;
+            as_float,
+        )? {
+            return Ok(true);
         }
 
-        self.config.dpad_layout = d
-            .get("dpad_layout")
-            .map(|v| GDPlatformerUI::from_str(v.as_string().unwrap()));
-
-        parse_val(&mut d, "resolution", |v| {
-            self.config.resolution = Resolution::try_from(v.as_signed_integer()? as i32).ok()?;
-            Some(())
-        })?;
-        parse_val(&mut d, "texQuality", |v| {
-            self.config.text_quality =
-                TextureQuality::try_from(v.as_signed_integer()? as i32).ok()?;
-            Some(())
-        })?;
-
-        parse_val(&mut d, "KBM_001", |v| {
-            self.keybinds.0 = v.as_dictionary()?.clone();
-            Some(())
-        })?;
-
-        parse_val(&mut d, "KBM_002", |v| {
-            self.keybinds.1 = v.as_dictionary()?.clone();
-            Some(())
-        })?;
-
         // {level_id: GDlevel}
         // common format for storing lists of levels
         // though, some level lists follow a slightly different schema for keys
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("GLM_01", &mut self.stats.official_level_progresses),
                 ("GLM_03", &mut self.stats.online_levels_played),
                 ("GLM_16", &mut self.stats.gauntlet_levels_played),
             ],
-            |v| parse_level_dict(&v),
-        )?;
+            parse_level_dict,
+        )? {
+            return Ok(true);
+        }
 
         // {i32: "1"}
         // robtop seems to use this format for lists of things
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("GLM_06", &mut self.account.following_creators),
                 ("GLM_07", &mut self.temp_state.last_played_levels),
@@ -211,124 +225,132 @@ impl CCGameManager {
                 ("GLM_15", &mut self.stats.submitted_ratings_demons),
             ],
             |v| {
-                Some(
-                    v.as_dictionary()?
-                        .iter()
-                        .map(|(k, _)| k.parse::<i32>().unwrap())
-                        .collect::<Vec<_>>(),
-                )
+                as_dict(v)?
+                    .keys()
+                    .map(|k| parse_str::<i32>(k, "integer key").map_err(|e| e.at(k)))
+                    .collect()
             },
-        )?;
+        )? {
+            return Ok(true);
+        }
 
-        parse_values(
-            &mut d,
+        if parse_field(
+            k,
+            v,
             &mut [
                 ("GLM_18", &mut self.config.saved_levels_foldernames),
                 ("GLM_19", &mut self.config.local_levels_foldernames),
             ],
             parse_foldernames,
-        )?;
+        )? {
+            return Ok(true);
+        }
 
-        parse_val(
-            &mut d,
-            "GLM_12",
+        match k {
+            "dpad_layout" => self.config.dpad_layout = Some(as_str(v)?.parse()?),
+            "resolution" => {
+                self.config.resolution =
+                    Resolution::try_from(as_int(v)?).map_err(|n| invalid("resolution", n))?;
+            }
+            "texQuality" => {
+                self.config.text_quality = TextureQuality::try_from(as_int(v)?)
+                    .map_err(|n| invalid("texture quality", n))?;
+            }
+            "KBM_001" => self.keybinds.0 = as_dict(v)?.clone(),
+            "KBM_002" => self.keybinds.1 = as_dict(v)?.clone(),
             // keys always of the form `likes_a_b_c_d` where a, b, c, d are i32
-            |v| {
-                self.config.glm12_unknown = v
-                    .as_dictionary()?
+            "GLM_12" => {
+                self.config.glm12_unknown = as_dict(v)?
+                    .keys()
+                    .map(|k| parse_glm12_key(k).map_err(|e| e.at(k)))
+                    .collect::<Result<Vec<_>, _>>()?;
+            }
+            "GLM_10" => {
+                self.stats.completed_dailies = as_dict(v)?
                     .iter()
-                    .map(|(k, _)| {
-                        let mut split = k.split("_").into_iter();
-                        let _ = split.next(); // skip `like`
-                        let keys =
-                            array::from_fn(|_| split.next().unwrap().parse::<i32>().unwrap());
-                        keys
+                    .map(|(k, v)| {
+                        Ok((
+                            parse_str::<i32>(k, "integer key")?,
+                            GDLevel::from_dict(as_dict(v)?)?,
+                        ))
+                        .map_err(|e: GDError| e.at(k))
                     })
-                    .collect::<Vec<_>>();
-                Some(())
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => match k
+                .strip_prefix("dpad0")
+                .and_then(|i| i.parse::<usize>().ok())
+            {
+                Some(i @ 1..=5) => self.config.dpads[i - 1] = as_str(v)?.parse()?,
+                /* Values not parsed */
+                // GLM_02, GLM_04, GS_8: These keys are unused and modern (2.2) GD savefiles.
+                _ => return Ok(false),
             },
-        )?;
-
-        parse_val(&mut d, "GLM_10", |v| {
-            self.stats.completed_dailies = v
-                .as_dictionary()?
-                .iter()
-                .map(|(k, v)| {
-                    (
-                        k.parse::<i32>().unwrap(),
-                        GDLevel::from_dict(v.as_dictionary().unwrap()).unwrap(),
-                    )
-                })
-                .collect();
-
-            Some(())
-        })?;
-
-        /* Values not parsed */
-        // GLM_02, GLM_04, GS_8: These keys are unused and modern (2.2) GD savefiles.
-
-        self.other_properties = d.into_iter().collect();
-
-        Some(())
+        }
+        Ok(true)
     }
 }
 
-// removes all valid values specified in `fields`
-// this function ensures that `d` is left with only the unaccounted keys
-#[must_use]
-fn parse_values<F: Fn(Value) -> Option<R>, R>(
-    d: &mut Dictionary,
+fn not_a_dict(root: &Value) -> GDError {
+    GDError::CorruptedSavefile(format!(
+        "CCGameManager.dat is not a dict, found {}",
+        describe(root)
+    ))
+}
+
+// parses `v` into the field of `fields` whose key is `k`; returns `false` if there is none
+fn parse_field<F: Fn(&Value) -> Result<R, GDError>, R>(
+    k: &str,
+    v: &Value,
     fields: &mut [(&str, &mut R)],
     parser: F,
-) -> Option<()> {
-    for (k, f) in fields {
-        if let Some(v) = d.remove(k) {
-            **f = parser(v)?;
+) -> Result<bool, GDError> {
+    match fields.iter_mut().find(|(key, _)| *key == k) {
+        Some((_, field)) => {
+            **field = parser(v)?;
+            Ok(true)
         }
+        None => Ok(false),
     }
-    Some(())
 }
 
-#[must_use]
-fn parse_val<F: FnMut(Value) -> Option<()>>(
-    d: &mut Dictionary,
-    key: &str,
-    mut parser: F,
-) -> Option<()> {
-    if let Some(v) = d.remove(key) {
-        parser(v)
-    } else {
-        Some(())
-    }
-}
-
-fn parse_level_dict(v: &Value) -> Option<Vec<GDLevel>> {
-    v.as_dictionary()?
+fn parse_level_dict(v: &Value) -> Result<Vec<GDLevel>, GDError> {
+    as_dict(v)?
         .iter()
-        .map(|(_id, level_dict)| GDLevel::from_dict(level_dict.as_dictionary().unwrap()))
-        .collect::<Option<Vec<GDLevel>>>()
+        .map(|(id, level_dict)| {
+            as_dict(level_dict)
+                .and_then(GDLevel::from_dict)
+                .map_err(|e| e.at(id))
+        })
+        .collect()
 }
 
-fn parse_foldernames(v: Value) -> Option<Vec<(i32, String)>> {
-    if v.as_dictionary()?.is_empty() {
-        return Some(vec![]);
-    }
-
-    let mut raw_folders = v
-        .as_dictionary()?
+fn parse_foldernames(v: &Value) -> Result<Vec<(i32, String)>, GDError> {
+    let mut raw_folders = as_dict(v)?
         .iter()
         .map(|(idx, name)| {
-            (
-                idx.parse::<i32>().unwrap(),
-                name.as_string().unwrap().to_string(),
-            )
+            Ok((
+                parse_str::<i32>(idx, "folder index")?,
+                as_str(name)?.to_owned(),
+            ))
+            .map_err(|e: GDError| e.at(idx))
         })
-        .collect::<Vec<_>>();
+        .collect::<Result<Vec<_>, GDError>>()?;
 
     // this ensures that the folders are ordered by index
-    raw_folders.sort_by(|(a, _), (b, _)| a.cmp(b));
+    raw_folders.sort_by_key(|(idx, _)| *idx);
 
-    Some(raw_folders)
+    Ok(raw_folders)
+}
+
+// keys are always of the form `likes_a_b_c_d` where a, b, c, d are i32
+fn parse_glm12_key(k: &str) -> Result<[i32; 4], GDError> {
+    let mut split = k.split('_').skip(1); // skip `like`
+    let mut keys = [0; 4];
+    for key in &mut keys {
+        *key = parse_str::<i32>(split.next().unwrap_or_default(), "`likes_a_b_c_d` key")?;
+    }
+    Ok(keys)
 }
 
 /// Player info: username, UDID, user id, all icon info
@@ -477,11 +499,14 @@ pub struct GDPlatformerUI {
     pub split: bool,     // The Split checkbox
 }
 
-impl GDPlatformerUI {
-    /// Parses a comma-separated list of values to this object
-    pub fn from_str(s: &str) -> Self {
+impl FromStr for GDPlatformerUI {
+    type Err = GDError;
+
+    /// Parses a comma-separated list of values to this object.
+    /// Values beyond the known fields are ignored.
+    fn from_str(s: &str) -> Result<Self, GDError> {
         let mut this = Self::default();
-        let fns = &[
+        let fns: &[FieldParser<Self>] = &[
             Self::parse_width,
             Self::parse_height,
             Self::parse_scale,
@@ -494,46 +519,58 @@ impl GDPlatformerUI {
             Self::parse_snap,
             Self::parse_split,
         ];
-        s.split(",")
-            .into_iter()
-            .enumerate()
-            .for_each(|(idx, s)| (fns[idx])(&mut this, s));
+        for (idx, (parse, s)) in fns.iter().zip(s.split(',')).enumerate() {
+            parse(&mut this, s).map_err(|e| e.at(&idx.to_string()))?;
+        }
 
-        this
+        Ok(this)
     }
+}
 
-    fn parse_width(&mut self, s: &str) {
-        self.width = s.parse::<i32>().unwrap();
+impl GDPlatformerUI {
+    fn parse_width(&mut self, s: &str) -> Result<(), GDError> {
+        self.width = parse_str(s, "integer")?;
+        Ok(())
     }
-    fn parse_height(&mut self, s: &str) {
-        self.height = s.parse::<i32>().unwrap();
+    fn parse_height(&mut self, s: &str) -> Result<(), GDError> {
+        self.height = parse_str(s, "integer")?;
+        Ok(())
     }
-    fn parse_scale(&mut self, s: &str) {
-        self.scale = s.parse::<f32>().unwrap();
+    fn parse_scale(&mut self, s: &str) -> Result<(), GDError> {
+        self.scale = parse_str(s, "number")?;
+        Ok(())
     }
-    fn parse_opacity(&mut self, s: &str) {
-        self.opacity = s.parse::<i32>().unwrap();
+    fn parse_opacity(&mut self, s: &str) -> Result<(), GDError> {
+        self.opacity = parse_str(s, "integer")?;
+        Ok(())
     }
-    fn parse_pos_x(&mut self, s: &str) {
-        self.pos.0 = s.parse::<f32>().unwrap();
+    fn parse_pos_x(&mut self, s: &str) -> Result<(), GDError> {
+        self.pos.0 = parse_str(s, "number")?;
+        Ok(())
     }
-    fn parse_pos_y(&mut self, s: &str) {
-        self.pos.1 = s.parse::<f32>().unwrap();
+    fn parse_pos_y(&mut self, s: &str) -> Result<(), GDError> {
+        self.pos.1 = parse_str(s, "number")?;
+        Ok(())
     }
-    fn parse_mode_b(&mut self, s: &str) {
-        self.mode_b = s.parse::<i32>().unwrap() == 1;
+    fn parse_mode_b(&mut self, s: &str) -> Result<(), GDError> {
+        self.mode_b = parse_str::<i32>(s, "0 or 1")? == 1;
+        Ok(())
     }
-    fn parse_deadzone(&mut self, s: &str) {
-        self.deadzone = s.parse::<f32>().unwrap();
+    fn parse_deadzone(&mut self, s: &str) -> Result<(), GDError> {
+        self.deadzone = parse_str(s, "number")?;
+        Ok(())
     }
-    fn parse_radius(&mut self, s: &str) {
-        self.radius = s.parse::<f32>().unwrap();
+    fn parse_radius(&mut self, s: &str) -> Result<(), GDError> {
+        self.radius = parse_str(s, "number")?;
+        Ok(())
     }
-    fn parse_snap(&mut self, s: &str) {
-        self.snap = s.parse::<i32>().unwrap() == 1;
+    fn parse_snap(&mut self, s: &str) -> Result<(), GDError> {
+        self.snap = parse_str::<i32>(s, "0 or 1")? == 1;
+        Ok(())
     }
-    fn parse_split(&mut self, s: &str) {
-        self.split = s.parse::<i32>().unwrap() == 1;
+    fn parse_split(&mut self, s: &str) -> Result<(), GDError> {
+        self.split = parse_str::<i32>(s, "0 or 1")? == 1;
+        Ok(())
     }
 }
 
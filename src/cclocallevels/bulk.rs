@@ -0,0 +1,285 @@
+//! This module contains the bulk export of a savefile to a directory of .gmd and .gmdl files, and its import.
+//!
+//! [`CCLocalLevels::export_all_to_dir`] writes every level as a .gmd file and every list as a .gmdl file, along with a
+//! manifest ([`MANIFEST_FILE`]) that records the order of the levels and lists, the folder of every level, and the
+//! parts of the savefile that are not levels or lists. [`CCLocalLevels::import_dir`] reads the manifest back and
+//! rebuilds the same savefile.
+
+use std::{collections::HashSet, fs, path::Path};
+
+use plist::{Dictionary, Value};
+
+use crate::{
+    cclocallevels::{
+        gdlevel::{CCLocalLevels, GDLevel, PLIST_FOOTER, PLIST_HEADER},
+        gdlist::GDList,
+    },
+    core::{
+        GDError,
+        files::write_atomic,
+        gdplist,
+        io::{SavefilePlatform, stringify_xml, vec_as_str},
+        parse::PlistParser,
+        values::{as_dict, as_int, as_str, describe, invalid},
+    },
+};
+
+/// Name of the manifest written by [`CCLocalLevels::export_all_to_dir`]
+pub const MANIFEST_FILE: &str = "manifest.plist";
+/// Version of the manifest format
+const MANIFEST_VERSION: i32 = 1;
+/// Longest file name stem, in UTF-8 bytes, before collision suffixes are added. Common filesystems limit file names
+/// to 255 bytes.
+const MAX_STEM_LEN: usize = 100;
+
+/// How [`CCLocalLevels::export_all_to_dir`] names the exported files.
+///
+/// Names are always made safe for every filesystem, and made unique (ignoring case) by appending ` (2)`, ` (3)`, etc.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FileNaming {
+    /// The name of the level or list, e.g. `My Level.gmd`
+    #[default]
+    Name,
+    /// The position of the level or list followed by its name, e.g. `007 My Level.gmd`,
+    /// so that files sort in the same order as the savefile
+    IndexedName,
+}
+
+impl CCLocalLevels {
+    /// Exports every level as a .gmd file and every list as a .gmdl file to `dir`, along with a manifest that lets
+    /// [`CCLocalLevels::import_dir`] rebuild this savefile. `dir` is created if it does not exist.
+    ///
+    /// Files of a previous export that are not in the new manifest are left in place, but are ignored on import.
+    /// The manifest is written last and atomically, so an interrupted export never leaves a manifest that refers to
+    /// missing files.
+    pub fn export_all_to_dir<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        naming: FileNaming,
+    ) -> Result<(), GDError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut taken = HashSet::new();
+        let mut levels = vec![];
+        for (idx, level) in self.levels.iter().enumerate() {
+            let file = file_name(
+                &level.identity.name,
+                idx,
+                self.levels.len(),
+                "gmd",
+                naming,
+                &mut taken,
+            );
+            level.export_to_gmd(dir.join(&file))?;
+
+            let mut entry = Dictionary::new();
+            entry.insert("file".to_owned(), Value::String(file));
+            entry.insert(
+                "folder".to_owned(),
+                Value::from(i64::from(level.meta.level_folder)),
+            );
+            entry.insert(
+                "order".to_owned(),
+                Value::from(i64::from(level.meta.level_order)),
+            );
+            levels.push(Value::Dictionary(entry));
+        }
+
+        let mut lists = vec![];
+        for (idx, list) in self.lists.iter().enumerate() {
+            let file = file_name(
+                &list.name,
+                idx,
+                self.lists.len(),
+                "gmdl",
+                naming,
+                &mut taken,
+            );
+            list.export_to_gmdl(dir.join(&file))?;
+
+            let mut entry = Dictionary::new();
+            entry.insert("file".to_owned(), Value::String(file));
+            entry.insert("order".to_owned(), Value::from(i64::from(list.order)));
+            lists.push(Value::Dictionary(entry));
+        }
+
+        let mut manifest = Dictionary::new();
+        manifest.insert(
+            "version".to_owned(),
+            Value::from(i64::from(MANIFEST_VERSION)),
+        );
+        manifest.insert("levels".to_owned(), Value::Array(levels));
+        manifest.insert("lists".to_owned(), Value::Array(lists));
+        manifest.insert("LLM_02".to_owned(), self.binary_version.clone());
+        manifest.insert(
+            "other".to_owned(),
+            Value::Dictionary(self.other_properties.clone()),
+        );
+
+        let manifest = format!(
+            "{PLIST_HEADER}{}{PLIST_FOOTER}",
+            stringify_xml(&manifest, true)
+        );
+        write_atomic(dir.join(MANIFEST_FILE), manifest.as_bytes())
+    }
+
+    /// Rebuilds a savefile from a directory written by [`CCLocalLevels::export_all_to_dir`].
+    ///
+    /// Levels and lists are read in the order of the manifest, and the folder and order of every level is taken from
+    /// the manifest. The savefile is encrypted for Windows when exported unless `platform` is changed.
+    pub fn import_dir<P: AsRef<Path>>(dir: P) -> Result<Self, GDError> {
+        let dir = dir.as_ref();
+        let file = vec_as_str(&fs::read(dir.join(MANIFEST_FILE))?)?;
+        let manifest =
+            gdplist::parse_with_fallback(&file, PlistParser::Native, gdplist::parse_document, Ok)?;
+        let manifest = as_dict(&manifest)?;
+
+        let version = manifest
+            .get("version")
+            .map(as_int)
+            .transpose()
+            .map_err(|e| e.at("version"))?
+            .ok_or(GDError::missing_key("version"))?;
+        if version > MANIFEST_VERSION {
+            return Err(invalid("manifest version 1", version).at("version"));
+        }
+
+        let levels = manifest_entries(manifest, "levels", |entry| {
+            let mut level = GDLevel::from_gmd(dir.join(entry_file(entry)?))?;
+            level.meta.level_folder = entry_int(entry, "folder")?;
+            level.meta.level_order = entry_int(entry, "order")?;
+            Ok(level)
+        })?;
+        let lists = manifest_entries(manifest, "lists", |entry| {
+            let mut list = GDList::from_gmdl(dir.join(entry_file(entry)?))?;
+            list.order = entry_int(entry, "order")?;
+            Ok(list)
+        })?;
+
+        let other_properties = match manifest.get("other") {
+            Some(other) => as_dict(other).map_err(|e| e.at("other"))?.clone(),
+            None => Dictionary::new(),
+        };
+
+        Ok(Self {
+            levels,
+            lists,
+            binary_version: manifest
+                .get("LLM_02")
+                .cloned()
+                .ok_or(GDError::missing_key("LLM_02"))?,
+            platform: SavefilePlatform::default(),
+            fingerprint: None,
+            other_properties,
+            source: None,
+        })
+    }
+}
+
+fn manifest_entries<T, F: Fn(&Dictionary) -> Result<T, GDError>>(
+    manifest: &Dictionary,
+    key: &str,
+    read: F,
+) -> Result<Vec<T>, GDError> {
+    let entries = match manifest.get(key) {
+        Some(Value::Array(entries)) => entries,
+        Some(v) => return Err(invalid("array", describe(v)).at(key)),
+        None => return Err(GDError::missing_key(key)),
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            as_dict(entry)
+                .and_then(&read)
+                .map_err(|e| e.at(&idx.to_string()).at(key))
+        })
+        .collect()
+}
+
+fn entry_file(entry: &Dictionary) -> Result<&str, GDError> {
+    let file = entry
+        .get("file")
+        .ok_or(GDError::missing_key("file"))
+        .and_then(as_str)
+        .map_err(|e| e.at("file"))?;
+    // the manifest may only refer to files in its own directory
+    if Path::new(file).file_name().and_then(|f| f.to_str()) != Some(file) {
+        return Err(invalid("file name", file).at("file"));
+    }
+    Ok(file)
+}
+
+fn entry_int(entry: &Dictionary, key: &str) -> Result<i32, GDError> {
+    entry
+        .get(key)
+        .ok_or(GDError::missing_key(key))
+        .and_then(as_int)
+        .map_err(|e| e.at(key))
+}
+
+/// Returns a unique, filesystem-safe file name for the item at `idx` and records it in `taken`.
+fn file_name(
+    name: &str,
+    idx: usize,
+    len: usize,
+    extension: &str,
+    naming: FileNaming,
+    taken: &mut HashSet<String>,
+) -> String {
+    let mut stem = sanitise(name);
+    if naming == FileNaming::IndexedName {
+        let width = len.max(1).to_string().len();
+        stem = format!("{idx:0width$} {stem}");
+    }
+
+    let file = (1..)
+        .map(|n| match n {
+            1 => format!("{stem}.{extension}"),
+            n => format!("{stem} ({n}).{extension}"),
+        })
+        .find(|file| !taken.contains(&file.to_lowercase()))
+        .expect("ran out of file names");
+    taken.insert(file.to_lowercase());
+    file
+}
+
+/// Replaces characters that are not allowed in file names on any common filesystem.
+fn sanitise(name: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let mut stem = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // cut on a char boundary, so that names in multi-byte scripts are not cut mid-character
+    if let Some((end, _)) = stem
+        .char_indices()
+        .find(|(idx, c)| idx + c.len_utf8() > MAX_STEM_LEN)
+    {
+        stem.truncate(end);
+    }
+    // Windows drops trailing dots and spaces, and hidden files start with a dot
+    stem = stem.trim_end_matches(['.', ' ']).to_owned();
+    if stem.starts_with('.') {
+        stem.insert(0, '_');
+    }
+
+    if stem.is_empty() {
+        stem = "Unnamed".to_owned();
+    }
+    // `CON.gmd` and `CON.txt.gmd` are as reserved as `CON`
+    let device = stem.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED.contains(&device.to_uppercase().as_str()) {
+        stem.insert(0, '_');
+    }
+    stem
+}
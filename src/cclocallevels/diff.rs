@@ -0,0 +1,517 @@
+//! This module contains structural diffs between savefiles, levels and level data.
+//!
+//! Levels are matched by their [`LevelKey`], the same way as in [`CCLocalLevels::merge_three_way`].
+//! Objects have no identity of their own, so they are paired in three passes: exact copies, then objects of the same
+//! type at the same position, then objects of the same type at the same index. Unpaired objects are added or removed.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fmt::{Display, Write},
+};
+
+use plist::{Dictionary, Value};
+
+use crate::{
+    cclocallevels::{
+        gdlevel::{
+            CCLocalLevels, GDLevel,
+            leveldata::{
+                GDLevelData, GDLevelHeader, GDLevelState, HeaderValue, KA_SIZE, KS_OFFSET, KS_SIZE,
+            },
+        },
+        gdobj::{GDObject, meta::GDObjConfig, structs::GDValue},
+        merge::{LevelKey, keyed_levels},
+        properties::{LEVEL_HEADER_PROPERTIES, PROPERTY_TABLE},
+    },
+    core::GDError,
+};
+
+/// Differences between two savefiles.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SavefileDiff {
+    /// Levels that are only in the new savefile
+    pub added: Vec<LevelKey>,
+    /// Levels that are only in the old savefile
+    pub removed: Vec<LevelKey>,
+    /// Levels that are in both savefiles but differ
+    pub changed: Vec<(LevelKey, LevelDiff)>,
+}
+
+/// Differences between two levels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelDiff {
+    /// Changed level properties (e.g. `k2` for the name), other than the level data
+    pub fields: Vec<FieldChange>,
+    /// Changes to the level data (`k4`)
+    pub data: LevelDataDiff,
+}
+
+/// A level property that was added, removed or changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// Internal key of the property, e.g. `k2`
+    pub key: String,
+    /// Old value, or `None` if the property was added
+    pub old: Option<Value>,
+    /// New value, or `None` if the property was removed
+    pub new: Option<Value>,
+}
+
+/// Differences between two [`GDLevelData`]s.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelDataDiff {
+    /// Changed header properties
+    pub headers: Vec<HeaderChange>,
+    /// Removed, modified and added objects, in that order
+    pub objects: Vec<ObjectDiff>,
+}
+
+/// A level header property that was added, removed or changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderChange {
+    /// Property ID from `gdobj::ids::level_header`
+    pub property: u16,
+    /// Name of the property, or its key (e.g. `kA13`) if it is unknown
+    pub name: Cow<'static, str>,
+    /// Old value, or `None` if the property was added
+    pub old: Option<HeaderValue>,
+    /// New value, or `None` if the property was removed
+    pub new: Option<HeaderValue>,
+}
+
+/// An object that was added, removed or modified.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectDiff {
+    /// An object that is only in the new level data
+    Added {
+        /// Index in the new level data
+        index: usize,
+        /// The object
+        object: GDObject,
+    },
+    /// An object that is only in the old level data
+    Removed {
+        /// Index in the old level data
+        index: usize,
+        /// The object
+        object: GDObject,
+    },
+    /// An object that is in both, with different properties
+    Modified {
+        /// Index in the old level data
+        old_index: usize,
+        /// Index in the new level data
+        new_index: usize,
+        /// Object ID
+        id: i32,
+        /// Changed properties, ordered by property ID
+        changes: Vec<PropertyChange>,
+    },
+}
+
+/// An object property that was added, removed or changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    /// Property ID from `gdobj::ids::properties`
+    pub property: u16,
+    /// Name of the property in [`PROPERTY_TABLE`], or its ID if it is unknown
+    pub name: Cow<'static, str>,
+    /// Old value, or `None` if the property was added
+    pub old: Option<GDValue>,
+    /// New value, or `None` if the property was removed
+    pub new: Option<GDValue>,
+}
+
+impl SavefileDiff {
+    /// Returns `true` if the savefiles have the same levels.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl LevelDiff {
+    /// Returns `true` if the levels are the same.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.data.is_empty()
+    }
+}
+
+impl LevelDataDiff {
+    /// Returns `true` if the level data is the same.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.objects.is_empty()
+    }
+}
+
+/// Compares the levels of two savefiles.
+///
+/// Returns an error if the level data of a changed level cannot be decrypted.
+pub fn diff_savefiles(old: &CCLocalLevels, new: &CCLocalLevels) -> Result<SavefileDiff, GDError> {
+    let old_levels = keyed_levels(&old.levels);
+    let new_levels = keyed_levels(&new.levels);
+
+    let mut diff = SavefileDiff::default();
+    for (key, (old_idx, old_dict)) in &old_levels {
+        match new_levels.get(key) {
+            None => diff.removed.push(key.clone()),
+            Some((_, new_dict)) if new_dict == old_dict => {}
+            Some((new_idx, _)) => {
+                let level_diff = diff_levels(&old.levels[*old_idx], &new.levels[*new_idx])?;
+                if !level_diff.is_empty() {
+                    diff.changed.push((key.clone(), level_diff));
+                }
+            }
+        }
+    }
+    diff.added = new_levels
+        .into_keys()
+        .filter(|key| !old_levels.contains_key(key))
+        .collect();
+
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(diff)
+}
+
+/// Compares two levels, including their level data.
+///
+/// Returns an error if the level data of either level cannot be decrypted.
+pub fn diff_levels(old: &GDLevel, new: &GDLevel) -> Result<LevelDiff, GDError> {
+    let fields = diff_dicts(old.to_dict(), new.to_dict());
+
+    // decrypting is slow, so identical encrypted data is not compared
+    if let (Some(GDLevelState::Encrypted(old)), Some(GDLevelState::Encrypted(new))) =
+        (&old.content.data, &new.content.data)
+        && old == new
+    {
+        return Ok(LevelDiff {
+            fields,
+            data: LevelDataDiff::default(),
+        });
+    }
+
+    let data = match (old.get_decrypted_data()?, new.get_decrypted_data()?) {
+        (None, None) => LevelDataDiff::default(),
+        (old, new) => diff_level_data(
+            &old.unwrap_or_else(empty_level_data),
+            &new.unwrap_or_else(empty_level_data),
+        ),
+    };
+    Ok(LevelDiff { fields, data })
+}
+
+/// Compares the headers and objects of two levels.
+#[must_use]
+pub fn diff_level_data(old: &GDLevelData, new: &GDLevelData) -> LevelDataDiff {
+    LevelDataDiff {
+        headers: diff_headers(&old.headers, &new.headers),
+        objects: diff_objects(&old.objects, &new.objects),
+    }
+}
+
+fn empty_level_data() -> GDLevelData {
+    GDLevelData {
+        headers: GDLevelHeader {
+            ka: [const { None }; KA_SIZE],
+            ks: [const { None }; KS_SIZE],
+        },
+        objects: vec![],
+    }
+}
+
+/// Compares every property but the level data.
+fn diff_dicts(mut old: Dictionary, mut new: Dictionary) -> Vec<FieldChange> {
+    old.retain(|k, _| k != "k4");
+    new.retain(|k, _| k != "k4");
+
+    let mut changes = old
+        .iter()
+        .filter(|(k, v)| new.get(k) != Some(*v))
+        .map(|(k, v)| FieldChange {
+            key: k.clone(),
+            old: Some(v.clone()),
+            new: new.get(k).cloned(),
+        })
+        .chain(
+            new.iter()
+                .filter(|(k, _)| !old.contains_key(k))
+                .map(|(k, v)| FieldChange {
+                    key: k.clone(),
+                    old: None,
+                    new: Some(v.clone()),
+                }),
+        )
+        .collect::<Vec<_>>();
+
+    // `k2` before `k10`
+    changes.sort_by_key(|c| {
+        (
+            c.key.trim_start_matches('k').parse::<u32>().ok(),
+            c.key.clone(),
+        )
+    });
+    changes
+}
+
+fn diff_headers(old: &GDLevelHeader, new: &GDLevelHeader) -> Vec<HeaderChange> {
+    let ka = (0..KA_SIZE as u16).map(|idx| (idx, Cow::Owned(format!("kA{idx}"))));
+    let ks = (0..KS_SIZE as u16).map(|idx| (KS_OFFSET + idx, Cow::Owned(format!("kS{idx}"))));
+
+    ka.chain(ks)
+        .filter_map(|(property, key)| {
+            let (old, new) = (old.get_property(property), new.get_property(property));
+            (old != new).then(|| HeaderChange {
+                property,
+                name: LEVEL_HEADER_PROPERTIES
+                    .get(&property)
+                    .map_or(key, |p| Cow::Borrowed(p.0)),
+                old: old.cloned(),
+                new: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+fn diff_objects(old: &[GDObject], new: &[GDObject]) -> Vec<ObjectDiff> {
+    let old_strs = old
+        .iter()
+        .map(GDObject::serialise_to_string)
+        .collect::<Vec<_>>();
+    let new_strs = new
+        .iter()
+        .map(GDObject::serialise_to_string)
+        .collect::<Vec<_>>();
+    let mut old_paired = vec![false; old.len()];
+    let mut new_paired = vec![false; new.len()];
+    let mut pairs = vec![];
+
+    // exact copies
+    let mut by_str: HashMap<&str, VecDeque<usize>> = HashMap::new();
+    for (idx, s) in old_strs.iter().enumerate() {
+        by_str.entry(s).or_default().push_back(idx);
+    }
+    for (new_idx, s) in new_strs.iter().enumerate() {
+        if let Some(old_idx) = by_str.get_mut(s.as_str()).and_then(VecDeque::pop_front) {
+            old_paired[old_idx] = true;
+            new_paired[new_idx] = true;
+        }
+    }
+
+    // same type at the same position
+    let position = |o: &GDObject| (o.id, o.config.pos.0.to_bits(), o.config.pos.1.to_bits());
+    let mut by_position: HashMap<_, VecDeque<usize>> = HashMap::new();
+    for (idx, obj) in old.iter().enumerate().filter(|(idx, _)| !old_paired[*idx]) {
+        by_position.entry(position(obj)).or_default().push_back(idx);
+    }
+    for (new_idx, obj) in new.iter().enumerate() {
+        if new_paired[new_idx] {
+            continue;
+        }
+        if let Some(old_idx) = by_position
+            .get_mut(&position(obj))
+            .and_then(VecDeque::pop_front)
+        {
+            old_paired[old_idx] = true;
+            new_paired[new_idx] = true;
+            pairs.push((old_idx, new_idx));
+        }
+    }
+
+    // same type at the same index, e.g. an object that was moved
+    for (idx, obj) in new.iter().enumerate() {
+        if !new_paired[idx] && old.get(idx).is_some_and(|o| o.id == obj.id) && !old_paired[idx] {
+            old_paired[idx] = true;
+            new_paired[idx] = true;
+            pairs.push((idx, idx));
+        }
+    }
+    pairs.sort_by_key(|(_, new_idx)| *new_idx);
+
+    let removed = old
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !old_paired[*idx])
+        .map(|(index, object)| ObjectDiff::Removed {
+            index,
+            object: object.clone(),
+        });
+    let modified = pairs.into_iter().filter_map(|(old_index, new_index)| {
+        let changes = diff_properties(
+            &old[old_index],
+            &old_strs[old_index],
+            &new[new_index],
+            &new_strs[new_index],
+        );
+        // objects that only differ in the order of their properties are unchanged
+        (!changes.is_empty()).then(|| ObjectDiff::Modified {
+            old_index,
+            new_index,
+            id: new[new_index].id,
+            changes,
+        })
+    });
+    let added = new
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !new_paired[*idx])
+        .map(|(index, object)| ObjectDiff::Added {
+            index,
+            object: object.clone(),
+        });
+
+    removed.chain(modified).chain(added).collect()
+}
+
+/// Compares every property that appears in the serialised form of either object.
+fn diff_properties(
+    old: &GDObject,
+    old_str: &str,
+    new: &GDObject,
+    new_str: &str,
+) -> Vec<PropertyChange> {
+    let mut properties = property_ids(old_str);
+    properties.extend(property_ids(new_str));
+    properties.sort_unstable();
+    properties.dedup();
+    // `get_property` looks properties up by binary search, but the list is public and may have been reordered
+    let (old, new) = (sorted_properties(old), sorted_properties(new));
+
+    properties
+        .into_iter()
+        .filter_map(|property| {
+            let (old, new) = (old.get_property(property), new.get_property(property));
+            (old != new).then(|| PropertyChange {
+                property,
+                name: PROPERTY_TABLE
+                    .get(&property)
+                    .map_or_else(|| Cow::Owned(property.to_string()), |p| Cow::Borrowed(p.0)),
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+fn sorted_properties(object: &GDObject) -> Cow<'_, GDObject> {
+    if object.properties.is_sorted_by_key(|(key, _)| *key) {
+        Cow::Borrowed(object)
+    } else {
+        let mut object = object.clone();
+        object.properties.sort_by_key(|(key, _)| *key);
+        Cow::Owned(object)
+    }
+}
+
+/// Returns the property IDs in a serialised object, using the same numbering as [`GDObject::get_property`].
+fn property_ids(serialised: &str) -> Vec<u16> {
+    serialised
+        .trim_end_matches(';')
+        .split(',')
+        .step_by(2)
+        .filter_map(|key| match key.strip_prefix("kA") {
+            Some(n) => n.parse::<u16>().ok().map(|n| n + 10_000),
+            None => key.parse().ok(),
+        })
+        .collect()
+}
+
+impl Display for SavefileDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for key in &self.removed {
+            writeln!(f, "- level {}", level_name(key))?;
+        }
+        for key in &self.added {
+            writeln!(f, "+ level {}", level_name(key))?;
+        }
+        for (key, diff) in &self.changed {
+            writeln!(f, "~ level {}", level_name(key))?;
+            let mut level = String::new();
+            write!(level, "{diff}")?;
+            for line in level.lines() {
+                writeln!(f, "    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for LevelDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.fields {
+            writeln!(
+                f,
+                "{}: {} -> {}",
+                change.key,
+                describe_value(change.old.as_ref()),
+                describe_value(change.new.as_ref())
+            )?;
+        }
+        write!(f, "{}", self.data)
+    }
+}
+
+impl Display for LevelDataDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.headers {
+            writeln!(
+                f,
+                "header {}: {} -> {}",
+                change.name,
+                describe(change.old.as_ref()),
+                describe(change.new.as_ref())
+            )?;
+        }
+        for object in &self.objects {
+            match object {
+                ObjectDiff::Removed { index, object } => writeln!(f, "- object {index}: {object}")?,
+                ObjectDiff::Added { index, object } => writeln!(f, "+ object {index}: {object}")?,
+                ObjectDiff::Modified {
+                    old_index,
+                    new_index,
+                    id,
+                    changes,
+                } => {
+                    let name = GDObject::new(*id, &GDObjConfig::default(), vec![]).get_name();
+                    writeln!(f, "~ object {old_index} -> {new_index}: {name}")?;
+                    for change in changes {
+                        writeln!(
+                            f,
+                            "    {}: {} -> {}",
+                            change.name,
+                            describe(change.old.as_ref()),
+                            describe(change.new.as_ref())
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn level_name(key: &LevelKey) -> String {
+    match key.occurrence {
+        0 => format!("{:?}", key.name),
+        n => format!("{:?} (#{})", key.name, n + 1),
+    }
+}
+
+fn describe<T: Display>(value: Option<&T>) -> String {
+    value.map_or_else(|| "(none)".to_owned(), ToString::to_string)
+}
+
+fn describe_value(value: Option<&Value>) -> String {
+    match value {
+        None => "(none)".to_owned(),
+        Some(Value::String(s)) => format!("{s:?}"),
+        Some(Value::Integer(i)) => i.to_string(),
+        Some(Value::Real(r)) => r.to_string(),
+        Some(Value::Boolean(b)) => b.to_string(),
+        Some(v) => format!("{v:?}"),
+    }
+}
@@ -0,0 +1,252 @@
+//! This module contains the original text of savefiles loaded in fidelity mode,
+//! which lets [`CCLocalLevels::export_to_string`] reproduce everything that was not edited byte for byte.
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Write},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Range,
+};
+
+use plist::{Dictionary, Value};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::{
+    cclocallevels::{gdlevel::CCLocalLevels, gdlist::GDList},
+    core::{
+        GDError,
+        gdplist::{RawEntry, dict_content, dict_entries, root_entries},
+        io::{escape_xml_into, stringify_xml, write_xml_value},
+    },
+};
+
+/// The original decrypted XML of a savefile, along with enough information to tell which parts were edited.
+///
+/// Created by [`CCLocalLevels::from_decrypted_with_options`] when [`crate::core::parse::ParseOptions::fidelity`]
+/// is set. On export, levels and lists whose serialised form did not change are copied from the original text, as is
+/// everything that this crate does not parse. A savefile that was not edited at all is reproduced byte for byte.
+///
+/// Level data that was decrypted counts as edited, since it is re-encoded on export.
+#[derive(Clone)]
+pub struct SavefileSource {
+    xml: String,
+    /// Content of the root dictionary
+    root_content: Range<usize>,
+    root: Vec<RawEntry>,
+    levels: SourceArray,
+    lists: SourceArray,
+    binary_version: Value,
+    other_properties: Dictionary,
+}
+
+/// An `_isArr` dictionary (`LLM_01` or `LLM_03`) in the original text.
+#[derive(Clone, Default)]
+struct SourceArray {
+    /// Entries that are not elements, e.g. `_isArr` itself
+    other: Vec<Range<usize>>,
+    /// Value span and hash of the serialised form of each element.
+    /// Elements that failed to parse in lenient mode have no hash and are never reused.
+    elements: Vec<(Range<usize>, Option<u64>)>,
+}
+
+impl Debug for SavefileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SavefileSource")
+            .field("len", &self.xml.len())
+            .field("levels", &self.levels.elements.len())
+            .field("lists", &self.lists.elements.len())
+            .finish()
+    }
+}
+
+impl SavefileSource {
+    /// Indexes the original text of a savefile.
+    ///
+    /// `level_hashes` and `list_hashes` hold the [`element_hashes`] of the parsed levels and lists, in document order,
+    /// with `None` for elements that were skipped.
+    pub(crate) fn new(
+        xml: String,
+        level_hashes: Vec<Option<u64>>,
+        list_hashes: Vec<Option<u64>>,
+        binary_version: Value,
+        other_properties: Dictionary,
+    ) -> Result<Self, GDError> {
+        let (root_content, root) = root_entries(&xml)?;
+        let find = |key: &str| root.iter().find(|e| &xml[e.key.clone()] == key);
+
+        let levels = match find("LLM_01") {
+            Some(entry) => SourceArray::new(&xml, entry, level_hashes)?,
+            None => SourceArray::default(),
+        };
+        let lists = match find("LLM_03") {
+            Some(entry) => SourceArray::new(&xml, entry, list_hashes)?,
+            None => SourceArray::default(),
+        };
+
+        Ok(Self {
+            levels,
+            lists,
+            binary_version,
+            other_properties,
+            root_content,
+            root,
+            xml,
+        })
+    }
+
+    /// Serialises `levels`, reusing the original text of everything that was not edited.
+    pub(crate) fn export(&self, levels: &CCLocalLevels) -> String {
+        let level_dicts = serialise_all(&levels.levels, |l| l.to_dict());
+        let list_dicts = serialise_all(&levels.lists, GDList::to_dict);
+
+        let mut out = String::with_capacity(self.xml.len());
+        let mut copied = 0;
+        for entry in &self.root {
+            let key = &self.xml[entry.key.clone()];
+            let edit = match key {
+                "LLM_01" => self.levels.export(&self.xml, &level_dicts),
+                "LLM_02" if levels.binary_version != self.binary_version => {
+                    Some(xml_value(&levels.binary_version))
+                }
+                "LLM_02" => None,
+                "LLM_03" => self.lists.export(&self.xml, &list_dicts),
+                _ => match levels.other_properties.get(key) {
+                    Some(v) if Some(v) == self.other_properties.get(key) => None,
+                    Some(v) => Some(xml_value(v)),
+                    None => {
+                        // removed entries are dropped along with their key
+                        out.push_str(&self.xml[copied..entry.entry.start]);
+                        copied = entry.entry.end;
+                        None
+                    }
+                },
+            };
+
+            if let Some(text) = edit {
+                out.push_str(&self.xml[copied..entry.value.start]);
+                out.push_str(&text);
+                copied = entry.value.end;
+            }
+        }
+
+        // new entries go at the end of the root dictionary
+        out.push_str(&self.xml[copied..self.root_content.end]);
+        for (key, value) in &levels.other_properties {
+            if !self.other_properties.contains_key(key) {
+                out.push_str("<k>");
+                escape_xml_into(&mut out, key);
+                out.push_str("</k>");
+                write_xml_value(&mut out, value);
+            }
+        }
+        out.push_str(&self.xml[self.root_content.end..]);
+        out
+    }
+}
+
+fn xml_value(value: &Value) -> String {
+    let mut out = String::new();
+    write_xml_value(&mut out, value);
+    out
+}
+
+impl SourceArray {
+    fn new(xml: &str, entry: &RawEntry, hashes: Vec<Option<u64>>) -> Result<Self, GDError> {
+        let content = dict_content(xml, entry.value.clone()).ok_or_else(|| {
+            GDError::CorruptedSavefile(
+                "Malformed plist: level or list array is not a dictionary".into(),
+            )
+        })?;
+
+        let mut array = Self::default();
+        let mut hashes = hashes.into_iter();
+        for entry in dict_entries(xml, content)? {
+            if &xml[entry.key.clone()] == "_isArr" {
+                array.other.push(entry.entry);
+            } else {
+                array.elements.push((entry.value, hashes.next().flatten()));
+            }
+        }
+        Ok(array)
+    }
+
+    /// Returns the new text of this array, or `None` if no element changed.
+    fn export(&self, xml: &str, serialised: &[String]) -> Option<String> {
+        let new_hashes = serialised.iter().map(|s| hash(s)).collect::<Vec<u64>>();
+        if new_hashes.len() == self.elements.len()
+            && new_hashes
+                .iter()
+                .zip(&self.elements)
+                .all(|(new, (_, old))| Some(*new) == *old)
+        {
+            return None;
+        }
+
+        // elements may have moved, e.g. when a level was added on top. Elements with the same hash serialise
+        // identically, so the original text of one can be used for all of them.
+        let mut originals: HashMap<u64, &Range<usize>> = HashMap::new();
+        for (span, hash) in &self.elements {
+            if let Some(hash) = hash {
+                originals.entry(*hash).or_insert(span);
+            }
+        }
+
+        let mut out = String::from("<d>");
+        for other in &self.other {
+            out.push_str(&xml[other.clone()]);
+        }
+        for (idx, (text, hash)) in serialised.iter().zip(new_hashes).enumerate() {
+            let _ = write!(out, "<k>k_{idx}</k>");
+            match originals.get(&hash) {
+                Some(span) => out.push_str(&xml[(*span).clone()]),
+                None => out.push_str(text),
+            }
+        }
+        out.push_str("</d>");
+        Some(out)
+    }
+}
+
+/// Hashes the serialised form of every element, with `None` for elements that were skipped.
+pub(crate) fn element_hashes<T: Sync, F: Fn(&T) -> Dictionary + Sync>(
+    items: &[Option<&T>],
+    to_dict: F,
+) -> Vec<Option<u64>> {
+    #[cfg(feature = "parallel")]
+    let hashes = items
+        .par_iter()
+        .map(|i| i.map(|i| hash(&stringify_xml(&to_dict(i), false))))
+        .collect();
+
+    #[cfg(not(feature = "parallel"))]
+    let hashes = items
+        .iter()
+        .map(|i| i.map(|i| hash(&stringify_xml(&to_dict(i), false))))
+        .collect();
+
+    hashes
+}
+
+fn serialise_all<T: Sync, F: Fn(&T) -> Dictionary + Sync>(items: &[T], to_dict: F) -> Vec<String> {
+    #[cfg(feature = "parallel")]
+    let serialised = items
+        .par_iter()
+        .map(|i| stringify_xml(&to_dict(i), false))
+        .collect();
+
+    #[cfg(not(feature = "parallel"))]
+    let serialised = items
+        .iter()
+        .map(|i| stringify_xml(&to_dict(i), false))
+        .collect();
+
+    serialised
+}
+
+pub(crate) fn hash(serialised: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serialised.hash(&mut hasher);
+    hasher.finish()
+}
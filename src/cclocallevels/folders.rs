@@ -0,0 +1,298 @@
+//! This module contains [`Folders`], which organises local levels into the editor's folders.
+//!
+//! A level's folder is stored in the level itself ([`GDLevelMeta::level_folder`], `k84`), while the folder names are
+//! stored in CCGameManager.dat ([`GDConfig::local_levels_foldernames`], `GLM_19`). [`Folders`] loads both savefiles and
+//! writes both back, so that they stay consistent.
+//!
+//! [`GDLevelMeta::level_folder`]: crate::cclocallevels::gdlevel::GDLevelMeta::level_folder
+//! [`GDConfig::local_levels_foldernames`]: crate::ccgamemanager::GDConfig::local_levels_foldernames
+
+use std::path::PathBuf;
+
+use plist::{Dictionary, Value};
+
+use crate::{
+    ccgamemanager::CCGameManager,
+    cclocallevels::gdlevel::CCLocalLevels,
+    core::{
+        GDError,
+        files::{SaveOptions, SavefileFingerprint, write_savefile},
+        gdplist, get_ccgamemanager_path, get_cclocallevels_path,
+        io::{
+            SavefilePlatform, decrypt_file_with_fingerprint, encrypt_savefile_str_for,
+            write_xml_value,
+        },
+    },
+};
+
+/// Internal key of the local level folder names in CCGameManager.dat
+const FOLDER_NAMES_KEY: &str = "GLM_19";
+
+/// A folder of local levels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folder {
+    /// Folder number, starting at 1. Levels that are not in a folder have the folder 0.
+    pub id: i32,
+    /// Name shown in the editor, or `None` if the folder was never named
+    pub name: Option<String>,
+    /// Positions of the folder's levels in [`CCLocalLevels::levels`], from newest to oldest
+    pub levels: Vec<usize>,
+}
+
+/// The local levels together with the names of their folders.
+///
+/// Only the folder names are written back to CCGameManager.dat; everything else in that savefile is copied from the
+/// original text untouched.
+#[derive(Debug, Clone)]
+pub struct Folders {
+    /// The local levels. Edits to these are exported along with the folders.
+    pub levels: CCLocalLevels,
+    /// Folder names, ordered by folder number
+    names: Vec<(i32, String)>,
+    /// Decrypted CCGameManager.dat
+    gamemanager: String,
+    /// Platform that CCGameManager.dat is encrypted for when exported
+    pub gamemanager_platform: SavefilePlatform,
+    /// Fingerprint of the CCGameManager.dat that was loaded, if any
+    pub gamemanager_fingerprint: Option<SavefileFingerprint>,
+}
+
+impl Folders {
+    /// Loads CCLocalLevels.dat and CCGameManager.dat from their standard locations.
+    pub fn from_local() -> Result<Self, GDError> {
+        Self::from_files(
+            get_cclocallevels_path().ok_or(GDError::MissingSavefile)?,
+            get_ccgamemanager_path().ok_or(GDError::MissingSavefile)?,
+        )
+    }
+
+    /// Loads the given encrypted CCLocalLevels.dat and CCGameManager.dat. Both may be from any platform.
+    pub fn from_files(local_levels: PathBuf, gamemanager: PathBuf) -> Result<Self, GDError> {
+        let levels = CCLocalLevels::from_file(local_levels)?;
+        let (decrypted, platform, fingerprint) = decrypt_file_with_fingerprint(gamemanager)?;
+        let mut folders = Self::from_decrypted(levels, decrypted)?;
+        folders.gamemanager_platform = platform;
+        folders.gamemanager_fingerprint = Some(fingerprint);
+        Ok(folders)
+    }
+
+    /// Combines already loaded levels with a decrypted CCGameManager.dat.
+    pub fn from_decrypted(levels: CCLocalLevels, gamemanager: String) -> Result<Self, GDError> {
+        let names = CCGameManager::from_raw_string(gamemanager.clone())?
+            .config
+            .local_levels_foldernames;
+        Ok(Self {
+            levels,
+            names,
+            gamemanager,
+            gamemanager_platform: SavefilePlatform::default(),
+            gamemanager_fingerprint: None,
+        })
+    }
+
+    /// Returns every folder that has a name or contains levels, ordered by folder number.
+    /// Levels that are not in a folder are not included; see [`Folders::unfiled_levels`].
+    #[must_use]
+    pub fn folders(&self) -> Vec<Folder> {
+        let mut ids = self
+            .names
+            .iter()
+            .map(|(id, _)| *id)
+            .chain(self.levels.levels.iter().map(|l| l.meta.level_folder))
+            .filter(|id| *id > 0)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+
+        ids.into_iter()
+            .map(|id| Folder {
+                id,
+                name: self.folder_name(id).map(str::to_owned),
+                levels: self.levels_in(id),
+            })
+            .collect()
+    }
+
+    /// Returns the folder names, ordered by folder number.
+    #[must_use]
+    pub fn folder_names(&self) -> &[(i32, String)] {
+        &self.names
+    }
+
+    /// Returns the name of the given folder, if it has one.
+    #[must_use]
+    pub fn folder_name(&self, folder: i32) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(id, _)| *id == folder)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Returns the number of the first folder with the given name.
+    #[must_use]
+    pub fn folder_by_name(&self, name: &str) -> Option<i32> {
+        self.names
+            .iter()
+            .find(|(_, n)| n == name)
+            .map(|(id, _)| *id)
+    }
+
+    /// Returns the positions of the levels in the given folder, from newest to oldest.
+    #[must_use]
+    pub fn levels_in(&self, folder: i32) -> Vec<usize> {
+        self.levels
+            .levels
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.meta.level_folder == folder)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Returns the positions of the levels that are not in a folder, from newest to oldest.
+    #[must_use]
+    pub fn unfiled_levels(&self) -> Vec<usize> {
+        self.levels_in(0)
+    }
+
+    /// Creates a folder with the given name in the first folder number that has neither a name nor levels,
+    /// and returns its number.
+    pub fn create_folder(&mut self, name: &str) -> i32 {
+        let id = (1..)
+            .find(|id| {
+                self.folder_name(*id).is_none()
+                    && !self
+                        .levels
+                        .levels
+                        .iter()
+                        .any(|l| l.meta.level_folder == *id)
+            })
+            .expect("ran out of folder numbers");
+        self.set_name(id, name);
+        id
+    }
+
+    /// Renames the given folder, which does not need to have a name yet.
+    /// Returns `false` if `folder` is not a valid folder number.
+    pub fn rename_folder(&mut self, folder: i32, name: &str) -> bool {
+        if folder < 1 {
+            return false;
+        }
+        self.set_name(folder, name);
+        true
+    }
+
+    /// Moves the level at `idx` into the given folder, or out of its folder if `folder` is 0.
+    /// Returns `false` if there is no such level or `folder` is negative.
+    pub fn move_level(&mut self, idx: usize, folder: i32) -> bool {
+        if folder < 0 {
+            return false;
+        }
+        match self.levels.levels.get_mut(idx) {
+            Some(level) => {
+                level.meta.level_folder = folder;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the levels at `indices` into the given folder and returns how many were moved.
+    /// Positions without a level are skipped.
+    pub fn move_levels(&mut self, indices: &[usize], folder: i32) -> usize {
+        indices
+            .iter()
+            .filter(|idx| self.move_level(**idx, folder))
+            .count()
+    }
+
+    /// Removes the name of the given folder and moves its levels out of it. Returns the removed name, if any.
+    pub fn delete_folder(&mut self, folder: i32) -> Option<String> {
+        for idx in self.levels_in(folder) {
+            self.levels.levels[idx].meta.level_folder = 0;
+        }
+        let pos = self.names.iter().position(|(id, _)| *id == folder)?;
+        Some(self.names.remove(pos).1)
+    }
+
+    /// Exports CCGameManager.dat as XML, with the folder names replaced.
+    pub fn export_gamemanager_to_string(&self) -> Result<String, GDError> {
+        let mut names = Dictionary::new();
+        for (id, name) in &self.names {
+            names.insert(id.to_string(), Value::String(name.clone()));
+        }
+        let mut value = String::new();
+        write_xml_value(&mut value, &Value::Dictionary(names));
+
+        let xml = &self.gamemanager;
+        let (content, root) = gdplist::root_entries(xml)?;
+        for entry in &root {
+            if gdplist::entry_key(xml, entry)? == FOLDER_NAMES_KEY {
+                return Ok([&xml[..entry.value.start], &value, &xml[entry.value.end..]].concat());
+            }
+        }
+
+        // the key is missing if no folder was ever named
+        let mut out = String::with_capacity(xml.len() + value.len() + 16);
+        out.push_str(&xml[..content.end]);
+        out.push_str("<k>");
+        out.push_str(FOLDER_NAMES_KEY);
+        out.push_str("</k>");
+        out.push_str(&value);
+        out.push_str(&xml[content.end..]);
+        Ok(out)
+    }
+
+    /// Exports both savefiles to their standard locations.
+    pub fn export_to_savefiles(&mut self) -> Result<(), GDError> {
+        self.export_to_files_with(
+            get_cclocallevels_path().ok_or(GDError::MissingSavefile)?,
+            get_ccgamemanager_path().ok_or(GDError::MissingSavefile)?,
+            &SaveOptions::without_backups(),
+        )
+    }
+
+    /// Exports both savefiles to the given files using the given save options.
+    ///
+    /// Both savefiles are checked for concurrent modification before either is written, so a
+    /// [`GDError::ConcurrentModification`] leaves both files untouched. See [`CCLocalLevels::export_to_file_with`].
+    pub fn export_to_files_with(
+        &mut self,
+        local_levels: PathBuf,
+        gamemanager: PathBuf,
+        options: &SaveOptions,
+    ) -> Result<(), GDError> {
+        let tracked = self
+            .gamemanager_fingerprint
+            .as_ref()
+            .is_some_and(|fp| fp.is_for(&gamemanager));
+        if !options.force {
+            for fingerprint in [&self.levels.fingerprint, &self.gamemanager_fingerprint]
+                .into_iter()
+                .flatten()
+                .filter(|fp| fp.is_for(&local_levels) || fp.is_for(&gamemanager))
+            {
+                fingerprint.verify()?;
+            }
+        }
+
+        let gamemanager_xml = self.export_gamemanager_to_string()?;
+        let export_str = encrypt_savefile_str_for(&gamemanager_xml, self.gamemanager_platform);
+        self.levels.export_to_file_with(local_levels, options)?;
+        write_savefile(&gamemanager, &export_str, options)?;
+
+        if tracked {
+            self.gamemanager_fingerprint =
+                Some(SavefileFingerprint::from_bytes(gamemanager, &export_str)?);
+        }
+        self.gamemanager = gamemanager_xml;
+        Ok(())
+    }
+
+    fn set_name(&mut self, folder: i32, name: &str) {
+        match self.names.binary_search_by_key(&folder, |(id, _)| *id) {
+            Ok(pos) => self.names[pos].1 = name.to_owned(),
+            Err(pos) => self.names.insert(pos, (folder, name.to_owned())),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! This module contains [`GDLevelBuilder`], which creates new levels that are ready to be played and edited in GD.
+
+use crate::{
+    cclocallevels::{
+        gdlevel::{
+            GDLevel,
+            enums::{GDLevelType, Length, OfficialSong},
+            header::LevelHeaderBuilder,
+            leveldata::{GDLevelData, GDLevelState},
+            palette::Palette,
+        },
+        gdobj::structs::{Colour, ColourChannel, Gamemode, Speed},
+    },
+    core::{GDError, values::invalid},
+};
+
+/// Binary version (`k50`) of levels created by GD 2.2
+pub const BINARY_VERSION: i32 = 47;
+/// Longest level name that GD accepts
+pub const MAX_NAME_LEN: usize = 20;
+/// Number of background textures (`kA6`)
+const BACKGROUND_TEXTURES: i32 = 59;
+/// Number of ground textures (`kA7`)
+const GROUND_TEXTURES: i32 = 22;
+/// Number of middleground textures (`kA25`); 0 is no middleground
+const MIDDLEGROUND_TEXTURES: i32 = 3;
+
+/// Builder for new levels.
+///
+/// [`GDLevelBuilder::build`] produces a local level with decrypted level data, starting from the same header as a level
+/// that was just created in the editor ([`LevelHeaderBuilder::new`]). Values are validated when the level is built, not
+/// when they are set.
+///
+/// ```
+/// use gdlib::cclocallevels::{
+///     gdlevel::{builder::GDLevelBuilder, enums::OfficialSong},
+///     gdobj::structs::{Colour, ColourChannel, Gamemode},
+/// };
+///
+/// let level = GDLevelBuilder::new("My level")
+///     .creator("gdlib")
+///     .official_song(OfficialSong::Jumper)
+///     .gamemode(Gamemode::Ship)
+///     .colour(ColourChannel::Background, Colour::from_rgb((20, 0, 60)))
+///     .build()
+///     .unwrap();
+/// assert_eq!(level.identity.name, "My level");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GDLevelBuilder {
+    name: String,
+    description: Option<String>,
+    creator: String,
+    official_song: Option<OfficialSong>,
+    custom_song: Option<i32>,
+    platformer: bool,
+    gamemode: Gamemode,
+    speed: Speed,
+    background: Option<i32>,
+    ground: Option<i32>,
+    middleground: Option<i32>,
+    colours: Vec<(ColourChannel, Colour)>,
+}
+
+impl GDLevelBuilder {
+    /// Starts a level with the given name. Everything else is the same as a new level in the editor.
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the description (`k3`).
+    #[must_use]
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// Sets the username of the creator (`k5`).
+    #[must_use]
+    pub fn creator(mut self, creator: &str) -> Self {
+        self.creator = creator.to_owned();
+        self
+    }
+
+    /// Uses an official song (`k8`). Cannot be combined with [`GDLevelBuilder::custom_song`].
+    #[must_use]
+    pub fn official_song(mut self, song: OfficialSong) -> Self {
+        self.official_song = Some(song);
+        self
+    }
+
+    /// Uses a Newgrounds or music library song by its ID (`k45`). Cannot be combined with
+    /// [`GDLevelBuilder::official_song`].
+    #[must_use]
+    pub fn custom_song(mut self, id: i32) -> Self {
+        self.custom_song = Some(id);
+        self
+    }
+
+    /// Makes this a platformer level (`k94`, `kA22`).
+    #[must_use]
+    pub fn platformer(mut self, platformer: bool) -> Self {
+        self.platformer = platformer;
+        self
+    }
+
+    /// Sets the starting gamemode (`kA2`).
+    #[must_use]
+    pub fn gamemode(mut self, gamemode: Gamemode) -> Self {
+        self.gamemode = gamemode;
+        self
+    }
+
+    /// Sets the starting speed (`kA4`).
+    #[must_use]
+    pub fn speed(mut self, speed: Speed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Sets the background texture (`kA6`), from 1 to 59.
+    #[must_use]
+    pub fn background(mut self, texture: i32) -> Self {
+        self.background = Some(texture);
+        self
+    }
+
+    /// Sets the ground texture (`kA7`), from 1 to 22.
+    #[must_use]
+    pub fn ground(mut self, texture: i32) -> Self {
+        self.ground = Some(texture);
+        self
+    }
+
+    /// Sets the middleground texture (`kA25`), from 1 to 3, or 0 for none.
+    #[must_use]
+    pub fn middleground(mut self, texture: i32) -> Self {
+        self.middleground = Some(texture);
+        self
+    }
+
+    /// Sets the initial colour of a colour channel. Setting the same channel again replaces its colour.
+    ///
+    /// Channels that are not in the default header are added with full opacity and no blending.
+    #[must_use]
+    pub fn colour(mut self, channel: ColourChannel, colour: Colour) -> Self {
+        self.colours.retain(|(c, _)| *c != channel);
+        self.colours.push((channel, colour));
+        self
+    }
+
+    /// Builds the level, or returns a [`GDError::InvalidValue`] naming the offending key if a value is out of range
+    /// or the song is ambiguous.
+    pub fn build(self) -> Result<GDLevel, GDError> {
+        self.validate()?;
+
+        let mut header = LevelHeaderBuilder::new()
+            .gamemode(self.gamemode)
+            .speed(self.speed);
+        if let Some(texture) = self.background {
+            header = header.background_texture(texture);
+        }
+        if let Some(texture) = self.ground {
+            header = header.ground_texture(texture);
+        }
+        if let Some(texture) = self.middleground {
+            header = header.middleground_texture(texture);
+        }
+        if self.platformer {
+            header = header.platformer_mode(true);
+        }
+        let mut headers = header.build();
+        if !self.colours.is_empty() {
+            let mut palette = Palette::from_header(&headers);
+            for (channel, colour) in &self.colours {
+                palette.set_colour(*channel, *colour);
+            }
+            palette.write_to(&mut headers);
+        }
+
+        let mut level = GDLevel::default();
+        level.identity.name = self.name;
+        level.identity.description = self.description;
+        level.identity.creator = self.creator;
+        level.identity.level_version = 1;
+        level.identity.binary_version = BINARY_VERSION;
+        level.identity.level_type = GDLevelType::Local;
+        level.content.official_song_id = self.official_song;
+        level.content.custom_song_id = self.custom_song;
+        level.content.is_platformer = self.platformer;
+        if self.platformer {
+            level.content.length = Length::Platformer;
+        }
+        level.content.data = Some(GDLevelState::Decrypted(Box::new(GDLevelData {
+            headers,
+            objects: vec![],
+        })));
+        level.flags.has_been_modified = true;
+        Ok(level)
+    }
+
+    fn validate(&self) -> Result<(), GDError> {
+        let name_len = self.name.chars().count();
+        if name_len == 0 || name_len > MAX_NAME_LEN {
+            return Err(invalid("level name of 1 to 20 characters", &self.name).at("k2"));
+        }
+
+        match (self.official_song, self.custom_song) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("either an official or a custom song", "both").at("k45"));
+            }
+            (Some(OfficialSong::Unrecognized(id)), None) => {
+                return Err(invalid("official song", id).at("k8"));
+            }
+            (None, Some(id)) if id <= 0 => {
+                return Err(invalid("positive song ID", id).at("k45"));
+            }
+            _ => {}
+        }
+
+        for (key, texture, range) in [
+            ("kA6", self.background, 1..=BACKGROUND_TEXTURES),
+            ("kA7", self.ground, 1..=GROUND_TEXTURES),
+            ("kA25", self.middleground, 0..=MIDDLEGROUND_TEXTURES),
+        ] {
+            if let Some(texture) = texture
+                && !range.contains(&texture)
+            {
+                return Err(invalid("existing texture ID", texture).at(key).at("k4"));
+            }
+        }
+
+        for (channel, _) in &self.colours {
+            // object colour is not a channel of its own, and custom channels go up to 999
+            let valid = match channel {
+                ColourChannel::Object => false,
+                ColourChannel::Channel(n) => (1..=999).contains(n),
+                _ => true,
+            };
+            if !valid {
+                return Err(invalid("colour channel with a colour", i16::from(*channel))
+                    .at("kS38")
+                    .at("k4"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! This module contains typed accessors for the properties of [`GDLevelHeader`], and [`LevelHeaderBuilder`].
+//!
+//! Every property in [`LEVEL_HEADER_PROPERTIES`] has a getter named after it (e.g. [`GDLevelHeader::fix_robot_jump`])
+//! and a setter prefixed with `set_`. Getters of missing properties return the value that GD assumes for them, which is
+//! 0, `false`, [`Gamemode::Cube`] or [`Speed::X1`].
+//!
+//! [`LEVEL_HEADER_PROPERTIES`]: crate::cclocallevels::properties::LEVEL_HEADER_PROPERTIES
+
+use crate::cclocallevels::{
+    gdlevel::leveldata::{
+        ColourString, DEFAULT_LEVEL_HEADERS, GDLevelHeader, GuidelineString, HeaderValue,
+    },
+    gdobj::{
+        ids::level_header,
+        structs::{Gamemode, Speed},
+    },
+};
+
+/// Builder for level headers, starting from the header of a new level ([`DEFAULT_LEVEL_HEADERS`]).
+///
+/// ```
+/// use gdlib::cclocallevels::{gdlevel::header::LevelHeaderBuilder, gdobj::structs::Gamemode};
+///
+/// let header = LevelHeaderBuilder::new()
+///     .gamemode(Gamemode::Wave)
+///     .fix_robot_jump(false)
+///     .build();
+/// assert!(!header.fix_robot_jump());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelHeaderBuilder {
+    header: GDLevelHeader,
+}
+
+impl Default for LevelHeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LevelHeaderBuilder {
+    /// Starts from the header of a new level.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::from_header(
+            GDLevelHeader::parse(DEFAULT_LEVEL_HEADERS)
+                .expect("default level headers should always parse"),
+        )
+    }
+
+    /// Starts from an existing header.
+    #[must_use]
+    pub fn from_header(header: GDLevelHeader) -> Self {
+        Self { header }
+    }
+
+    /// Sets the guidelines (`kA14`).
+    #[must_use]
+    pub fn guidelines(mut self, guidelines: GuidelineString) -> Self {
+        self.header.set_guidelines(guidelines);
+        self
+    }
+
+    /// Sets the colour channels (`kS38`).
+    #[must_use]
+    pub fn colours(mut self, colours: Vec<ColourString>) -> Self {
+        self.header.set_colours(colours);
+        self
+    }
+
+    /// Returns the header.
+    #[must_use]
+    pub fn build(self) -> GDLevelHeader {
+        self.header
+    }
+}
+
+impl GDLevelHeader {
+    /// Guidelines (`kA14`), if the level has any.
+    #[must_use]
+    pub fn guidelines(&self) -> Option<&GuidelineString> {
+        match self.get_property(level_header::GUIDELINES) {
+            Some(HeaderValue::GuidelineString(guidelines)) => Some(guidelines),
+            _ => None,
+        }
+    }
+
+    /// Sets [`GDLevelHeader::guidelines`].
+    pub fn set_guidelines(&mut self, guidelines: GuidelineString) {
+        self.set_property(
+            level_header::GUIDELINES,
+            HeaderValue::GuidelineString(guidelines),
+        );
+    }
+
+    /// Sets the guidelines from guidelines in song time, such as those of [`GuidelineString::from_bpm`], so that they
+    /// line up with the song when the level starts at [`GDLevelHeader::song_offset`].
+    pub fn set_song_guidelines(&mut self, guidelines: &GuidelineString) {
+        self.set_guidelines(guidelines.with_song_offset(self.song_offset()));
+    }
+
+    /// Colour channels (`kS38`), if the level has any.
+    #[must_use]
+    pub fn colours(&self) -> Option<&[ColourString]> {
+        match self.get_property(level_header::COLOURS) {
+            Some(HeaderValue::ColourString(colours)) => Some(colours),
+            _ => None,
+        }
+    }
+
+    /// Sets [`GDLevelHeader::colours`].
+    pub fn set_colours(&mut self, colours: Vec<ColourString>) {
+        self.set_property(level_header::COLOURS, HeaderValue::ColourString(colours));
+    }
+}
+
+/// Generates a getter and setter on [`GDLevelHeader`] and a method on [`LevelHeaderBuilder`] for each property.
+macro_rules! header_accessors {
+    ($( $(#[$doc:meta])* $id:ident => $get:ident, $set:ident: $variant:ident($t:ty); )*) => {
+        impl GDLevelHeader {
+            $(
+                $(#[$doc])*
+                #[must_use]
+                pub fn $get(&self) -> $t {
+                    match self.get_property(level_header::$id) {
+                        Some(HeaderValue::$variant(v)) => *v,
+                        _ => <$t>::default(),
+                    }
+                }
+
+                #[doc = concat!("Sets [`GDLevelHeader::", stringify!($get), "`].")]
+                pub fn $set(&mut self, value: $t) {
+                    self.set_property(level_header::$id, HeaderValue::$variant(value));
+                }
+            )*
+        }
+
+        impl LevelHeaderBuilder {
+            $(
+                #[doc = concat!("Sets [`GDLevelHeader::", stringify!($get), "`].")]
+                #[must_use]
+                pub fn $get(mut self, value: $t) -> Self {
+                    self.header.$set(value);
+                    self
+                }
+            )*
+        }
+    };
+}
+
+header_accessors! {
+    /// Official song that the level uses when it has no custom song (`kA1`)
+    AUDIO_TRACK => audio_track, set_audio_track: Int(i32);
+    /// Starting gamemode (`kA2`)
+    GAMEMODE => gamemode, set_gamemode: Gamemode(Gamemode);
+    /// Start as mini (`kA3`)
+    MINI_MODE => mini_mode, set_mini_mode: Bool(bool);
+    /// Starting speed (`kA4`)
+    SPEED => speed, set_speed: Speed(Speed);
+    /// Blending of the Obj2 colour (`kA5`)
+    OBJ2_BLENDING => obj2_blending, set_obj2_blending: Bool(bool);
+    /// Background texture (`kA6`)
+    BACKGROUND_TEXTURE_ID => background_texture, set_background_texture: Int(i32);
+    /// Ground texture (`kA7`)
+    GROUND_TEXTURE_ID => ground_texture, set_ground_texture: Int(i32);
+    /// Start as dual (`kA8`)
+    DUAL_MODE => dual_mode, set_dual_mode: Bool(bool);
+    /// Whether this header belongs to the level rather than a start position (`kA9`)
+    LEVEL_OR_STARTPOS_OBJECT => level_or_startpos_object, set_level_or_startpos_object: Bool(bool);
+    /// Two-player mode (`kA10`)
+    TWO_PLAYER_MODE => two_player_mode, set_two_player_mode: Bool(bool);
+    /// Start with flipped gravity (`kA11`)
+    FLIP_GRAVITY => flip_gravity, set_flip_gravity: Bool(bool);
+    /// Blending of the Colour3 colour (`kA12`)
+    COLOUR3_BLENDING => colour3_blending, set_colour3_blending: Bool(bool);
+    /// Offset into the song where the level starts, in seconds (`kA13`)
+    SONG_OFFSET_SECONDS => song_offset, set_song_offset: Float(f32);
+    /// Fade the song in (`kA15`)
+    FADE_IN => fade_in, set_fade_in: Bool(bool);
+    /// Fade the song out (`kA16`)
+    FADE_OUT => fade_out, set_fade_out: Bool(bool);
+    /// Ground line style (`kA17`)
+    GROUND_LINE_ID => ground_line, set_ground_line: Int(i32);
+    /// Font of the percentage and attempt counter (`kA18`)
+    FONT_ID => font, set_font: Int(i32);
+    /// Target order of a start position (`kA19`)
+    STARTPOS_TARGET_ORDER => startpos_target_order, set_startpos_target_order: Int(i32);
+    /// Start with reversed gameplay (`kA20`)
+    REVERSE_GAMEPLAY => reverse_gameplay, set_reverse_gameplay: Bool(bool);
+    /// Whether a start position is disabled (`kA21`)
+    STARTPOS_DISABLED => startpos_disabled, set_startpos_disabled: Bool(bool);
+    /// Platformer mode (`kA22`)
+    PLAFORMER_MODE => platformer_mode, set_platformer_mode: Bool(bool);
+    /// Middleground texture, or 0 for none (`kA25`)
+    MIDDLEGROUND_TEXTURE_ID => middleground_texture, set_middleground_texture: Int(i32);
+    /// Target channel of a start position (`kA26`)
+    STARTPOS_TARGET_CHANNEL => startpos_target_channel, set_startpos_target_channel: Int(i32);
+    /// Allow multi-rotation (`kA27`)
+    ALLOW_MULTIROTATION => allow_multi_rotation, set_allow_multi_rotation: Bool(bool);
+    /// Start mirrored (`kA28`)
+    MIRROR_MODE => mirror_mode, set_mirror_mode: Bool(bool);
+    /// Start with rotated gameplay (`kA29`)
+    ROTATE_GAMEPLAY => rotate_gameplay, set_rotate_gameplay: Bool(bool);
+    /// Enable player squeeze (`kA31`)
+    ENABLE_PLAYER_SQUEEZE => enable_player_squeeze, set_enable_player_squeeze: Bool(bool);
+    /// Fix gravity bug, a 2.2 fix flag (`kA32`)
+    FIX_GRAVITY_BUG => fix_gravity_bug, set_fix_gravity_bug: Bool(bool);
+    /// Fix negative scale, a 2.2 fix flag (`kA33`)
+    FIX_NEGATIVE_SCALE => fix_negative_scale, set_fix_negative_scale: Bool(bool);
+    /// Fix robot jump, a 2.2 fix flag (`kA34`)
+    FIX_ROBOT_JUMP => fix_robot_jump, set_fix_robot_jump: Bool(bool);
+    /// Whether a start position resets the camera (`kA35`)
+    STARTPOS_RESET_CAMERA => startpos_reset_camera, set_startpos_reset_camera: Bool(bool);
+    /// Group that is spawned when the level starts (`kA36`)
+    SPAWN_GROUP => spawn_group, set_spawn_group: Int(i32);
+    /// Dynamic level height (`kA37`)
+    DYNAMIC_LEVEL_HEIGHT => dynamic_level_height, set_dynamic_level_height: Bool(bool);
+    /// Sort groups (`kA38`)
+    SORT_GROUPS => sort_groups, set_sort_groups: Bool(bool);
+    /// Fix radius collision, a 2.2 fix flag (`kA39`)
+    FIX_RADIUS_COLLISION => fix_radius_collision, set_fix_radius_collision: Bool(bool);
+    /// Enable 2.2 changes (`kA40`)
+    ENABLE_22_CHANGES => enable_22_changes, set_enable_22_changes: Bool(bool);
+    /// Allow static rotate (`kA41`)
+    ALLOW_STATIC_ROTATE => allow_static_rotate, set_allow_static_rotate: Bool(bool);
+    /// Reverse sync (`kA42`)
+    REVERSE_SYNC => reverse_sync, set_reverse_sync: Bool(bool);
+    /// No time penalty, for platformer levels (`kA43`)
+    NO_TIME_PENALTY => no_time_penalty, set_no_time_penalty: Bool(bool);
+    /// Decrease boost slide (`kA44`)
+    DECREASE_BOOST_SLIDE => decrease_boost_slide, set_decrease_boost_slide: Bool(bool);
+    /// Colour page shown in the editor (`kS39`)
+    COLOUR_PAGE => colour_page, set_colour_page: Int(i32);
+}
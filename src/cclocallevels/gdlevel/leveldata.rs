@@ -12,7 +12,12 @@ use crate::{
         },
         properties::get_level_header_property_type,
     },
-    core::io::{decompress, encrypt_level_str, vec_as_str},
+    core::{
+        GDError,
+        io::{decompress, encrypt_level_str},
+        parse::{Diagnostic, DiagnosticKind, Diagnostics, ParseMode, ParseOptions},
+        values::{describe_str, invalid},
+    },
 };
 
 #[cfg(feature = "parallel")]
@@ -23,8 +28,10 @@ use rayon::prelude::*;
 /// This is the state of the level header immediately after initializing new level data.
 pub const DEFAULT_LEVEL_HEADERS: &str = "kS38,1_40_2_125_3_255_11_255_12_255_13_255_4_-1_6_1000_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_255_4_-1_6_1001_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_255_4_-1_6_1009_7_1_15_1_18_0_8_1|1_255_2_255_3_255_11_255_12_255_13_255_4_-1_6_1002_5_1_7_1_15_1_18_0_8_1|1_40_2_125_3_255_11_255_12_255_13_255_4_-1_6_1013_7_1_15_1_18_0_8_1|1_40_2_125_3_255_11_255_12_255_13_255_4_-1_6_1014_7_1_15_1_18_0_8_1|1_0_2_125_3_255_11_255_12_255_13_255_4_-1_6_1005_5_1_7_1_15_1_18_0_8_1|1_0_2_200_3_255_11_255_12_255_13_255_4_-1_6_1006_5_1_7_1_15_1_18_0_8_1|,kA13,0,kA15,0,kA16,0,kA14,,kA6,0,kA7,0,kA25,0,kA17,0,kA18,0,kS39,0,kA2,0,kA3,0,kA8,0,kA4,0,kA9,0,kA10,0,kA22,0,kA23,0,kA24,0,kA27,1,kA40,1,kA41,1,kA42,1,kA28,0,kA29,0,kA31,1,kA32,1,kA36,0,kA43,0,kA44,0,kA45,1,kA46,0,kA33,1,kA34,1,kA35,0,kA37,1,kA38,1,kA39,1,kA19,0,kA26,0,kA20,0,kA21,0,kA11,0";
 
-const KA_SIZE: usize = 64;
-const KS_SIZE: usize = 48;
+pub(crate) const KA_SIZE: usize = 64;
+pub(crate) const KS_SIZE: usize = 48;
+/// Offset of kS properties in the property IDs of `gdobj::ids::level_header`: kS38 is 1038, while kA13 is 13.
+pub const KS_OFFSET: u16 = 1000;
 
 /// This struct contains level data that has not yet been decrypted
 #[derive(Clone, Debug, PartialEq)]
@@ -48,7 +55,7 @@ pub enum GDLevelState {
     /// Raw encrypted data
     Encrypted(GDEncryptedLevelData),
     /// Parsed, structured data
-    Decrypted(GDLevelData),
+    Decrypted(Box<GDLevelData>),
 }
 
 /// Contains the properties of the level header string.
@@ -176,7 +183,8 @@ impl GDLevelData {
         unencrypted.push(';');
         unencrypted.push_str(&object_data);
 
-        vec_as_str(&encrypt_level_str(&unencrypted))
+        // the encrypted data is base64, so it is always valid UTF-8
+        String::from_utf8_lossy(&encrypt_level_str(&unencrypted)).into_owned()
     }
 
     /// Returns a list of all the groups that contain at least one object
@@ -285,39 +293,115 @@ impl GDLevelData {
         groups
     }
 
-    /// Parse raw level data to this struct
-    #[must_use]
-    pub fn parse<T: AsRef<str>>(raw_data: T) -> Option<Self> {
-        let raw_data = raw_data.as_ref();
-        // parse level data
-        let raw_data = decompress(raw_data.as_bytes().to_vec()).ok()?;
-        let decrypted = std::str::from_utf8(&raw_data[..]).ok()?;
-        let split: Vec<&str> = decrypted.split(';').collect();
+    /// Parses raw level data (the `k4` value of a level) into this struct.
+    ///
+    /// Values that cannot be parsed are silently replaced with their default or dropped;
+    /// use [`GDLevelData::parse_with_options`] to detect them.
+    pub fn parse<T: AsRef<str>>(raw_data: T) -> Result<Self, GDError> {
+        let decrypted = Self::decrypt(raw_data.as_ref())?;
+        let (headers, object_slice) = Self::split(&decrypted);
+        let level_headers = GDLevelHeader::parse(headers)
+            .ok_or_else(|| invalid("level header string", describe_str(headers)))?;
+
+        #[cfg(feature = "parallel")]
+        let objects = object_slice.par_iter().map(GDObject::parse_str).collect();
 
-        // level start string
-        let headers = split.first().unwrap_or(&"").to_string();
-        let level_headers = GDLevelHeader::parse(&headers)?;
+        #[cfg(not(feature = "parallel"))]
+        let objects = object_slice.iter().map(GDObject::parse_str).collect();
 
-        let object_slice = split.get(1..).unwrap_or(&[]);
+        Ok(Self {
+            headers: level_headers,
+            objects,
+        })
+    }
 
+    /// Parses raw level data (the `k4` value of a level) into this struct according to `options`.
+    ///
+    /// In strict mode, the first value that cannot be parsed without loss is returned as an error with a path like
+    /// `objects/12/57` or `header/kA13`. In lenient mode, such values are coerced or dropped, objects that cannot be
+    /// parsed at all are skipped, and every such decision is returned as a [`Diagnostic`] with the object index set.
+    pub fn parse_with_options<T: AsRef<str>>(
+        raw_data: T,
+        options: &ParseOptions,
+    ) -> Result<(Self, Diagnostics), GDError> {
+        let decrypted = Self::decrypt(raw_data.as_ref())?;
+        let (headers, object_slice) = Self::split(&decrypted);
+        let (level_headers, mut diagnostics) =
+            GDLevelHeader::parse_with_options(headers, options).map_err(|e| e.at("header"))?;
+
+        let parse_object =
+            |(idx, obj): (usize, &&str)| match GDObject::parse_str_with_options(obj, options) {
+                Ok((obj, mut diagnostics)) => {
+                    for d in &mut diagnostics {
+                        d.object = Some(idx);
+                    }
+                    Ok((Some(obj), diagnostics))
+                }
+                Err(e) if options.is_strict() => Err(e.at(&idx.to_string()).at("objects")),
+                Err(e) => {
+                    let mut skipped =
+                        Diagnostic::skipped(&e, |reason| DiagnosticKind::SkippedObject { reason });
+                    skipped.object = Some(idx);
+                    Ok((None, vec![skipped]))
+                }
+            };
+
+        // object indices count every object string, including skipped ones
         #[cfg(feature = "parallel")]
-        let objects = object_slice
+        let parsed = object_slice
             .par_iter()
-            .filter(|obj| obj.len() > 1)
-            .map(GDObject::parse_str)
-            .collect();
+            .enumerate()
+            .map(parse_object)
+            .collect::<Result<Vec<_>, GDError>>()?;
 
         #[cfg(not(feature = "parallel"))]
-        let objects = object_slice
+        let parsed = object_slice
             .iter()
-            .filter(|obj| obj.len() > 1)
-            .map(GDObject::parse_str)
-            .collect();
+            .enumerate()
+            .map(parse_object)
+            .collect::<Result<Vec<_>, GDError>>()?;
 
-        Some(Self {
-            headers: level_headers,
-            objects,
-        })
+        let mut objects = Vec::with_capacity(parsed.len());
+        for (obj, obj_diagnostics) in parsed {
+            objects.extend(obj);
+            diagnostics.extend(obj_diagnostics);
+        }
+
+        Ok((
+            Self {
+                headers: level_headers,
+                objects,
+            },
+            diagnostics,
+        ))
+    }
+
+    fn decrypt(raw_data: &str) -> Result<String, GDError> {
+        let raw_data = decompress(raw_data.as_bytes().to_vec())?;
+        Ok(String::from_utf8(raw_data)?)
+    }
+
+    /// Splits decrypted level data into the header string and the object strings.
+    fn split(decrypted: &str) -> (&str, Vec<&str>) {
+        let mut split = decrypted.split(';');
+        let headers = split.next().unwrap_or("");
+        (headers, split.filter(|obj| obj.len() > 1).collect())
+    }
+}
+
+impl HeaderValueType {
+    /// Short description of the values of this type, used in errors and diagnostics.
+    #[must_use]
+    pub const fn expected(self) -> &'static str {
+        match self {
+            Self::Int => "32-bit integer",
+            Self::Float => "number",
+            Self::Bool => "boolean (0 or 1)",
+            Self::Gamemode => "gamemode ID",
+            Self::Speed => "speed ID",
+            Self::GuidelineString => "guideline string",
+            Self::ColourString => "colour string",
+        }
     }
 }
 
@@ -337,7 +421,8 @@ impl HeaderValue {
             HeaderValueType::ColourString => Some(Self::ColourString({
                 // there's usually 14 segments
                 let mut segments = Vec::with_capacity(14);
-                for segment in val.split("|").into_iter() {
+                // GD ends the colour string with a separator
+                for segment in val.split('|').filter(|s| !s.is_empty()) {
                     segments.push(ColourString::parse(segment)?)
                 }
                 segments
@@ -379,25 +464,113 @@ impl GuidelineColour {
 impl GuidelineString {
     /// Parses an input string to this object
     pub fn parse(s: &str) -> Option<Self> {
-        let mut guidelines = vec![];
-        for g in s.split(".") {
-            let mut split_iter = g.split('~');
-            guidelines.push((
-                split_iter.next()?.parse::<f32>().ok()?,
-                GuidelineColour::from_f32(split_iter.next()?.parse::<f32>().ok()?),
-            ));
+        // GD writes every time and colour followed by a `~`
+        let values = s
+            .split('~')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        if values.len() % 2 != 0 {
+            return None;
         }
 
-        Some(Self { guidelines })
+        Some(Self {
+            guidelines: values
+                .chunks_exact(2)
+                .map(|pair| (pair[0], GuidelineColour::from_f32(pair[1])))
+                .collect(),
+        })
     }
 
-    /// Serialises this object to a string
-    pub fn to_string(&self) -> String {
-        self.guidelines
+    /// Creates guidelines on the beats of a song with a constant tempo, from `offset` to `duration` seconds into
+    /// the song. The first beat of every bar is orange and the other beats are yellow.
+    ///
+    /// Times are in song time; use [`GDLevelHeader::set_song_guidelines`] to set them on a level that starts
+    /// partway into its song. Returns no guidelines if `bpm` is not positive or `duration` is not finite.
+    #[must_use]
+    pub fn from_bpm(bpm: f32, offset: f32, beats_per_bar: u32, duration: f32) -> Self {
+        if !bpm.is_finite() || bpm <= 0.0 || !offset.is_finite() || !duration.is_finite() {
+            return Self { guidelines: vec![] };
+        }
+        let beat_len = 60.0 / bpm;
+        let beats_per_bar = u64::from(beats_per_bar.max(1));
+        // beats before the start of the song are skipped rather than counted through
+        let first_beat = (-offset / beat_len).ceil().max(0.0) as u64;
+
+        let guidelines = (first_beat..)
+            // each beat is computed from the offset so that rounding errors do not add up
+            .map(|beat| (beat, offset + beat as f32 * beat_len))
+            .take_while(|(_, time)| *time <= duration)
+            .filter(|(_, time)| *time >= 0.0)
+            .map(|(beat, time)| {
+                let colour = if beat % beats_per_bar == 0 {
+                    GuidelineColour::Orange
+                } else {
+                    GuidelineColour::Yellow
+                };
+                (time, colour)
+            })
+            .collect();
+        Self { guidelines }
+    }
+
+    /// Creates yellow guidelines at the given times, in seconds into the song. Times are sorted, and negative and
+    /// duplicate times are left out.
+    #[must_use]
+    pub fn from_beat_times(times: &[f32]) -> Self {
+        let mut times = times
             .iter()
-            .map(|(t, c)| format!("{t}~{}", c.to_float()))
-            .collect::<Vec<String>>()
-            .join(".")
+            .copied()
+            .filter(|t| t.is_finite() && *t >= 0.0)
+            .collect::<Vec<f32>>();
+        times.sort_by(f32::total_cmp);
+        times.dedup();
+
+        Self {
+            guidelines: times
+                .into_iter()
+                .map(|t| (t, GuidelineColour::Yellow))
+                .collect(),
+        }
+    }
+
+    /// Adds `n - 1` green guidelines evenly spaced between each pair of consecutive guidelines, e.g. `2` adds a
+    /// guideline halfway between every two beats.
+    #[must_use]
+    pub fn subdivide(&self, n: u32) -> Self {
+        let mut guidelines = vec![];
+        for (idx, &(time, colour)) in self.guidelines.iter().enumerate() {
+            guidelines.push((time, colour));
+            if let Some(&(next, _)) = self.guidelines.get(idx + 1) {
+                let step = (next - time) / n.max(1) as f32;
+                guidelines.extend((1..n).map(|i| (time + i as f32 * step, GuidelineColour::Green)));
+            }
+        }
+        Self { guidelines }
+    }
+
+    /// Moves guidelines from song time to level time, for a level that starts `song_offset` seconds into its song
+    /// (`kA13`). Guidelines before the start of the level are left out.
+    #[must_use]
+    pub fn with_song_offset(&self, song_offset: f32) -> Self {
+        Self {
+            guidelines: self
+                .guidelines
+                .iter()
+                .map(|&(time, colour)| (time - song_offset, colour))
+                .filter(|(time, _)| *time >= 0.0)
+                .collect(),
+        }
+    }
+}
+
+// serialiser for `GuidelineString`
+impl Display for GuidelineString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (time, colour) in &self.guidelines {
+            write!(f, "{time}~{}~", colour.to_float())?;
+        }
+        Ok(())
     }
 }
 
@@ -470,9 +643,11 @@ impl ColourString {
 
         Some(new)
     }
+}
 
-    /// Serialises this object to a String.
-    pub fn to_string(&self) -> String {
+// serialiser for `ColourString`
+impl Display for ColourString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // properties 1, 2, 3, 11, 12, 13, 18 are always present
 
         // casting to f32 is essential to keep all properties as one type
@@ -529,7 +704,7 @@ impl ColourString {
             str_buf.pop();
         }
 
-        str_buf
+        f.write_str(&str_buf)
     }
 }
 
@@ -550,11 +725,8 @@ impl Display for HeaderValue {
                 Self::Int(i) => i.to_string(),
                 Self::Speed(s) => (*s as i32).to_string(),
                 Self::Gamemode(g) => (*g as i32).to_string(),
-                Self::ColourString(c) => c
-                    .iter()
-                    .map(ColourString::to_string)
-                    .collect::<Vec<_>>()
-                    .join("|"),
+                // GD ends every segment with a separator, including the last one
+                Self::ColourString(c) => c.iter().map(|segment| format!("{segment}|")).collect(),
                 Self::GuidelineString(g) => g.to_string(),
             },
         )
@@ -568,84 +740,119 @@ impl Display for GDLevelHeader {
             .ka
             .iter()
             .enumerate()
-            .filter_map(|(idx, v)| {
-                if let Some(val) = v {
-                    Some(format!("kA{idx},{val}"))
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(",");
+            .filter_map(|(idx, v)| v.as_ref().map(|val| (format!("kA{idx}"), val)));
         let kss = self
             .ks
             .iter()
             .enumerate()
-            .filter_map(|(idx, v)| {
-                if let Some(val) = v {
-                    Some(format!(",kS{idx},{val}"))
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("");
-        write!(f, "{kas},{kss}")
+            .filter_map(|(idx, v)| v.as_ref().map(|val| (format!("kS{idx}"), val)));
+        for (idx, (key, val)) in kas.chain(kss).enumerate() {
+            if idx != 0 {
+                f.write_char(',')?;
+            }
+            // booleans are displayed as true/false, but stored as 1/0
+            match val {
+                HeaderValue::Bool(b) => write!(f, "{key},{}", *b as i32)?,
+                val => write!(f, "{key},{val}")?,
+            }
+        }
+        Ok(())
     }
 }
 
 impl GDLevelHeader {
-    /// Parses the input string to this object
+    /// Parses the input string to this object. Properties that cannot be parsed are dropped.
     pub fn parse(s: &str) -> Option<Self> {
+        Self::parse_with(s, None).ok().map(|(headers, _)| headers)
+    }
+
+    /// Parses the input string to this object according to `options`.
+    ///
+    /// In strict mode, a property that cannot be parsed is an error whose path is the property key, e.g. `kA13`.
+    /// In lenient mode, it is dropped and reported as a [`Diagnostic`]. Empty values are treated as absent.
+    pub fn parse_with_options(
+        s: &str,
+        options: &ParseOptions,
+    ) -> Result<(Self, Diagnostics), GDError> {
+        Self::parse_with(s, Some(options.mode))
+    }
+
+    fn parse_with(s: &str, mode: Option<ParseMode>) -> Result<(Self, Diagnostics), GDError> {
         let mut headers_kv = s.split(",");
         let mut ka_props: [Option<HeaderValue>; KA_SIZE] = [const { None }; KA_SIZE];
         let mut ks_props: [Option<HeaderValue>; KS_SIZE] = [const { None }; KS_SIZE];
+        let mut diagnostics = vec![];
 
         while let (Some(k), Some(v)) = (headers_kv.next(), headers_kv.next()) {
             // assume that all properties start with either kA or kS
             let is_ks = k.starts_with("kS");
-            let prop_idx = match k[2..].parse::<u16>() {
-                Ok(n) => n,
-                Err(_) => {
-                    continue;
-                }
+            let prop_idx = k.get(2..).and_then(|i| i.parse::<u16>().ok());
+            let property = prop_idx.map(|i| if is_ks { KS_OFFSET + i } else { i });
+
+            let ptype = property
+                .and_then(get_level_header_property_type)
+                // assume int
+                .unwrap_or(HeaderValueType::Int);
+            // properties beyond the known ranges are dropped
+            let slot = match (is_ks, prop_idx) {
+                (true, Some(i)) => ks_props.get_mut(i as usize),
+                (false, Some(i)) => ka_props.get_mut(i as usize),
+                (_, None) => None,
             };
 
-            let ptype = if let Some(t) =
-                get_level_header_property_type(1000u16 * (is_ks as u16) + prop_idx)
-            {
-                t
+            let value = if v.is_empty() {
+                Some(None)
             } else {
-                // assume int
-                HeaderValueType::Int
+                HeaderValue::parse(v, ptype).map(Some)
             };
-            match is_ks {
-                true => ks_props[prop_idx as usize] = HeaderValue::parse(v, ptype),
-                false => ka_props[prop_idx as usize] = HeaderValue::parse(v, ptype),
+
+            match (slot, value) {
+                (Some(slot), Some(value)) => *slot = value,
+                (slot, _) => {
+                    let expected = if slot.is_none() {
+                        "known header property"
+                    } else {
+                        ptype.expected()
+                    };
+                    match mode {
+                        None => {}
+                        Some(ParseMode::Strict) => {
+                            return Err(invalid(expected, describe_str(v)).at(k));
+                        }
+                        Some(ParseMode::Lenient) => diagnostics.push(Diagnostic {
+                            property,
+                            key: Some(k.to_owned()),
+                            raw: Some(v.to_owned()),
+                            ..Diagnostic::new(DiagnosticKind::Dropped { expected })
+                        }),
+                    }
+                }
             }
         }
 
-        Some(Self {
-            ka: ka_props,
-            ks: ks_props,
-        })
+        Ok((
+            Self {
+                ka: ka_props,
+                ks: ks_props,
+            },
+            diagnostics,
+        ))
     }
 
     /// Gets a property based on the index. The index must be obtained from `gdobj::ids::level_header`.
     pub fn get_property(&self, property: u16) -> Option<&HeaderValue> {
-        if property > 1000 {
+        if property >= KS_OFFSET {
             self.ks
-                .get((property - 1000) as usize)
-                .map(|o| o.as_ref())
-                .flatten()
+                .get((property - KS_OFFSET) as usize)
+                .and_then(|o| o.as_ref())
         } else {
-            self.ka.get(property as usize).map(|o| o.as_ref()).flatten()
+            self.ka.get(property as usize).and_then(|o| o.as_ref())
         }
     }
     /// Sets a property based on the index. The index must be obtained from `gdobj::ids::level_header`.
     pub fn set_property(&mut self, property: u16, value: HeaderValue) {
-        if property > 1000 {
-            self.ks[(property - 1000) as usize] = Some(value);
+        if property >= KS_OFFSET {
+            self.ks[(property - KS_OFFSET) as usize] = Some(value);
         } else {
             self.ka[property as usize] = Some(value);
         }
@@ -653,8 +860,8 @@ impl GDLevelHeader {
 
     /// Removes a property based on the index. The index must be obtained from `gdobj::ids::level_header`.
     pub fn del_property(&mut self, property: u16) {
-        if property > 1000 {
-            self.ks[(property - 1000) as usize] = None;
+        if property >= KS_OFFSET {
+            self.ks[(property - KS_OFFSET) as usize] = None;
         } else {
             self.ka[property as usize] = None;
         }
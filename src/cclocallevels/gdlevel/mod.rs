@@ -2,11 +2,14 @@
 //! the Level struct, and its constituent structs.
 
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fmt::Display,
     fs::{read, write},
-    io::Cursor,
+    io::{Read, Write},
+    ops::Range,
     path::PathBuf,
+    sync::Arc,
 };
 
 use plist::{Dictionary, Value};
@@ -14,26 +17,48 @@ use smallvec::SmallVec;
 
 use crate::{
     cclocallevels::{
+        fidelity::{SavefileSource, element_hashes},
         gdlevel::{
             enums::{DemonType, DifficultyRating, EpicRating, GDLevelType, Length, OfficialSong},
             leveldata::{GDEncryptedLevelData, GDLevelData, GDLevelState},
+            timeline::Timeline,
         },
         gdlist::GDList,
-        gdobj::GDObject,
+        gdobj::{
+            GDObject,
+            ids::{
+                objects::{TRIGGER_SFX, TRIGGER_SONG, USER_COIN},
+                properties::SONG_ID,
+            },
+            structs::GDValue,
+        },
     },
     core::{
-        GDError, b64_decode, b64_encode, get_cclocallevels_path,
-        io::{decrypt_file, encrypt_savefile_str, stringify_xml, vec_as_str},
-        proper_plist_tags,
+        GDError, b64_decode, b64_encode,
+        files::{
+            Backup, SaveOptions, SavefileFingerprint, list_backups, restore_backup, write_savefile,
+        },
+        gdplist, get_cclocallevels_path,
+        io::{
+            SavefilePlatform, decrypt_file_with_fingerprint, decrypt_reader, encrypt_level_str,
+            encrypt_savefile_str_for, encrypt_savefile_to, stringify_xml, vec_as_str,
+        },
+        parse::{Diagnostic, DiagnosticKind, Diagnostics, ParseOptions, PlistParser},
         structs::KCEKValue,
+        values::{as_bool, as_dict, as_float, as_int, as_str, describe, invalid, parse_str},
     },
 };
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
+pub mod builder;
 pub mod enums;
+pub mod header;
 pub mod leveldata;
+pub mod palette;
+pub mod recolour;
+pub mod timeline;
 
 /// Standard header of a GD plist.
 pub const PLIST_HEADER: &str = "<?xml version=\"1.0\"?><plist version=\"1.0\" gjver=\"2.0\">";
@@ -49,107 +74,357 @@ pub struct CCLocalLevels {
     pub binary_version: Value,
     /// All lists in the savefile in order from newest to oldest.
     pub lists: Vec<GDList>,
+    /// Platform that the savefile is encrypted for when exported. This is detected when loading an encrypted savefile.
+    pub platform: SavefilePlatform,
+    /// Fingerprint of the savefile that this struct was loaded from, if any.
+    /// Exports refuse to overwrite that savefile if it was modified in the meantime.
+    pub fingerprint: Option<SavefileFingerprint>,
+    /// Root entries of the savefile other than `LLM_01`, `LLM_02` and `LLM_03`, in their original order.
+    /// These are written back untouched on export.
+    pub other_properties: Dictionary,
+    /// Original text of the savefile if it was loaded with [`ParseOptions::fidelity`].
+    /// Exports reuse it for everything that was not edited; set this to `None` to always serialise from scratch.
+    pub source: Option<Arc<SavefileSource>>,
 }
 
-impl CCLocalLevels {
-    /// Returns the levels in CCLocalLevels.dat if retrievable
-    #[inline]
-    pub fn from_local() -> Result<Self, GDError> {
-        CCLocalLevels::from_decrypted(decrypt_file(
-            get_cclocallevels_path().ok_or(GDError::MissingSavefile)?,
-        )?)
-    }
+/// Contents of a savefile before [`ParseOptions::mode`] is applied. Errors already carry their full key path.
+struct ParsedSavefile {
+    levels: Vec<Result<GDLevel, GDError>>,
+    lists: Vec<Result<GDList, GDError>>,
+    binary_version: Value,
+    other_properties: Dictionary,
+}
 
-    /// Parses raw savefile string into this struct
-    pub fn from_decrypted(s: String) -> Result<Self, GDError> {
-        if !s.starts_with(PLIST_HEADER) {
-            return Err(GDError::CorruptedSavefile("Savefile header does not match the expected header. This may be due to a corrupted savefile or a savefile from a previous version of GD.".into()));
-        };
+impl ParsedSavefile {
+    /// Parses the decrypted savefile text with the native parser.
+    fn native(xml: &str) -> Result<Self, GDError> {
+        let (_, root) = gdplist::root_entries(xml)?;
+        let (mut llm_01, mut llm_02, mut llm_03) = (None, None, None);
+        let mut other_properties = Dictionary::new();
+        for entry in root {
+            let key = gdplist::entry_key(xml, &entry)?;
+            match &*key {
+                "LLM_01" => llm_01 = Some(entry.value),
+                "LLM_02" => llm_02 = Some(gdplist::parse_value(xml, entry.value)?),
+                "LLM_03" => llm_03 = Some(entry.value),
+                _ => {
+                    let value = gdplist::parse_value(xml, entry.value)?;
+                    other_properties.insert(key.into_owned(), value);
+                }
+            }
+        }
 
-        let mut xmltree = Value::from_reader_xml(Cursor::new(s.as_bytes()))?
-            .into_dictionary()
-            .ok_or(GDError::CorruptedSavefile(
-                "CCLocalLevels.dat is not a dict".into(),
-            ))?;
-
-        let levels_dict = xmltree
-            .remove("LLM_01")
-            .to_owned()
-            .ok_or(GDError::CorruptedSavefile("No LLM_01 (levels)".into()))?
-            .into_dictionary()
-            .ok_or(GDError::CorruptedSavefile("LLM_01 is not a dict".into()))?;
-
-        let llm_02 = xmltree.remove("LLM_02").ok_or(GDError::CorruptedSavefile(
-            "No LLM_02 (binary version)".into(),
-        ))?;
-        let llm_03 = xmltree
-            .remove("LLM_03")
-            .ok_or(GDError::CorruptedSavefile("No LLM_03 (lists)".into()))?;
+        let levels_dict = llm_01.ok_or(GDError::missing_key("LLM_01"))?;
+        let binary_version = llm_02.ok_or(GDError::missing_key("LLM_02"))?;
+        let lists_dict = llm_03.ok_or(GDError::missing_key("LLM_03"))?;
 
         // these are stored as "k_0": <level>, "k_1": <level>, etc. in the savefile,
         // the vec prserves that order.
-        let collected_dicts = levels_dict
+        let level_entries = match gdplist::dict_content(xml, levels_dict.clone()) {
+            Some(content) => gdplist::dict_entries(xml, content)?,
+            None => {
+                let found = describe(&gdplist::parse_value(xml, levels_dict)?);
+                return Err(invalid("dictionary", found).at("LLM_01"));
+            }
+        };
+        let level_entries = level_entries
             .into_iter()
-            .filter_map(|(k, v)| match k.as_str() {
-                "_isArr" => None,
-                _ => v.into_dictionary(),
-            })
-            .collect::<Vec<Dictionary>>();
+            .map(|e| Ok((gdplist::entry_key(xml, &e)?, e.value)))
+            .filter(|e| !matches!(e, Ok((k, _)) if k == "_isArr"))
+            .collect::<Result<Vec<_>, GDError>>()?;
+
+        let parse_level = |(k, v): &(Cow<str>, Range<usize>)| {
+            GDLevel::from_gdplist(xml, v.clone()).map_err(|e| e.at(k).at("LLM_01"))
+        };
 
         #[cfg(feature = "parallel")]
-        let levels_parsed: Vec<GDLevel> = collected_dicts
-            .par_iter()
-            .map(GDLevel::from_dict)
-            .collect::<Option<_>>()
-            .ok_or(GDError::CorruptedSavefile("Unable to parse levels".into()))?;
+        let levels = level_entries.par_iter().map(parse_level).collect();
 
         #[cfg(not(feature = "parallel"))]
-        let levels_parsed: Vec<GDLevel> = collected_dicts
-            .iter()
-            .map(GDLevel::from_dict)
-            .collect::<Option<_>>()
-            .ok_or(GDError::CorruptedSavefile("Unable to parse levels".into()))?;
-
-        let lists = CCLocalLevels::parse_from_value(&llm_03)?;
+        let levels = level_entries.iter().map(parse_level).collect();
+
+        let lists = match gdplist::dict_content(xml, lists_dict.clone()) {
+            Some(content) => {
+                let mut lists = vec![];
+                for entry in gdplist::dict_entries(xml, content)? {
+                    // `_isArr` and anything else that is not a list is ignored
+                    if gdplist::dict_content(xml, entry.value.clone()).is_some() {
+                        let key = gdplist::entry_key(xml, &entry)?;
+                        lists.push(
+                            GDList::from_gdplist(xml, entry.value)
+                                .map_err(|e| e.at(&key).at("LLM_03")),
+                        );
+                    }
+                }
+                lists
+            }
+            None => {
+                let found = describe(&gdplist::parse_value(xml, lists_dict)?);
+                return Err(invalid("dictionary", found).at("LLM_03"));
+            }
+        };
 
-        let levels = CCLocalLevels {
-            levels: levels_parsed,
-            binary_version: llm_02,
+        Ok(Self {
+            levels,
             lists,
+            binary_version,
+            other_properties,
+        })
+    }
+
+    /// Reads the root value of a savefile that was parsed by the plist crate.
+    fn from_tree(root: Value) -> Result<Self, GDError> {
+        let root = root.into_dictionary().ok_or(GDError::CorruptedSavefile(
+            "CCLocalLevels.dat is not a dict".into(),
+        ))?;
+
+        // `Dictionary::remove` does not preserve the order of the remaining keys
+        let (mut llm_01, mut llm_02, mut llm_03) = (None, None, None);
+        let mut other_properties = Dictionary::new();
+        for (k, v) in root {
+            match k.as_str() {
+                "LLM_01" => llm_01 = Some(v),
+                "LLM_02" => llm_02 = Some(v),
+                "LLM_03" => llm_03 = Some(v),
+                _ => {
+                    other_properties.insert(k, v);
+                }
+            }
+        }
+
+        let levels_dict = match llm_01 {
+            Some(Value::Dictionary(d)) => d,
+            Some(v) => return Err(invalid("dictionary", describe(&v)).at("LLM_01")),
+            None => return Err(GDError::missing_key("LLM_01")),
         };
+        let binary_version = llm_02.ok_or(GDError::missing_key("LLM_02"))?;
+        let llm_03 = llm_03.ok_or(GDError::missing_key("LLM_03"))?;
 
-        Ok(levels)
-    }
+        let collected_dicts = levels_dict
+            .into_iter()
+            .filter(|(k, _)| k != "_isArr")
+            .collect::<Vec<(String, Value)>>();
+
+        let parse_level = |(k, v): &(String, Value)| {
+            as_dict(v)
+                .and_then(GDLevel::from_dict)
+                .map_err(|e| e.at(k).at("LLM_01"))
+        };
 
-    /// Parses the given plist dictionary to this struct
-    pub(crate) fn parse_from_value(v: &Value) -> Result<Vec<GDList>, GDError> {
-        /* Input dict structure
+        #[cfg(feature = "parallel")]
+        let levels = collected_dicts.par_iter().map(parse_level).collect();
+
+        #[cfg(not(feature = "parallel"))]
+        let levels = collected_dicts.iter().map(parse_level).collect();
+
+        /* Lists dict structure
          * "_isArr": always Boolean(true)
          * k_X: list at index X
          */
-        let dict = v.as_dictionary().ok_or(GDError::CorruptedSavefile(
-            "Input value is not a dictionary.".into(),
-        ))?;
-        let mut lists = Vec::with_capacity(dict.len());
+        let lists = as_dict(&llm_03)
+            .map_err(|e| e.at("LLM_03"))?
+            .iter()
+            .filter_map(|(k, v)| match v {
+                Value::Dictionary(d) => {
+                    Some(GDList::from_dictionary(d).map_err(|e| e.at(k).at("LLM_03")))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Ok(Self {
+            levels,
+            lists,
+            binary_version,
+            other_properties,
+        })
+    }
+}
+
+impl CCLocalLevels {
+    /// Returns the levels in CCLocalLevels.dat if retrievable
+    #[inline]
+    pub fn from_local() -> Result<Self, GDError> {
+        CCLocalLevels::from_file(get_cclocallevels_path().ok_or(GDError::MissingSavefile)?)
+    }
+
+    /// Returns the levels in the given encrypted savefile. The savefile may be from any platform.
+    ///
+    /// The savefile is read into memory as a whole to fingerprint it; use [`CCLocalLevels::from_reader`] to decrypt it
+    /// while it is read instead.
+    pub fn from_file(file: PathBuf) -> Result<Self, GDError> {
+        Self::from_file_with_options(file, &ParseOptions::strict()).map(|(levels, _)| levels)
+    }
+
+    /// Returns the levels in the given encrypted savefile, parsed according to `options`.
+    /// See [`CCLocalLevels::from_decrypted_with_options`].
+    pub fn from_file_with_options(
+        file: PathBuf,
+        options: &ParseOptions,
+    ) -> Result<(Self, Diagnostics), GDError> {
+        let (decrypted, platform, fingerprint) = decrypt_file_with_fingerprint(file)?;
+        let (mut levels, diagnostics) =
+            CCLocalLevels::from_decrypted_with_options(decrypted, options)?;
+        levels.platform = platform;
+        levels.fingerprint = Some(fingerprint);
+        Ok((levels, diagnostics))
+    }
+
+    /// Returns the levels in an encrypted savefile read from `reader`. The savefile may be from any platform.
+    ///
+    /// Windows savefiles are decrypted while they are read, so the encrypted savefile is never held in memory as a
+    /// whole. The decrypted XML is, until the levels have been parsed from it.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, GDError> {
+        Self::from_reader_with_options(reader, &ParseOptions::strict()).map(|(levels, _)| levels)
+    }
+
+    /// Like [`CCLocalLevels::from_reader`], but parsed according to `options`.
+    /// See [`CCLocalLevels::from_decrypted_with_options`].
+    pub fn from_reader_with_options<R: Read>(
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<(Self, Diagnostics), GDError> {
+        let (decrypted, platform) = decrypt_reader(reader)?;
+        let (mut levels, diagnostics) =
+            CCLocalLevels::from_decrypted_with_options(decrypted, options)?;
+        levels.platform = platform;
+        Ok((levels, diagnostics))
+    }
 
-        for (_, v) in dict {
-            if let Value::Dictionary(d) = v {
-                lists.push(GDList::from_dictionary(d).ok_or(GDError::CorruptedSavefile(
-                    "Unable to parse into a valid GD list.".into(),
-                ))?)
+    /// Parses raw savefile string into this struct
+    pub fn from_decrypted(s: String) -> Result<Self, GDError> {
+        Self::from_decrypted_with_options(s, &ParseOptions::strict()).map(|(levels, _)| levels)
+    }
+
+    /// Parses raw savefile string into this struct according to `options`.
+    ///
+    /// In strict mode, any level or list that cannot be parsed is an error. In lenient mode, it is skipped and
+    /// reported as a [`Diagnostic`] with the level index (or key path, for lists) set.
+    ///
+    /// Level data is still decrypted lazily; use [`CCLocalLevels::decrypt_levels_with_options`] to parse the objects
+    /// of every level with the same options.
+    pub fn from_decrypted_with_options(
+        s: String,
+        options: &ParseOptions,
+    ) -> Result<(Self, Diagnostics), GDError> {
+        if !s.starts_with(PLIST_HEADER) {
+            return Err(GDError::CorruptedSavefile("Savefile header does not match the expected header. This may be due to a corrupted savefile or a savefile from a previous version of GD.".into()));
+        };
+
+        let parsed = gdplist::parse_with_fallback(
+            &s,
+            options.parser,
+            ParsedSavefile::native,
+            ParsedSavefile::from_tree,
+        )?;
+
+        let level_hashes = options.fidelity.then(|| {
+            let levels = parsed
+                .levels
+                .iter()
+                .map(|l| l.as_ref().ok())
+                .collect::<Vec<_>>();
+            element_hashes(&levels, GDLevel::to_dict)
+        });
+
+        let mut diagnostics = vec![];
+        let mut levels = Vec::with_capacity(parsed.levels.len());
+        for (idx, level) in parsed.levels.into_iter().enumerate() {
+            match level {
+                Ok(level) => levels.push(level),
+                Err(e) if options.is_strict() => return Err(e),
+                Err(e) => diagnostics.push(Diagnostic {
+                    level: Some(idx),
+                    ..Diagnostic::skipped(&e, |reason| DiagnosticKind::SkippedLevel { reason })
+                }),
+            }
+        }
+
+        let mut lists = Vec::with_capacity(parsed.lists.len());
+        for list in parsed.lists {
+            match list {
+                Ok(list) => lists.push(Some(list)),
+                Err(e) if options.is_strict() => return Err(e),
+                Err(e) => {
+                    diagnostics.push(Diagnostic::skipped(&e, |reason| {
+                        DiagnosticKind::SkippedList { reason }
+                    }));
+                    lists.push(None);
+                }
             }
         }
 
-        Ok(lists)
+        let source = match level_hashes {
+            Some(level_hashes) => {
+                let parsed_lists = lists.iter().map(Option::as_ref).collect::<Vec<_>>();
+                let list_hashes = element_hashes(&parsed_lists, GDList::to_dict);
+                Some(Arc::new(SavefileSource::new(
+                    s,
+                    level_hashes,
+                    list_hashes,
+                    parsed.binary_version.clone(),
+                    parsed.other_properties.clone(),
+                )?))
+            }
+            None => None,
+        };
+
+        let levels = CCLocalLevels {
+            levels,
+            binary_version: parsed.binary_version,
+            lists: lists.into_iter().flatten().collect(),
+            platform: SavefilePlatform::default(),
+            fingerprint: None,
+            other_properties: parsed.other_properties,
+            source,
+        };
+
+        Ok((levels, diagnostics))
+    }
+
+    /// Decrypts and parses the level data of every level according to `options`.
+    ///
+    /// In strict mode, the first level whose data cannot be parsed is an error. In lenient mode, its data is left
+    /// encrypted and reported as a skipped level. Every diagnostic has the level index set.
+    pub fn decrypt_levels_with_options(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<Diagnostics, GDError> {
+        let mut diagnostics = vec![];
+        for (idx, level) in self.levels.iter_mut().enumerate() {
+            let level_diagnostics = match level.decrypt_level_data_with_options(options) {
+                Ok(d) => d,
+                Err(e) if options.is_strict() => {
+                    return Err(e.at(&format!("k_{idx}")).at("LLM_01"));
+                }
+                Err(e) => vec![Diagnostic::skipped(&e, |reason| {
+                    DiagnosticKind::SkippedLevel { reason }
+                })],
+            };
+            diagnostics.extend(level_diagnostics.into_iter().map(|d| Diagnostic {
+                level: Some(idx),
+                ..d
+            }));
+        }
+        Ok(diagnostics)
     }
 
-    /// Adds a level to the beginning of `self.levels`
+    /// Adds a level to the beginning of `self.levels`, making it the newest level.
+    /// See [`CCLocalLevels::normalise_level_order`].
     pub fn add_level(&mut self, level: GDLevel) {
         self.levels.insert(0, level);
+        self.normalise_level_order();
     }
 
-    /// Exports this struct as XML to a String
+    /// Exports this struct as XML to a String.
+    ///
+    /// If this struct was loaded with [`ParseOptions::fidelity`], everything that was not edited is copied from the
+    /// original savefile; see [`SavefileSource`].
     pub fn export_to_string(&mut self) -> String {
+        if let Some(source) = &self.source {
+            return source.export(self);
+        }
+
         let mut dict = Dictionary::new();
 
         /* levels */
@@ -173,7 +448,7 @@ impl CCLocalLevels {
             .map(|(idx, level)| (format!("k_{idx}"), Value::from(level.to_dict())))
             .collect::<Vec<(String, Value)>>();
 
-        levels_dict.extend(level_dict_entries.into_iter());
+        levels_dict.extend(level_dict_entries);
 
         /* lists */
 
@@ -196,44 +471,95 @@ impl CCLocalLevels {
             .map(|(idx, level)| (format!("k_{idx}"), Value::from(level.to_dict())))
             .collect::<Vec<(String, Value)>>();
 
-        lists_dict.extend(list_dict_entries.into_iter());
+        lists_dict.extend(list_dict_entries);
 
         dict.insert("LLM_01".to_string(), Value::Dictionary(levels_dict));
         dict.insert("LLM_02".to_string(), self.binary_version.clone());
         dict.insert("LLM_03".to_string(), Value::Dictionary(lists_dict));
+        dict.extend(self.other_properties.clone());
 
         format!("{PLIST_HEADER}{}{PLIST_FOOTER}", stringify_xml(&dict, true))
     }
 
+    /// Exports this struct as XML encrypted for `self.platform` to `writer`, and returns the writer.
+    ///
+    /// The XML is built in memory as a whole (see [`CCLocalLevels::export_to_string`]); only the encrypted savefile
+    /// is streamed to `writer`.
+    pub fn export_to_writer<W: Write>(&mut self, writer: W) -> Result<W, GDError> {
+        encrypt_savefile_to(&self.export_to_string(), self.platform, writer)
+    }
+
     /// Exports this struct as encrypted XML to the standard CCLocalLevels.dat location.
     /// This function will return an error if it is unable to find the savefile at the standard location.
+    /// The savefile is encrypted for `self.platform` and written atomically, without creating a backup.
     ///
-    /// Standard location on windows: %LOCALAPPDATA%\GeometryDash
-    /// Standard location on linux: [`crate::core::LINUX_GD_FILES`]
+    /// See [`crate::core::locator::SavefileLocator`] for the locations that are searched.
     pub fn export_to_savefile(&mut self) -> Result<(), GDError> {
-        let savefile = get_cclocallevels_path().ok_or(GDError::MissingSavefile)?;
-        let export_str = encrypt_savefile_str(&self.export_to_string());
-        write(savefile, export_str)?;
-        Ok(())
+        self.export_to_savefile_with(&SaveOptions::without_backups())
     }
 
-    /// Exports this struct as encrypted XML to a given file
+    /// Exports this struct as encrypted XML to a given file. The savefile is encrypted for `self.platform` and written atomically.
+    ///
+    /// The encrypted savefile is built in memory as a whole to fingerprint it; use [`CCLocalLevels::export_to_writer`]
+    /// to encrypt it while it is written instead.
     pub fn export_to_file(&mut self, file: PathBuf) -> Result<(), GDError> {
-        let export_str = encrypt_savefile_str(self.export_to_string().as_str());
-        write(file, export_str)?;
-        Ok(())
+        self.export_to_file_with(file, &SaveOptions::without_backups())
     }
 
-    /// Exports this struct as encrypted XML to CCLocalLevels.dat and creates a backup, CCLocalLevels.dat.bak
+    /// Exports this struct as encrypted XML to CCLocalLevels.dat and creates a timestamped backup of the previous savefile.
+    /// The five most recent backups are kept; see [`crate::core::files::BackupPolicy`] to configure this.
     pub fn export_to_savefile_with_backup(&mut self) -> Result<(), GDError> {
+        self.export_to_savefile_with(&SaveOptions::default())
+    }
+
+    /// Exports this struct as encrypted XML to CCLocalLevels.dat using the given save options.
+    pub fn export_to_savefile_with(&mut self, options: &SaveOptions) -> Result<(), GDError> {
         let savefile = get_cclocallevels_path().ok_or(GDError::MissingSavefile)?;
-        let backup_path = format!("{}.bak", savefile.to_string_lossy());
-        write(backup_path, read(&savefile)?)?;
+        self.export_to_file_with(savefile, options)
+    }
 
-        let export_str = encrypt_savefile_str(&self.export_to_string());
-        write(savefile, export_str)?;
+    /// Exports this struct as encrypted XML to a given file using the given save options.
+    ///
+    /// The new savefile is written to a temporary file and renamed over `file`,
+    /// so an interrupted export never leaves a truncated savefile behind.
+    ///
+    /// If `file` is the savefile that this struct was loaded from and it was modified since then,
+    /// [`GDError::ConcurrentModification`] is returned unless `options.force` is set.
+    /// Use [`CCLocalLevels::merge_three_way`] to apply your changes onto the newer savefile instead.
+    pub fn export_to_file_with(
+        &mut self,
+        file: PathBuf,
+        options: &SaveOptions,
+    ) -> Result<(), GDError> {
+        let tracked = self.fingerprint.as_ref().is_some_and(|fp| fp.is_for(&file));
+        if tracked
+            && !options.force
+            && let Some(fingerprint) = &self.fingerprint
+        {
+            fingerprint.verify()?;
+        }
+
+        let export_str = encrypt_savefile_str_for(&self.export_to_string(), self.platform);
+        write_savefile(&file, &export_str, options)?;
+
+        if tracked {
+            self.fingerprint = Some(SavefileFingerprint::from_bytes(file, &export_str)?);
+        }
         Ok(())
     }
+
+    /// Returns all timestamped backups of CCLocalLevels.dat, newest first.
+    pub fn backups() -> Result<Vec<Backup>, GDError> {
+        list_backups(get_cclocallevels_path().ok_or(GDError::MissingSavefile)?)
+    }
+
+    /// Restores the given backup over CCLocalLevels.dat.
+    pub fn restore_backup(backup: &Backup) -> Result<(), GDError> {
+        restore_backup(
+            get_cclocallevels_path().ok_or(GDError::MissingSavefile)?,
+            backup,
+        )
+    }
 }
 
 /* Notes for maintainers
@@ -271,6 +597,12 @@ pub struct GDLevel {
     pub integrity: GDLevelIntegrity,
     /// Unaccounted for/unknown keys: k91, k92, k101, k106 and all other keys that didn't fit in any other fields in this struct.
     pub unknowns: GDLevelUnknowns,
+    /// Call [`GDLevel::refresh_metadata`] on a copy of this level whenever it is exported, so that the exported
+    /// object count, level size, song and SFX lists and coin count always match the level data. Off by default,
+    /// since the level data has to be parsed (or serialised) once more.
+    ///
+    /// Not stored in the savefile.
+    pub refresh_metadata_on_export: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -635,13 +967,12 @@ pub struct GDLevelUnknowns {
 impl GDLevel {
     /// Parses a .gmd file to a `Self` object
     pub fn from_gmd<T: Into<PathBuf>>(path: T) -> Result<Self, GDError> {
-        let file = proper_plist_tags(vec_as_str(&read(path.into())?))?;
-        let xmltree = Value::from_reader_xml(Cursor::new(file.as_bytes()))?;
-
-        Ok(
-            Self::from_dict(xmltree.as_dictionary().unwrap()).ok_or(GDError::CorruptedSavefile(
-                "Unable to parse file to valid level.".into(),
-            ))?,
+        let file = vec_as_str(&read(path.into())?)?;
+        gdplist::parse_with_fallback(
+            &file,
+            PlistParser::Native,
+            |xml| Self::from_gdplist(xml, gdplist::root_value(xml)?),
+            |root| Self::from_dict(as_dict(&root)?),
         )
     }
 
@@ -661,226 +992,223 @@ impl GDLevel {
     /// This method is intended to convert all valid keys (that is, keys that are known and whose value is of its corresponding type).
     /// Known keys are parsed, while unknown keys are stored as-is.
     ///
-    /// This function panics if any of the documented keys have a value which does not fit its expected type. For instance, if the attempt count, which is expected to be an integer is instead found to be a string/float/array/etc.
-    pub(crate) fn from_dict(d: &Dictionary) -> Option<Self> {
+    /// Returns [`GDError::InvalidValue`] if any of the documented keys have a value which does not fit its expected type. For instance, if the attempt count, which is expected to be an integer is instead found to be a string/float/array/etc.
+    pub(crate) fn from_dict(d: &Dictionary) -> Result<Self, GDError> {
         let mut level = Self::default();
         for (k, v) in d {
-            // keys come in a few shapes:
-            // 1. kCEK: special kCEK key
-            // 2. kXX: general keys
-            // 3. kIX: some specific keys (no difference from kXX other than that you can't pase key[1..] to int)
-            // 4. possibly unknown keys that do not necessarily follow this shape
-
-            // structure:
-            // - try parse key and value and set the corresponding field
-            // - if the key (or its value) was not parsed successfully, try the next branch
-
-            if k == "kCEK" {
-                level.meta.kcek = KCEKValue::try_from(v.as_signed_integer()? as i32).unwrap();
-                continue;
-            }
-            if k.starts_with("kI")
-                && let Ok(key_id) = k[2..].parse::<i32>()
-            {
-                match key_id {
-                    1 => level.editor_state.camera_x = to_float(v)?,
-                    2 => level.editor_state.camera_y = to_float(v)?,
-                    3 => level.editor_state.camera_zoom = to_float(v)?,
-                    4 => level.editor_state.build_tab_page = v.as_signed_integer()? as i32,
-                    5 => level.editor_state.build_tab = v.as_signed_integer()? as i32,
-                    6 => {
-                        level.editor_state.build_tab_pages = {
-                            let mut pages = SmallVec::new();
-                            for (tab, page) in v.as_dictionary()? {
-                                let tab_idx = tab.parse::<usize>().ok().unwrap();
-                                // this is horrible but necessary due to rob's savefile format
-                                let page_idx = page
-                                    .as_string()
-                                    .unwrap()
-                                    .to_string()
-                                    .parse::<i32>()
-                                    .ok()
-                                    .unwrap();
-
-                                // resize array
-                                if tab_idx >= pages.len() {
-                                    pages.resize(tab_idx + 1, 0);
-                                }
-                                pages[tab_idx] = page_idx;
+            level.parse_key(k, v).map_err(|e| e.at(k))?;
+        }
+        Ok(level)
+    }
+
+    /// Parses the dictionary spanning `value` in a document of GD's plist dialect into a GDLevel object.
+    /// Keys are handled the same way as in [`GDLevel::from_dict`].
+    pub(crate) fn from_gdplist(xml: &str, value: Range<usize>) -> Result<Self, GDError> {
+        let mut level = Self::default();
+        gdplist::for_each_entry(xml, value, |k, v| {
+            level.parse_key(k, &v).map_err(|e| e.at(k))
+        })?;
+        Ok(level)
+    }
+
+    fn parse_key(&mut self, k: &str, v: &Value) -> Result<(), GDError> {
+        // keys come in a few shapes:
+        // 1. kCEK: special kCEK key
+        // 2. kXX: general keys
+        // 3. kIX: some specific keys (no difference from kXX other than that you can't pase key[1..] to int)
+        // 4. possibly unknown keys that do not necessarily follow this shape
+
+        // structure:
+        // - try parse key and value and set the corresponding field
+        // - if the key (or its value) was not parsed successfully, try the next branch
+
+        if k == "kCEK" {
+            self.meta.kcek = KCEKValue::from(as_int(v)?);
+            return Ok(());
+        }
+        if k.starts_with("kI")
+            && let Ok(key_id) = k[2..].parse::<i32>()
+        {
+            match key_id {
+                1 => self.editor_state.camera_x = as_float(v)?,
+                2 => self.editor_state.camera_y = as_float(v)?,
+                3 => self.editor_state.camera_zoom = as_float(v)?,
+                4 => self.editor_state.build_tab_page = as_int(v)?,
+                5 => self.editor_state.build_tab = as_int(v)?,
+                6 => {
+                    self.editor_state.build_tab_pages = {
+                        let mut pages = SmallVec::new();
+                        for (tab, page) in as_dict(v)? {
+                            // there are only 14 build tabs; anything far beyond that is corrupt
+                            let tab_idx = parse_str::<u8>(tab, "build tab index")
+                                .map_err(|e| e.at(tab))?
+                                as usize;
+                            // this is horrible but necessary due to rob's savefile format
+                            let page_idx = as_str(page)
+                                .and_then(|p| parse_str::<i32>(p, "build tab page"))
+                                .map_err(|e| e.at(tab))?;
+
+                            // resize array
+                            if tab_idx >= pages.len() {
+                                pages.resize(tab_idx + 1, 0);
                             }
-                            pages
+                            pages[tab_idx] = page_idx;
                         }
+                        pages
                     }
-                    7 => level.editor_state.editor_layer = to_float(v)?,
-                    _ => {
-                        level.unknowns.other.insert(k.clone(), v.clone());
-                    }
                 }
-                continue;
+                7 => self.editor_state.editor_layer = as_float(v)?,
+                _ => {
+                    self.unknowns.other.insert(k.to_owned(), v.clone());
+                }
             }
-            if k.starts_with("k")
-                && let Ok(key_id) = k[1..].parse::<i32>()
-            {
-                match key_id {
-                    1 => level.identity.id = v.as_signed_integer()? as i32,
-                    2 => level.identity.name = v.as_string()?.to_string(),
-                    3 => {
-                        // special case: b64-encoded data
-                        level.identity.description =
-                            Some(vec_as_str(&b64_decode(&v.as_string()?[..]).ok()?[..]))
-                    }
-                    4 => {
-                        // special case: level data
-                        let data = v.as_string()?.to_string();
-                        level.content.data =
-                            Some(GDLevelState::Encrypted(GDEncryptedLevelData { data }))
-                    }
-                    5 => level.identity.creator = v.as_string()?.to_string(),
-                    6 => level.identity.user_id = v.as_signed_integer()? as i32,
-                    7 => level.ratings.difficulty = v.as_signed_integer()? as i32,
-                    8 => {
-                        level.content.official_song_id =
-                            Some(OfficialSong::from(v.as_signed_integer()? as i32))
-                    }
-                    9 => {
-                        level.ratings.rating = DifficultyRating::from(v.as_signed_integer()? as i32)
-                    }
-                    10 => level.ratings.rating_sum = v.as_signed_integer()? as i32,
-                    11 => level.ratings.downloads = v.as_signed_integer()? as i32,
-                    12 => level.player_stats.completions = v.as_signed_integer()? as i32,
-                    13 => level.flags.is_editable = v.as_boolean()?,
-                    14 => level.flags.verified = v.as_boolean()?,
-                    15 => level.flags.uploaded = v.as_boolean()?,
-                    16 => level.identity.level_version = v.as_signed_integer()? as i32,
-                    17 => level.identity.game_version = v.as_signed_integer()? as i32,
-                    18 => level.player_stats.attempts = v.as_signed_integer()? as i32,
-                    19 => level.player_stats.normal_percentage = v.as_signed_integer()? as i32,
-                    20 => level.player_stats.practice_percentage = v.as_signed_integer()? as i32,
-                    21 => {
-                        // special case: GDLevelType enum
-                        level.identity.level_type =
-                            GDLevelType::try_from(v.as_signed_integer()? as i32)
-                                .ok()
-                                .unwrap()
-                    }
-                    22 => level.ratings.like_rating = v.as_signed_integer()? as i32,
-                    23 => level.content.length = Length::from(v.as_signed_integer()? as i32),
-                    24 => level.ratings.dislikes = v.as_signed_integer()? as i32,
-                    25 => level.ratings.is_demon = v.as_boolean()?,
-                    26 => level.ratings.stars = v.as_signed_integer()? as i32,
-                    27 => level.ratings.feature_score = Some(v.as_signed_integer()? as i32),
-                    /* 28 - 32 are unused */
-                    33 => level.ratings.is_auto = v.as_boolean()?,
-                    34 => level.integrity.replay_data = Some(v.as_string()?.as_bytes().to_vec()),
-                    35 => level.flags.is_playable = v.as_boolean()?,
-                    36 => level.player_stats.jumps = v.as_signed_integer()? as i32,
-                    37 => level.coins.required_coins = Some(v.as_signed_integer()? as i32),
-                    38 => level.flags.is_unlocked = v.as_boolean()?,
-                    39 => level.meta.level_size = v.as_signed_integer()? as i32,
-                    40 => level.identity.build_version = v.as_signed_integer()? as i32,
-                    41 => level.identity.password = Some(v.as_signed_integer()? as i32),
-                    42 => level.identity.original_id = Some(v.as_signed_integer()? as i32),
-                    43 => level.content.two_player_mode = v.as_boolean()?,
-                    /* 44 is unused */
-                    45 => level.content.custom_song_id = Some(v.as_signed_integer()? as i32),
-                    46 => level.identity.level_revision = Some(v.as_signed_integer()? as i32),
-                    47 => level.flags.has_been_modified = v.as_boolean()?,
-                    48 => level.content.object_count = v.as_signed_integer()? as i32,
-                    /* 49 is unused */
-                    50 => level.identity.binary_version = v.as_signed_integer()? as i32,
-                    51 => level.meta.capacity_001 = v.as_signed_integer()? as i32,
-                    52 => level.meta.capacity_002 = v.as_signed_integer()? as i32,
-                    53 => level.meta.capacity_003 = v.as_signed_integer()? as i32,
-                    54 => level.meta.capacity_004 = v.as_signed_integer()? as i32,
-                    /* 55 - 59 as unused */
-                    60 => level.identity.account_id = v.as_signed_integer()? as i32,
-                    61 => level.coins.coin1_acquired = v.as_boolean()?,
-                    62 => level.coins.coin2_acquired = v.as_boolean()?,
-                    63 => level.coins.coin3_acquired = v.as_boolean()?,
-                    64 => level.coins.total_coins = v.as_signed_integer()? as i32,
-                    65 => level.coins.coins_verified = v.as_boolean()?,
-                    66 => level.ratings.requested_stars = Some(v.as_signed_integer()? as i32),
-                    67 => level.meta.capacity_string = Some(v.as_string()?.to_string()),
-                    68 => level.integrity.triggered_anti_cheat = v.as_boolean()?,
-                    69 => level.content.high_object_count = v.as_boolean()?,
-                    /* 70 is unused */
-                    71 => level.player_stats.mana_orb_percentage = v.as_signed_integer()? as i32,
-                    72 => level.flags.has_ldm = v.as_boolean()?,
-                    73 => level.flags.ldm_enabled = v.as_boolean()?,
-                    74 => level.identity.timely_id = v.as_signed_integer()? as i32,
-                    75 => {
-                        // special case: EpicRating
-                        level.ratings.epic_rating =
-                            EpicRating::try_from(v.as_signed_integer()? as i32)
-                                .ok()
-                                .unwrap()
-                    }
-                    76 => {
-                        // special case: DemonType
-                        level.ratings.demon_type = Some(
-                            DemonType::try_from(v.as_signed_integer()? as i32)
-                                .ok()
-                                .unwrap(),
-                        )
-                    }
-                    77 => level.flags.is_gauntlet = v.as_boolean()?,
-                    78 => level.flags.is_alt_game = v.as_boolean()?,
-                    79 => level.flags.unlisted = v.as_boolean()?,
-                    80 => level.meta.seconds_editing = v.as_signed_integer()? as i32,
-                    81 => level.meta.seconds_editing_copies = v.as_signed_integer()? as i32,
-                    82 => level.flags.is_favourited = v.as_boolean()?,
-                    83 => level.meta.level_order = v.as_signed_integer()? as i32,
-                    84 => level.meta.level_folder = v.as_signed_integer()? as i32,
-                    85 => level.player_stats.clicks = v.as_signed_integer()? as i32,
-                    86 => level.player_stats.best_attempt_time = v.as_signed_integer()? as i32,
-                    87 => level.integrity.level_seed = Some(v.as_signed_integer()? as i32),
-                    88 => {
-                        // special case: comma-separated list
-                        level.player_stats.progress_diffs = parse_csv(v)?;
-                    }
-                    89 => level.integrity.vfd_chk = v.as_boolean()?,
-                    90 => level.player_stats.leaderboard_percentage = v.as_signed_integer()? as i32,
-                    91 => level.unknowns.k91 = Some(v.as_string()?.to_string()),
-                    92 => level.unknowns.k92 = Some(v.as_signed_integer()? as i32),
-                    93 => level.flags.unlimited_objects = v.as_boolean()?,
-                    94 => level.content.is_platformer = v.as_boolean()?,
-                    95 => {
-                        level.player_stats.verification_time = Some(v.as_signed_integer()? as i32)
-                    }
-                    /* 96 - 100 are unused */
-                    101 => level.unknowns.k101 = Some(v.as_string()?.to_string()),
-                    /* 102 - 103 */
-                    104 => {
-                        // special case: comma-separated list
-                        level.content.song_list = parse_csv(v)?;
-                    }
-                    105 => {
-                        // special case: comma-separated list
-                        level.content.sfx_list = Some(parse_csv(v)?);
-                    }
-                    106 => level.unknowns.k106 = Some(v.as_signed_integer()? as i32),
-                    107 => level.player_stats.best_time_ms = Some(v.as_signed_integer()? as i32),
-                    108 => level.player_stats.best_points = Some(v.as_signed_integer()? as i32),
-                    109 => {
-                        // special case: comma-separated list
-                        level.player_stats.local_best_times = parse_csv(v)?;
-                    }
-                    110 => {
-                        // special case: comma-separated list
-                        level.player_stats.local_best_points = parse_csv(v)?;
-                    }
-                    111 => level.integrity.platformer_seed = Some(v.as_signed_integer()? as i32),
-                    112 => level.flags.no_shake = v.as_boolean()?,
-                    _ => {
-                        level.unknowns.other.insert(k.clone(), v.clone());
-                    }
+            return Ok(());
+        }
+        if k.starts_with("k")
+            && let Ok(key_id) = k[1..].parse::<i32>()
+        {
+            match key_id {
+                1 => self.identity.id = as_int(v)?,
+                2 => self.identity.name = as_str(v)?.to_string(),
+                3 => {
+                    // special case: b64-encoded data
+                    self.identity.description =
+                        Some(vec_as_str(&b64_decode(as_str(v)?).map_err(|_| {
+                            invalid("base64-encoded string", describe(v))
+                        })?)?)
+                }
+                4 => {
+                    // special case: level data
+                    let data = as_str(v)?.to_string();
+                    self.content.data = Some(GDLevelState::Encrypted(GDEncryptedLevelData { data }))
+                }
+                5 => self.identity.creator = as_str(v)?.to_string(),
+                6 => self.identity.user_id = as_int(v)?,
+                7 => self.ratings.difficulty = as_int(v)?,
+                8 => self.content.official_song_id = Some(OfficialSong::from(as_int(v)?)),
+                9 => self.ratings.rating = DifficultyRating::from(as_int(v)?),
+                10 => self.ratings.rating_sum = as_int(v)?,
+                11 => self.ratings.downloads = as_int(v)?,
+                12 => self.player_stats.completions = as_int(v)?,
+                13 => self.flags.is_editable = as_bool(v)?,
+                14 => self.flags.verified = as_bool(v)?,
+                15 => self.flags.uploaded = as_bool(v)?,
+                16 => self.identity.level_version = as_int(v)?,
+                17 => self.identity.game_version = as_int(v)?,
+                18 => self.player_stats.attempts = as_int(v)?,
+                19 => self.player_stats.normal_percentage = as_int(v)?,
+                20 => self.player_stats.practice_percentage = as_int(v)?,
+                21 => {
+                    // special case: GDLevelType enum
+                    self.identity.level_type = GDLevelType::from(as_int(v)?)
+                }
+                22 => self.ratings.like_rating = as_int(v)?,
+                23 => self.content.length = Length::from(as_int(v)?),
+                24 => self.ratings.dislikes = as_int(v)?,
+                25 => self.ratings.is_demon = as_bool(v)?,
+                26 => self.ratings.stars = as_int(v)?,
+                27 => self.ratings.feature_score = Some(as_int(v)?),
+                /* 28 - 32 are unused */
+                33 => self.ratings.is_auto = as_bool(v)?,
+                34 => self.integrity.replay_data = Some(as_str(v)?.as_bytes().to_vec()),
+                35 => self.flags.is_playable = as_bool(v)?,
+                36 => self.player_stats.jumps = as_int(v)?,
+                37 => self.coins.required_coins = Some(as_int(v)?),
+                38 => self.flags.is_unlocked = as_bool(v)?,
+                39 => self.meta.level_size = as_int(v)?,
+                40 => self.identity.build_version = as_int(v)?,
+                41 => self.identity.password = Some(as_int(v)?),
+                42 => self.identity.original_id = Some(as_int(v)?),
+                43 => self.content.two_player_mode = as_bool(v)?,
+                /* 44 is unused */
+                45 => self.content.custom_song_id = Some(as_int(v)?),
+                46 => self.identity.level_revision = Some(as_int(v)?),
+                47 => self.flags.has_been_modified = as_bool(v)?,
+                48 => self.content.object_count = as_int(v)?,
+                /* 49 is unused */
+                50 => self.identity.binary_version = as_int(v)?,
+                51 => self.meta.capacity_001 = as_int(v)?,
+                52 => self.meta.capacity_002 = as_int(v)?,
+                53 => self.meta.capacity_003 = as_int(v)?,
+                54 => self.meta.capacity_004 = as_int(v)?,
+                /* 55 - 59 as unused */
+                60 => self.identity.account_id = as_int(v)?,
+                61 => self.coins.coin1_acquired = as_bool(v)?,
+                62 => self.coins.coin2_acquired = as_bool(v)?,
+                63 => self.coins.coin3_acquired = as_bool(v)?,
+                64 => self.coins.total_coins = as_int(v)?,
+                65 => self.coins.coins_verified = as_bool(v)?,
+                66 => self.ratings.requested_stars = Some(as_int(v)?),
+                67 => self.meta.capacity_string = Some(as_str(v)?.to_string()),
+                68 => self.integrity.triggered_anti_cheat = as_bool(v)?,
+                69 => self.content.high_object_count = as_bool(v)?,
+                /* 70 is unused */
+                71 => self.player_stats.mana_orb_percentage = as_int(v)?,
+                72 => self.flags.has_ldm = as_bool(v)?,
+                73 => self.flags.ldm_enabled = as_bool(v)?,
+                74 => self.identity.timely_id = as_int(v)?,
+                75 => {
+                    // special case: EpicRating
+                    self.ratings.epic_rating = EpicRating::from(as_int(v)?)
+                }
+                76 => {
+                    // special case: DemonType
+                    self.ratings.demon_type = Some(DemonType::from(as_int(v)?))
+                }
+                77 => self.flags.is_gauntlet = as_bool(v)?,
+                78 => self.flags.is_alt_game = as_bool(v)?,
+                79 => self.flags.unlisted = as_bool(v)?,
+                80 => self.meta.seconds_editing = as_int(v)?,
+                81 => self.meta.seconds_editing_copies = as_int(v)?,
+                82 => self.flags.is_favourited = as_bool(v)?,
+                83 => self.meta.level_order = as_int(v)?,
+                84 => self.meta.level_folder = as_int(v)?,
+                85 => self.player_stats.clicks = as_int(v)?,
+                86 => self.player_stats.best_attempt_time = as_int(v)?,
+                87 => self.integrity.level_seed = Some(as_int(v)?),
+                88 => {
+                    // special case: comma-separated list
+                    self.player_stats.progress_diffs = parse_csv(v)?;
+                }
+                89 => self.integrity.vfd_chk = as_bool(v)?,
+                90 => self.player_stats.leaderboard_percentage = as_int(v)?,
+                91 => self.unknowns.k91 = Some(as_str(v)?.to_string()),
+                92 => self.unknowns.k92 = Some(as_int(v)?),
+                93 => self.flags.unlimited_objects = as_bool(v)?,
+                94 => self.content.is_platformer = as_bool(v)?,
+                95 => self.player_stats.verification_time = Some(as_int(v)?),
+                /* 96 - 100 are unused */
+                101 => self.unknowns.k101 = Some(as_str(v)?.to_string()),
+                /* 102 - 103 */
+                104 => {
+                    // special case: comma-separated list
+                    self.content.song_list = parse_csv(v)?;
+                }
+                105 => {
+                    // special case: comma-separated list
+                    self.content.sfx_list = Some(parse_csv(v)?);
+                }
+                106 => self.unknowns.k106 = Some(as_int(v)?),
+                107 => self.player_stats.best_time_ms = Some(as_int(v)?),
+                108 => self.player_stats.best_points = Some(as_int(v)?),
+                109 => {
+                    // special case: comma-separated list
+                    self.player_stats.local_best_times = parse_csv(v)?;
+                }
+                110 => {
+                    // special case: comma-separated list
+                    self.player_stats.local_best_points = parse_csv(v)?;
+                }
+                111 => self.integrity.platformer_seed = Some(as_int(v)?),
+                112 => self.flags.no_shake = as_bool(v)?,
+                _ => {
+                    self.unknowns.other.insert(k.to_owned(), v.clone());
                 }
-                continue;
             }
-            level.unknowns.other.insert(k.clone(), v.clone());
+            return Ok(());
         }
-
-        Some(level)
+        self.unknowns.other.insert(k.to_owned(), v.clone());
+        Ok(())
     }
 
     /// Returns this object as a `plist::Dictionary`
@@ -909,6 +1237,15 @@ impl GDLevel {
          * ints: 4, 5
          */
 
+        if self.refresh_metadata_on_export {
+            let mut level = self.clone();
+            level.refresh_metadata_on_export = false;
+            // level data that cannot be parsed is exported with the metadata it was loaded with
+            if level.refresh_metadata().is_ok() {
+                return level.to_dict();
+            }
+        }
+
         let mut d = Dictionary::new();
 
         /* common cases */
@@ -1067,7 +1404,10 @@ impl GDLevel {
         };
 
         if let Some(ref str) = self.integrity.replay_data {
-            d.insert("k34".into(), Value::String(vec_as_str(&str[..])));
+            d.insert(
+                "k34".into(),
+                Value::String(String::from_utf8_lossy(str).into_owned()),
+            );
         }
 
         if let Some(ref csv) = self.content.sfx_list {
@@ -1109,38 +1449,123 @@ impl GDLevel {
             return Ok(());
         };
 
-        self.content.data = Some(GDLevelState::Decrypted(
-            leveldata::GDLevelData::parse(raw_data).ok_or(GDError::CorruptedSavefile(
-                "Unable to parse level header".into(),
-            ))?,
-        ));
+        self.content.data = Some(GDLevelState::Decrypted(Box::new(
+            leveldata::GDLevelData::parse(raw_data).map_err(|e| e.at("k4"))?,
+        )));
         Ok(())
     }
 
-    /// Returns the decrypted level data as a `GDLevelContents` object if there is data.
-    pub fn get_decrypted_data(&self) -> Option<GDLevelData> {
-        match self.content.data.clone() {
-            Some(data) => match data {
-                GDLevelState::Encrypted(encrypted) => GDLevelData::parse(&encrypted.data),
-                GDLevelState::Decrypted(d) => Some(d), // already decrypted
-            },
-            None => None, // no level data
+    /// Decrypts the level data in place according to `options`. See [`GDLevelData::parse_with_options`].
+    ///
+    /// On error, the level data is left encrypted. Returns no diagnostics if the data was already decrypted.
+    pub fn decrypt_level_data_with_options(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<Diagnostics, GDError> {
+        let Some(GDLevelState::Encrypted(enc)) = &self.content.data else {
+            return Ok(vec![]);
+        };
+
+        let (data, diagnostics) =
+            GDLevelData::parse_with_options(&enc.data, options).map_err(|e| e.at("k4"))?;
+        self.content.data = Some(GDLevelState::Decrypted(Box::new(data)));
+        Ok(diagnostics)
+    }
+
+    /// Returns the decrypted level data as a `GDLevelData` object if there is data.
+    ///
+    /// Returns an error if the level data could not be decrypted or parsed.
+    pub fn get_decrypted_data(&self) -> Result<Option<GDLevelData>, GDError> {
+        match &self.content.data {
+            Some(GDLevelState::Encrypted(encrypted)) => GDLevelData::parse(&encrypted.data)
+                .map(Some)
+                .map_err(|e| e.at("k4")),
+            Some(GDLevelState::Decrypted(d)) => Ok(Some((**d).clone())), // already decrypted
+            None => Ok(None),                                            // no level data
         }
     }
 
-    /// Returns the decrypted level data as a `GDLevelContents` object if there is data.
-    pub fn get_decrypted_data_ref(&mut self) -> Option<&mut GDLevelData> {
-        self.decrypt_level_data().ok().unwrap();
+    /// Decrypts the level data in place and returns a mutable reference to it if there is data.
+    ///
+    /// Returns an error if the level data could not be decrypted or parsed.
+    pub fn get_decrypted_data_ref(&mut self) -> Result<Option<&mut GDLevelData>, GDError> {
+        self.decrypt_level_data()?;
         match &mut self.content.data {
-            Some(d) => {
-                if let GDLevelState::Decrypted(data) = d {
-                    Some(data)
-                } else {
-                    None
-                }
+            Some(GDLevelState::Decrypted(data)) => Ok(Some(data)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Recomputes the metadata that GD derives from the level data, so that it matches the objects after they were
+    /// edited:
+    /// - [`GDLevelContents::object_count`] (`k48`) and [`GDLevelContents::high_object_count`] (`k69`)
+    /// - [`GDLevelMeta::level_size`] (`k39`), from the length of the stored level string, or of the level data once
+    ///   it is encrypted if it was decrypted
+    /// - [`GDLevelContents::song_list`] (`k104`): the custom song, followed by the songs of every song trigger
+    /// - [`GDLevelContents::sfx_list`] (`k105`): the sound effects of every SFX trigger
+    /// - [`GDLevelCoins::total_coins`] (`k64`): the number of user coins
+    /// - [`GDLevelContents::length`] (`k23`): the length bracket of the [`Timeline::playtime`], or
+    ///   [`Length::Platformer`] for platformer levels
+    ///
+    /// [`GDLevelFlags::has_been_modified`] (`k47`) is set if any of these changed. Encrypted level data is parsed but
+    /// left encrypted. Levels without level data get an object count and size of 0.
+    ///
+    /// Returns an error if the level data could not be decrypted or parsed, in which case nothing is changed.
+    pub fn refresh_metadata(&mut self) -> Result<(), GDError> {
+        let (summary, encrypted_len, length) = match &self.content.data {
+            Some(GDLevelState::Encrypted(enc)) => {
+                let data = GDLevelData::parse(&enc.data).map_err(|e| e.at("k4"))?;
+                (
+                    ObjectSummary::of(&data.objects),
+                    // the stored level string is what gets exported as `k4`
+                    enc.data.len(),
+                    Timeline::new(&data).length(),
+                )
+            }
+            Some(GDLevelState::Decrypted(data)) => (
+                ObjectSummary::of(&data.objects),
+                encrypt_level_str(&data.serialise_to_string()).len(),
+                Timeline::new(data).length(),
+            ),
+            None => (ObjectSummary::default(), 0, Length::Tiny),
+        };
+        let length = if self.content.is_platformer {
+            Length::Platformer
+        } else {
+            length
+        };
+
+        let mut song_list = self
+            .content
+            .custom_song_id
+            .filter(|id| *id > 0)
+            .into_iter()
+            .collect::<Vec<_>>();
+        for id in summary.songs {
+            if !song_list.contains(&id) {
+                song_list.push(id);
             }
-            None => None,
         }
+        let sfx_list = (!summary.sfx.is_empty()).then_some(summary.sfx);
+        let object_count = i32::try_from(summary.count).unwrap_or(i32::MAX);
+        let level_size = (encrypted_len as f64 * 0.152).floor() as i32;
+
+        let changed = self.content.object_count != object_count
+            || self.content.song_list != song_list
+            || self.content.sfx_list != sfx_list
+            || self.meta.level_size != level_size
+            || self.coins.total_coins != summary.coins
+            || self.content.length != length;
+
+        self.content.object_count = object_count;
+        self.content.high_object_count = object_count > HIGH_OBJECT_COUNT;
+        self.content.song_list = song_list;
+        self.content.sfx_list = sfx_list;
+        self.meta.level_size = level_size;
+        self.coins.total_coins = summary.coins;
+        self.content.length = length;
+        self.flags.has_been_modified |= changed;
+        Ok(())
     }
 
     /// Adds a `GDObject` to `self.objects`
@@ -1160,7 +1585,7 @@ impl GDLevel {
         if let Some(data) = &mut self.content.data {
             match data {
                 GDLevelState::Decrypted(state) => {
-                    state.objects.extend(objects.into_iter());
+                    state.objects.extend(objects);
                 }
                 GDLevelState::Encrypted(_) => (),
             };
@@ -1266,10 +1691,52 @@ impl Default for GDLevel {
                 k101: Some("0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0".into()),
                 ..Default::default()
             },
+            refresh_metadata_on_export: false,
         }
     }
 }
 
+/// Object count above which GD flags a level as having a high object count (`k69`)
+const HIGH_OBJECT_COUNT: i32 = 40_000;
+
+/// What [`GDLevel::refresh_metadata`] needs to know about the objects of a level
+#[derive(Default)]
+struct ObjectSummary {
+    count: usize,
+    coins: i32,
+    /// Songs of song triggers, without duplicates, in order of first use
+    songs: Vec<i32>,
+    /// Sound effects of SFX triggers, without duplicates, in order of first use
+    sfx: Vec<i32>,
+}
+
+impl ObjectSummary {
+    fn of(objects: &[GDObject]) -> Self {
+        let mut summary = Self {
+            count: objects.len(),
+            ..Default::default()
+        };
+        for obj in objects {
+            let list = match obj.id {
+                USER_COIN => {
+                    summary.coins += 1;
+                    continue;
+                }
+                TRIGGER_SONG => &mut summary.songs,
+                TRIGGER_SFX => &mut summary.sfx,
+                _ => continue,
+            };
+            if let Some(GDValue::Int(id)) = obj.get_property(SONG_ID)
+                && id > 0
+                && !list.contains(&id)
+            {
+                list.push(id);
+            }
+        }
+        summary
+    }
+}
+
 /// `fields`: &[id, field]
 pub(crate) fn serialise_fields<T: Default + Clone + PartialEq>(
     d: &mut Dictionary,
@@ -1308,25 +1775,21 @@ pub(crate) fn serialise_bool_fields(d: &mut Dictionary, fields: &[(&str, bool)])
     }
 }
 
-fn to_float(v: &Value) -> Option<f32> {
-    if let Some(f) = v.as_real() {
-        Some(f as f32)
-    } else if let Some(i) = v.as_signed_integer() {
-        Some(i as f32)
-    } else {
-        None
+pub(crate) fn parse_csv(v: &Value) -> Result<Vec<i32>, GDError> {
+    let s = as_str(v)?;
+    if s.is_empty() {
+        return Ok(vec![]);
     }
+    s.split(',')
+        .enumerate()
+        .map(|(idx, d)| {
+            parse_str::<i32>(d, "comma-separated list of integers")
+                .map_err(|e| e.at(&idx.to_string()))
+        })
+        .collect()
 }
 
-pub(crate) fn parse_csv(v: &Value) -> Option<Vec<i32>> {
-    v.as_string()
-        .unwrap()
-        .split(",")
-        .map(|d| d.parse::<i32>().ok())
-        .collect::<Option<Vec<i32>>>()
-}
-
-pub(crate) fn to_csv<T: ToString>(v: &Vec<T>) -> String {
+pub(crate) fn to_csv<T: ToString>(v: &[T]) -> String {
     v.iter()
         .map(|i| i.to_string())
         .collect::<Vec<_>>()
@@ -0,0 +1,235 @@
+//! This module contains [`Palette`], which edits the colour channels of a level header (`kS38`) by [`ColourChannel`].
+
+use std::fmt::Display;
+
+use crate::cclocallevels::{
+    gdlevel::{
+        header::LevelHeaderBuilder,
+        leveldata::{ColourString, GDLevelHeader, PlayerColour},
+    },
+    gdobj::structs::{Colour, ColourChannel, HSVColour},
+};
+
+/// The colour channels of a level.
+///
+/// Channels are kept in the order they were read in; new channels are appended. Reading a channel that is not in the
+/// palette returns `None`, while setting one adds it with full opacity and no blending.
+///
+/// ```
+/// use gdlib::cclocallevels::{
+///     gdlevel::{header::LevelHeaderBuilder, palette::Palette},
+///     gdobj::structs::{Colour, ColourChannel},
+/// };
+///
+/// let mut header = LevelHeaderBuilder::new().build();
+/// let mut palette = Palette::from_header(&header);
+/// palette.set_colour(ColourChannel::Background, Colour::from_rgb((10, 0, 40)));
+/// palette.set_opacity(ColourChannel::Channel(12), 0.5);
+/// palette.write_to(&mut header);
+///
+/// let palette = Palette::from_header(&header);
+/// assert_eq!(palette.opacity(ColourChannel::Channel(12)), Some(0.5));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Palette {
+    channels: Vec<ColourString>,
+}
+
+impl Palette {
+    /// Reads the colour channels of `header`. Headers without colours give an empty palette.
+    #[must_use]
+    pub fn from_header(header: &GDLevelHeader) -> Self {
+        Self {
+            channels: header.colours().unwrap_or_default().to_vec(),
+        }
+    }
+
+    /// Parses the value of `kS38`. Returns `None` if a channel cannot be parsed.
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        s.split('|')
+            .filter(|s| !s.is_empty())
+            .map(ColourString::parse)
+            .collect::<Option<Vec<_>>>()
+            .map(|channels| Self { channels })
+    }
+
+    /// Writes this palette to the colours of `header`.
+    pub fn write_to(&self, header: &mut GDLevelHeader) {
+        header.set_colours(self.channels.clone());
+    }
+
+    /// Adds the channels that every level has (BG, G1, G2, Line, MG, MG2, P1, P2 and 3DL) with the colours of a new
+    /// level, if they are missing. Channels that are already present are left untouched.
+    pub fn fill_defaults(&mut self) {
+        let defaults = LevelHeaderBuilder::new().build();
+        let defaults = defaults
+            .colours()
+            .unwrap_or_default()
+            .iter()
+            .cloned()
+            // a new level has no 3DL channel, but GD shows it as white
+            .chain([new_channel(ColourChannel::ThreeDLine)]);
+
+        for default in defaults {
+            if !self
+                .channels
+                .iter()
+                .any(|c| c.colour_ch_idx == default.colour_ch_idx)
+            {
+                self.channels.push(default);
+            }
+        }
+    }
+
+    /// Returns the channels in this palette.
+    #[must_use]
+    pub fn channels(&self) -> &[ColourString] {
+        &self.channels
+    }
+
+    /// Returns a channel, if it is in this palette.
+    #[must_use]
+    pub fn channel(&self, channel: ColourChannel) -> Option<&ColourString> {
+        let idx = channel_idx(channel);
+        self.channels.iter().find(|c| c.colour_ch_idx == idx)
+    }
+
+    /// Returns a channel mutably, adding it if it is not in this palette.
+    pub fn channel_mut(&mut self, channel: ColourChannel) -> &mut ColourString {
+        let idx = channel_idx(channel);
+        let pos = match self.channels.iter().position(|c| c.colour_ch_idx == idx) {
+            Some(pos) => pos,
+            None => {
+                self.channels.push(new_channel(channel));
+                self.channels.len() - 1
+            }
+        };
+        &mut self.channels[pos]
+    }
+
+    /// Removes a channel and returns it, if it was in this palette.
+    pub fn remove(&mut self, channel: ColourChannel) -> Option<ColourString> {
+        let idx = channel_idx(channel);
+        let pos = self.channels.iter().position(|c| c.colour_ch_idx == idx)?;
+        Some(self.channels.remove(pos))
+    }
+
+    /// Returns the colour of a channel.
+    #[must_use]
+    pub fn colour(&self, channel: ColourChannel) -> Option<Colour> {
+        self.channel(channel).map(|c| c.from)
+    }
+
+    /// Sets the colour of a channel, both what it starts as (`from`) and what it changes to (`to`).
+    pub fn set_colour(&mut self, channel: ColourChannel, colour: Colour) {
+        let c = self.channel_mut(channel);
+        c.from = colour;
+        c.to = colour;
+    }
+
+    /// Returns the opacity of a channel, from 0 to 1.
+    #[must_use]
+    pub fn opacity(&self, channel: ColourChannel) -> Option<f32> {
+        self.channel(channel).map(|c| c.from_opacity)
+    }
+
+    /// Sets the opacity of a channel, from 0 to 1, both what it starts as and what it changes to.
+    pub fn set_opacity(&mut self, channel: ColourChannel, opacity: f32) {
+        let c = self.channel_mut(channel);
+        c.from_opacity = opacity.clamp(0.0, 1.0);
+        c.to_opacity = c.from_opacity;
+    }
+
+    /// Returns whether a channel uses additive blending.
+    #[must_use]
+    pub fn blending(&self, channel: ColourChannel) -> Option<bool> {
+        self.channel(channel).map(|c| c.blending)
+    }
+
+    /// Sets whether a channel uses additive blending.
+    pub fn set_blending(&mut self, channel: ColourChannel, blending: bool) {
+        self.channel_mut(channel).blending = blending;
+    }
+
+    /// Returns the player colour that a channel copies.
+    #[must_use]
+    pub fn player_colour(&self, channel: ColourChannel) -> Option<PlayerColour> {
+        self.channel(channel).map(|c| c.player_colour)
+    }
+
+    /// Makes a channel copy a player colour, or stop copying with [`PlayerColour::None`].
+    /// Copying a player colour stops copying another channel.
+    pub fn set_player_colour(&mut self, channel: ColourChannel, player_colour: PlayerColour) {
+        let c = self.channel_mut(channel);
+        c.player_colour = player_colour;
+        if player_colour != PlayerColour::None {
+            c.inherited_col_ch_idx = 0;
+            c.copied_hsv = None;
+        }
+    }
+
+    /// Returns the channel that a channel copies, with the HSV adjustment applied to it, if it copies one.
+    #[must_use]
+    pub fn copied_colour(
+        &self,
+        channel: ColourChannel,
+    ) -> Option<(ColourChannel, Option<&HSVColour>)> {
+        let c = self.channel(channel)?;
+        let source = i16::try_from(c.inherited_col_ch_idx).ok()?;
+        (source != 0).then(|| (ColourChannel::from(source), c.copied_hsv.as_ref()))
+    }
+
+    /// Makes a channel copy the colour of `source`, adjusted by `hsv`. Copying a channel stops copying a player colour.
+    pub fn set_copied_colour(
+        &mut self,
+        channel: ColourChannel,
+        source: ColourChannel,
+        hsv: Option<HSVColour>,
+    ) {
+        let c = self.channel_mut(channel);
+        c.inherited_col_ch_idx = channel_idx(source);
+        c.copied_hsv = hsv;
+        c.player_colour = PlayerColour::None;
+    }
+
+    /// Makes a channel stop copying another channel.
+    pub fn clear_copied_colour(&mut self, channel: ColourChannel) {
+        let idx = channel_idx(channel);
+        if let Some(c) = self.channels.iter_mut().find(|c| c.colour_ch_idx == idx) {
+            c.inherited_col_ch_idx = 0;
+            c.copied_hsv = None;
+        }
+    }
+}
+
+// serialises to the value of `kS38`
+impl Display for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, channel) in self.channels.iter().enumerate() {
+            if idx != 0 {
+                f.write_str("|")?;
+            }
+            f.write_str(&channel.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+fn channel_idx(channel: ColourChannel) -> i32 {
+    i32::from(i16::from(channel))
+}
+
+/// A channel as GD adds it: white, fully opaque and without blending
+fn new_channel(channel: ColourChannel) -> ColourString {
+    let white = Colour::from_rgb((255, 255, 255));
+    ColourString {
+        from: white,
+        to: white,
+        colour_ch_idx: channel_idx(channel),
+        from_opacity: 1.0,
+        to_opacity: 1.0,
+        opacity_toggled: true,
+        ..Default::default()
+    }
+}
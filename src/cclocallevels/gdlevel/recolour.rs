@@ -0,0 +1,404 @@
+//! This module contains [`Recolour`], which swaps the colour scheme of a level, and the report of what it changes.
+//!
+//! A recolour applies to every place that a level sets a colour:
+//! * the colour channels of the header (`kS38`)
+//! * colour triggers and pulse triggers that set an RGB colour
+//! * the main and detail HSV overrides of objects
+//!
+//! Channels, triggers and HSV adjustments that copy another colour are left alone, because they follow the colour
+//! they copy. An HSV override is only rewritten when the colour that it produces is recoloured, so that the object
+//! ends up with the new colour even though its channel changed.
+
+use std::fmt::Display;
+
+use crate::cclocallevels::{
+    gdlevel::{
+        leveldata::{GDLevelData, PlayerColour},
+        palette::Palette,
+    },
+    gdobj::{
+        GDObject,
+        ids::{
+            objects::{TRIGGER_COLOUR, TRIGGER_PULSE},
+            properties::{
+                BLUE, COPY_COLOUR_FROM_CHANNEL, DETAIL_HSV, DETAIL_HSV_ENABLED, GREEN,
+                HSV_IS_ENABLED, MAIN_HSV, MAIN_HSV_ENABLED, RED, USING_PLAYER_COLOUR_1,
+                USING_PLAYER_COLOUR_2,
+            },
+        },
+        structs::{Colour, ColourChannel, GDValue, HSVColour},
+    },
+};
+
+/// Highest saturation or brightness multiplier of an HSV adjustment
+const MAX_HSV_MULT: f64 = 2.0;
+
+/// A change of colour scheme.
+///
+/// ```
+/// use gdlib::cclocallevels::{
+///     gdlevel::{header::LevelHeaderBuilder, leveldata::GDLevelData, palette::Palette, recolour::Recolour},
+///     gdobj::structs::{Colour, ColourChannel},
+/// };
+///
+/// let mut header = LevelHeaderBuilder::new().build();
+/// let mut palette = Palette::from_header(&header);
+/// palette.set_colour(ColourChannel::Channel(1), Colour::from_rgb((255, 0, 0)));
+/// palette.write_to(&mut header);
+/// let mut data = GDLevelData { headers: header, objects: vec![] };
+///
+/// let recolour = Recolour::Map(vec![(Colour::from_rgb((255, 0, 0)), Colour::from_rgb((0, 0, 255)))]);
+/// let report = data.recolour(&recolour);
+/// assert_eq!(report.changes.len(), 1);
+///
+/// let palette = Palette::from_header(&data.headers);
+/// assert_eq!(palette.colour(ColourChannel::Channel(1)), Some(Colour::from_rgb((0, 0, 255))));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recolour {
+    /// Replaces every colour that is exactly the first colour of a pair with the second colour.
+    /// Colours that are not in the map are left alone.
+    Map(Vec<(Colour, Colour)>),
+    /// Adjusts every colour by an HSV adjustment, the same way GD applies one: the hue is rotated by
+    /// `hue_shift` degrees, and saturation and brightness are multiplied, or offset if the static option is set.
+    HSV(HSVColour),
+}
+
+impl Recolour {
+    /// Returns the colour that `colour` becomes, or `None` if this recolour leaves it as it is.
+    #[must_use]
+    pub fn apply(&self, colour: Colour) -> Option<Colour> {
+        let new = match self {
+            Self::Map(map) => map.iter().find(|(old, _)| *old == colour)?.1,
+            Self::HSV(hsv) => apply_hsv(colour, hsv),
+        };
+        (new != colour).then_some(new)
+    }
+}
+
+/// What a recolour changes, or would change.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecolourReport {
+    /// Every change, in the order of the header channels followed by the objects
+    pub changes: Vec<RecolourChange>,
+}
+
+impl RecolourReport {
+    /// Returns whether nothing is recoloured.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// One colour that is recoloured.
+///
+/// For HSV overrides, `old` and `new` are the colours that the object shows, i.e. its channel with the HSV
+/// adjustment applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecolourChange {
+    /// Where the colour is set
+    pub target: RecolourTarget,
+    /// Colour before the recolour
+    pub old: Colour,
+    /// Colour after the recolour
+    pub new: Colour,
+}
+
+/// Where a recoloured colour is set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecolourTarget {
+    /// Colour channel in the level header
+    Channel(ColourChannel),
+    /// Colour trigger, by its index in [`GDLevelData::objects`]
+    ColourTrigger(usize),
+    /// Pulse trigger, by its index in [`GDLevelData::objects`]
+    PulseTrigger(usize),
+    /// HSV override of an object, by its index in [`GDLevelData::objects`]
+    HSVOverride {
+        /// Index of the object
+        index: usize,
+        /// Whether this is the override of the detail colour rather than the main colour
+        detail: bool,
+        /// Override before the recolour
+        old: HSVColour,
+        /// Override after the recolour
+        new: HSVColour,
+    },
+}
+
+impl GDLevelData {
+    /// Recolours this level and returns what was changed. See [`Recolour`].
+    pub fn recolour(&mut self, recolour: &Recolour) -> RecolourReport {
+        let report = self.recolour_dry_run(recolour);
+
+        let mut palette = Palette::from_header(&self.headers);
+        for change in &report.changes {
+            match &change.target {
+                RecolourTarget::Channel(channel) => palette.set_colour(*channel, change.new),
+                RecolourTarget::ColourTrigger(index) | RecolourTarget::PulseTrigger(index) => {
+                    let object = &mut self.objects[*index];
+                    object.set_property(RED, GDValue::Int(change.new.red.into()));
+                    object.set_property(GREEN, GDValue::Int(change.new.green.into()));
+                    object.set_property(BLUE, GDValue::Int(change.new.blue.into()));
+                }
+                RecolourTarget::HSVOverride {
+                    index, detail, new, ..
+                } => {
+                    let key = if *detail { DETAIL_HSV } else { MAIN_HSV };
+                    self.objects[*index].set_property(key, GDValue::String(new.to_string()));
+                }
+            }
+        }
+        if report
+            .changes
+            .iter()
+            .any(|c| matches!(c.target, RecolourTarget::Channel(_)))
+        {
+            palette.write_to(&mut self.headers);
+        }
+        report
+    }
+
+    /// Returns what [`GDLevelData::recolour`] would change, without changing anything.
+    #[must_use]
+    pub fn recolour_dry_run(&self, recolour: &Recolour) -> RecolourReport {
+        let palette = Palette::from_header(&self.headers);
+        let mut changes = vec![];
+
+        for channel in palette.channels() {
+            if channel.player_colour != PlayerColour::None || channel.inherited_col_ch_idx != 0 {
+                continue;
+            }
+            if let Some(new) = recolour.apply(channel.from) {
+                changes.push(RecolourChange {
+                    target: RecolourTarget::Channel(ColourChannel::from(
+                        channel.colour_ch_idx as i16,
+                    )),
+                    old: channel.from,
+                    new,
+                });
+            }
+        }
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let target = match object.id {
+                TRIGGER_COLOUR => RecolourTarget::ColourTrigger(index),
+                TRIGGER_PULSE => RecolourTarget::PulseTrigger(index),
+                _ => {
+                    changes.extend(hsv_changes(object, index, &palette, recolour));
+                    continue;
+                }
+            };
+            if let Some(old) = trigger_colour(object)
+                && let Some(new) = recolour.apply(old)
+            {
+                changes.push(RecolourChange { target, old, new });
+            }
+        }
+
+        RecolourReport { changes }
+    }
+}
+
+impl Display for RecolourReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            let (old, new) = (hex(change.old), hex(change.new));
+            match &change.target {
+                RecolourTarget::Channel(channel) => {
+                    writeln!(f, "colour channel {}: {old} -> {new}", i16::from(*channel))?
+                }
+                RecolourTarget::ColourTrigger(index) => {
+                    writeln!(f, "colour trigger (object {index}): {old} -> {new}")?;
+                }
+                RecolourTarget::PulseTrigger(index) => {
+                    writeln!(f, "pulse trigger (object {index}): {old} -> {new}")?;
+                }
+                RecolourTarget::HSVOverride {
+                    index,
+                    detail,
+                    old: old_hsv,
+                    new: new_hsv,
+                } => writeln!(
+                    f,
+                    "{} HSV of object {index}: {old} -> {new} ({old_hsv} -> {new_hsv})",
+                    if *detail { "detail" } else { "main" }
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The RGB colour that a colour or pulse trigger sets, if it sets one rather than copying a colour
+fn trigger_colour(object: &GDObject) -> Option<Colour> {
+    let is_set = |key| match object.get_property(key) {
+        Some(GDValue::Bool(b)) => b,
+        Some(GDValue::ColourChannel(c)) => i16::from(c) != 0,
+        _ => false,
+    };
+    if [
+        USING_PLAYER_COLOUR_1,
+        USING_PLAYER_COLOUR_2,
+        HSV_IS_ENABLED,
+        COPY_COLOUR_FROM_CHANNEL,
+    ]
+    .into_iter()
+    .any(is_set)
+    {
+        return None;
+    }
+
+    // GD leaves out components that are 255
+    let component = |key| match object.get_property(key) {
+        Some(GDValue::Int(n)) => n.clamp(0, 255) as u8,
+        _ => 255,
+    };
+    Some(Colour::from_rgb((
+        component(RED),
+        component(GREEN),
+        component(BLUE),
+    )))
+}
+
+fn hsv_changes(
+    object: &GDObject,
+    index: usize,
+    palette: &Palette,
+    recolour: &Recolour,
+) -> Vec<RecolourChange> {
+    let mut changes = vec![];
+    for (detail, enabled, key, channel) in [
+        (
+            false,
+            MAIN_HSV_ENABLED,
+            MAIN_HSV,
+            object.config.colour_channels.0,
+        ),
+        (
+            true,
+            DETAIL_HSV_ENABLED,
+            DETAIL_HSV,
+            object.config.colour_channels.1,
+        ),
+    ] {
+        if object.get_property(enabled) != Some(GDValue::Bool(true)) {
+            continue;
+        }
+        let Some(GDValue::String(hsv)) = object.get_property(key) else {
+            continue;
+        };
+        let (Some(hsv), Some(base)) = (HSVColour::parse(&hsv), channel_base(palette, channel))
+        else {
+            continue;
+        };
+
+        let old = apply_hsv(base, &hsv);
+        let Some(new) = recolour.apply(old) else {
+            continue;
+        };
+        let new_base = recolour.apply(base).unwrap_or(base);
+        if apply_hsv(new_base, &hsv) == new {
+            continue;
+        }
+
+        changes.push(RecolourChange {
+            target: RecolourTarget::HSVOverride {
+                index,
+                detail,
+                new: solve_hsv(new_base, new, &hsv),
+                old: hsv,
+            },
+            old,
+            new,
+        });
+    }
+    changes
+}
+
+/// Colour of a channel that sets its own colour, rather than copying another one
+fn channel_base(palette: &Palette, channel: ColourChannel) -> Option<Colour> {
+    let c = palette.channel(channel)?;
+    (c.player_colour == PlayerColour::None && c.inherited_col_ch_idx == 0).then_some(c.from)
+}
+
+fn hex(c: Colour) -> String {
+    format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue)
+}
+
+/// Applies an HSV adjustment to a colour
+fn apply_hsv(colour: Colour, hsv: &HSVColour) -> Colour {
+    let (h, s, v) = to_hsv(colour);
+    let adjust =
+        |x: f64, by: f64, offset: bool| if offset { x + by } else { x * by }.clamp(0.0, 1.0);
+    from_hsv(
+        h + f64::from(hsv.hue_shift),
+        adjust(s, hsv.saturation_mult, hsv.static_sat_scalar),
+        adjust(v, hsv.brightness_mult, hsv.static_bright_scalar),
+    )
+}
+
+/// Returns the HSV adjustment that turns `base` into `target`, keeping to multipliers where `template` does and
+/// they can reach the target
+fn solve_hsv(base: Colour, target: Colour, template: &HSVColour) -> HSVColour {
+    let (base_h, base_s, base_v) = to_hsv(base);
+    let (target_h, target_s, target_v) = to_hsv(target);
+    let solve = |from: f64, to: f64, offset: bool| {
+        let round = |x: f64| (x * 1000.0).round() / 1000.0;
+        if !offset && from > 0.0 && to / from <= MAX_HSV_MULT {
+            (round(to / from), false)
+        } else {
+            (round(to - from), true)
+        }
+    };
+
+    let (saturation_mult, static_sat_scalar) = solve(base_s, target_s, template.static_sat_scalar);
+    let (brightness_mult, static_bright_scalar) =
+        solve(base_v, target_v, template.static_bright_scalar);
+    // hue shifts are whole degrees from -180 to 180
+    let hue_shift = (target_h - base_h).round() as i32;
+    HSVColour {
+        hue_shift: (hue_shift + 540) % 360 - 180,
+        saturation_mult,
+        brightness_mult,
+        static_sat_scalar,
+        static_bright_scalar,
+    }
+}
+
+/// Hue in degrees, saturation and value from 0 to 1
+fn to_hsv(c: Colour) -> (f64, f64, f64) {
+    let [r, g, b] = [c.red, c.green, c.blue].map(|x| f64::from(x) / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+    (h, s, max)
+}
+
+fn from_hsv(h: f64, s: f64, v: f64) -> Colour {
+    let h = h.rem_euclid(360.0);
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match h {
+        h if h < 60.0 => (c, x, 0.0),
+        h if h < 120.0 => (x, c, 0.0),
+        h if h < 180.0 => (0.0, c, x),
+        h if h < 240.0 => (0.0, x, c),
+        h if h < 300.0 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_u8 = |x: f64| ((x + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    Colour::from_rgb((to_u8(r), to_u8(g), to_u8(b)))
+}
@@ -0,0 +1,170 @@
+//! This module contains [`Timeline`], which converts between x positions and time in a level.
+
+use crate::cclocallevels::{
+    gdlevel::{enums::Length, leveldata::GDLevelData},
+    gdobj::{
+        GDObject,
+        ids::{
+            objects::{
+                SPEED_PORTAL_1X, SPEED_PORTAL_2X, SPEED_PORTAL_3X, SPEED_PORTAL_4X,
+                SPEED_PORTAL_05X, START_POS,
+            },
+            properties::STARTING_SPEED,
+        },
+        structs::{GDValue, Speed},
+    },
+};
+
+/// Longest playtime of each length bracket, in seconds
+const LENGTHS: [(f64, Length); 4] = [
+    (10.0, Length::Tiny),
+    (30.0, Length::Short),
+    (60.0, Length::Medium),
+    (120.0, Length::Long),
+];
+
+/// Conversion between x positions and seconds since the player started, following the speed portals of a level.
+///
+/// Speed portals take effect at their x position. Positions before the start are extrapolated at the starting speed,
+/// and give negative times.
+///
+/// ```
+/// use gdlib::cclocallevels::{
+///     gdlevel::{leveldata::GDLevelData, header::LevelHeaderBuilder, timeline::Timeline},
+///     gdobj::{GDObject, structs::Speed},
+/// };
+///
+/// let data = GDLevelData {
+///     headers: LevelHeaderBuilder::new().speed(Speed::X2).build(),
+///     objects: vec![GDObject::parse_str("1,1,2,774.84,3,15")],
+/// };
+/// let timeline = Timeline::new(&data);
+/// assert!((timeline.time_at(774.84) - 2.0).abs() < 1e-9);
+/// assert!((timeline.x_at(1.0) - 387.42).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    /// Stretches of constant speed, sorted by x; the first one is at the start
+    segments: Vec<Segment>,
+    /// x position of the rightmost object
+    end: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    x: f64,
+    time: f64,
+    speed: Speed,
+}
+
+impl Timeline {
+    /// Creates the timeline of a level that is played from the start, at the starting speed of its header (`kA4`).
+    #[must_use]
+    pub fn new(data: &GDLevelData) -> Self {
+        Self::starting_at(data, 0.0, data.headers.speed())
+    }
+
+    /// Creates the timeline of a level that is played from a start position, at the speed of that start position.
+    /// Returns `None` if `start_pos` is not a start position.
+    #[must_use]
+    pub fn from_start_pos(data: &GDLevelData, start_pos: &GDObject) -> Option<Self> {
+        if start_pos.id != START_POS {
+            return None;
+        }
+        let speed = match start_pos.get_property(STARTING_SPEED) {
+            Some(GDValue::Int(speed)) => Speed::try_from(speed).unwrap_or_default(),
+            _ => Speed::default(),
+        };
+        Some(Self::starting_at(data, start_pos.config.pos.0, speed))
+    }
+
+    /// Creates the timeline of a level that is played from `x` at `speed`. Speed portals before `x` are ignored.
+    #[must_use]
+    pub fn starting_at(data: &GDLevelData, x: f64, speed: Speed) -> Self {
+        let mut portals = data
+            .objects
+            .iter()
+            .filter_map(|obj| Some((obj.config.pos.0, portal_speed(obj.id)?)))
+            .filter(|(portal_x, _)| *portal_x >= x)
+            .collect::<Vec<_>>();
+        // stable, so the last of several portals at the same x wins
+        portals.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut segments = vec![Segment {
+            x,
+            time: 0.0,
+            speed,
+        }];
+        for (portal_x, speed) in portals {
+            let last = segments.last_mut().expect("timeline starts with a segment");
+            if portal_x == last.x {
+                last.speed = speed;
+                continue;
+            }
+            let time = last.time + (portal_x - last.x) / last.speed.units_per_second();
+            segments.push(Segment {
+                x: portal_x,
+                time,
+                speed,
+            });
+        }
+
+        let end = data
+            .objects
+            .iter()
+            .map(|obj| obj.config.pos.0)
+            .fold(x, f64::max);
+        Self { segments, end }
+    }
+
+    /// Returns the time in seconds at which the player reaches `x`.
+    #[must_use]
+    pub fn time_at(&self, x: f64) -> f64 {
+        let idx = self.segments.partition_point(|s| s.x <= x);
+        let segment = &self.segments[idx.saturating_sub(1)];
+        segment.time + (x - segment.x) / segment.speed.units_per_second()
+    }
+
+    /// Returns the x position that the player reaches after `time` seconds.
+    #[must_use]
+    pub fn x_at(&self, time: f64) -> f64 {
+        let idx = self.segments.partition_point(|s| s.time <= time);
+        let segment = &self.segments[idx.saturating_sub(1)];
+        segment.x + (time - segment.time) * segment.speed.units_per_second()
+    }
+
+    /// Returns the speed of the player at `x`.
+    #[must_use]
+    pub fn speed_at(&self, x: f64) -> Speed {
+        let idx = self.segments.partition_point(|s| s.x <= x);
+        self.segments[idx.saturating_sub(1)].speed
+    }
+
+    /// Returns the time in seconds that it takes to reach the rightmost object.
+    #[must_use]
+    pub fn playtime(&self) -> f64 {
+        self.time_at(self.end)
+    }
+
+    /// Returns the length bracket (`k23`) of [`Timeline::playtime`]. This is not meaningful for platformer levels,
+    /// which are always [`Length::Platformer`].
+    #[must_use]
+    pub fn length(&self) -> Length {
+        let playtime = self.playtime();
+        LENGTHS
+            .iter()
+            .find(|(max, _)| playtime < *max)
+            .map_or(Length::XL, |(_, length)| *length)
+    }
+}
+
+fn portal_speed(id: i32) -> Option<Speed> {
+    match id {
+        SPEED_PORTAL_05X => Some(Speed::X0Point5),
+        SPEED_PORTAL_1X => Some(Speed::X1),
+        SPEED_PORTAL_2X => Some(Speed::X2),
+        SPEED_PORTAL_3X => Some(Speed::X3),
+        SPEED_PORTAL_4X => Some(Speed::X4),
+        _ => None,
+    }
+}
@@ -3,7 +3,7 @@
 use std::{
     collections::HashMap,
     fs::{read, write},
-    io::Cursor,
+    ops::Range,
     path::PathBuf,
 };
 
@@ -17,10 +17,11 @@ use crate::{
         parse_csv, serialise_bool_fields, serialise_fields, serialise_optional_fields, to_csv,
     },
     core::{
-        GDError, b64_decode, b64_encode,
+        GDError, b64_decode, b64_encode, gdplist,
         io::{stringify_xml, vec_as_str},
-        proper_plist_tags,
+        parse::PlistParser,
         structs::KCEKValue,
+        values::{as_bool, as_dict, as_int, as_str, describe, invalid},
     },
 };
 
@@ -174,91 +175,101 @@ impl Default for GDList {
 
 impl GDList {
     /// Parses a plist dictionary a GD list object
-    pub fn from_dictionary(d: &Dictionary) -> Option<Self> {
+    pub fn from_dictionary(d: &Dictionary) -> Result<Self, GDError> {
         /* Input dict structure
          * kXX: regular list values
          * k97: dict of levels: {"id": level, ...}
          */
 
         let mut list = Self::default();
-
         for (k, v) in d {
-            if k == "kCEK" {
-                list.kcek = KCEKValue::try_from(v.as_signed_integer().unwrap() as i32).unwrap();
-                continue;
-            }
+            list.parse_key(k, v).map_err(|e| e.at(k))?;
+        }
+        Ok(list)
+    }
 
-            let key_id = match k[1..].parse::<i32>() {
-                Ok(n) => n,
-                Err(_) => {
-                    list.other_properties.insert(k.clone(), v.clone());
-                    continue;
-                }
-            };
+    /// Parses the dictionary spanning `value` in a document of GD's plist dialect into a GDList object.
+    pub(crate) fn from_gdplist(xml: &str, value: Range<usize>) -> Result<Self, GDError> {
+        let mut list = Self::default();
+        gdplist::for_each_entry(xml, value, |k, v| {
+            list.parse_key(k, &v).map_err(|e| e.at(k))
+        })?;
+        Ok(list)
+    }
 
-            match key_id {
-                1 => list.id = v.as_signed_integer()? as i32,
-                2 => list.name = v.as_string()?.to_owned(),
-                3 => {
-                    // special case: b64 encoded string
-                    list.description = vec_as_str(&b64_decode(v.as_string()?).ok()?[..])
-                }
-                5 => list.creator = v.as_string()?.to_owned(),
-                7 => {
-                    // special case: difficulty
-                    list.difficulty =
-                        GDListDifficulty::try_from(v.as_signed_integer()? as i32).ok()?
-                }
-                11 => list.downloads = v.as_signed_integer()? as i32,
-                15 => list.is_uploaded = v.as_boolean()?,
-                16 => list.version = v.as_signed_integer()? as i32,
-                21 => {
-                    // special case: list type
-                    list.list_type = GDListType::try_from(v.as_signed_integer()? as i32).ok()?
-                }
-                22 => list.likes = v.as_signed_integer()? as i32,
-                25 => list.is_demon = v.as_boolean()?,
-                26 => list.stars = v.as_signed_integer()? as i32,
-                27 => list.featured = v.as_boolean()?,
-                42 => list.original = Some(v.as_signed_integer()? as i32),
-                46 => list.list_revision = v.as_signed_integer()? as i32,
-                60 => list.account_id = v.as_signed_integer()? as i32,
-                79 => list.unlisted = v.as_boolean()?,
-                82 => list.favourited = v.as_boolean()?,
-                83 => list.order = v.as_signed_integer()? as i32,
-                96 => {
-                    // special case: comma-separated list of ids
-                    list.level_ids = parse_csv(v)?
-                }
-                97 => {
-                    // special case: levels dictionary
-                    // omit id (key) since it is found in the level anyway
-                    list.levels = v
-                        .as_dictionary()
-                        .unwrap()
-                        .iter()
-                        .map(|(_id, level_dict)| {
-                            match GDLevel::from_dict(level_dict.as_dictionary().unwrap()) {
-                                Some(l) => l,
-                                None => {
-                                    panic!("couldnt parse {level_dict:#?}")
-                                }
-                            }
-                        })
-                        .collect::<Vec<GDLevel>>();
-                }
-                98 => list.upload_time = v.as_signed_integer()? as i32,
-                99 => list.update_time = Some(v.as_signed_integer()? as i32),
-                113 => list.diamond_reward = v.as_signed_integer()? as i32,
-                114 => list.required_levels = v.as_signed_integer()? as i32,
+    fn parse_key(&mut self, k: &str, v: &Value) -> Result<(), GDError> {
+        if k == "kCEK" {
+            self.kcek = KCEKValue::from(as_int(v)?);
+            return Ok(());
+        }
 
-                _ => {
-                    list.other_properties.insert(k.clone(), v.clone());
-                }
+        let key_id = match k.get(1..).map(str::parse::<i32>) {
+            Some(Ok(n)) => n,
+            _ => {
+                self.other_properties.insert(k.to_owned(), v.clone());
+                return Ok(());
             }
-        }
+        };
 
-        Some(list)
+        match key_id {
+            1 => self.id = as_int(v)?,
+            2 => self.name = as_str(v)?.to_owned(),
+            3 => {
+                // special case: b64 encoded string
+                self.description = vec_as_str(
+                    &b64_decode(as_str(v)?)
+                        .map_err(|_| invalid("base64-encoded string", describe(v)))?,
+                )?
+            }
+            5 => self.creator = as_str(v)?.to_owned(),
+            7 => {
+                // special case: difficulty
+                self.difficulty = GDListDifficulty::from(as_int(v)?)
+            }
+            11 => self.downloads = as_int(v)?,
+            15 => self.is_uploaded = as_bool(v)?,
+            16 => self.version = as_int(v)?,
+            21 => {
+                // special case: list type
+                self.list_type = GDListType::from(as_int(v)?)
+            }
+            22 => self.likes = as_int(v)?,
+            25 => self.is_demon = as_bool(v)?,
+            26 => self.stars = as_int(v)?,
+            27 => self.featured = as_bool(v)?,
+            42 => self.original = Some(as_int(v)?),
+            46 => self.list_revision = as_int(v)?,
+            60 => self.account_id = as_int(v)?,
+            79 => self.unlisted = as_bool(v)?,
+            82 => self.favourited = as_bool(v)?,
+            83 => self.order = as_int(v)?,
+            96 => {
+                // special case: comma-separated list of ids
+                self.level_ids = parse_csv(v)?
+            }
+            97 => {
+                // special case: levels dictionary
+                // omit id (key) since it is found in the level anyway
+                self.levels = as_dict(v)?
+                    .iter()
+                    .map(|(id, level_dict)| {
+                        as_dict(level_dict)
+                            .and_then(GDLevel::from_dict)
+                            .map_err(|e| e.at(id))
+                    })
+                    .collect::<Result<Vec<GDLevel>, GDError>>()
+                    .map_err(|e| e.at("k97"))?;
+            }
+            98 => self.upload_time = as_int(v)?,
+            99 => self.update_time = Some(as_int(v)?),
+            113 => self.diamond_reward = as_int(v)?,
+            114 => self.required_levels = as_int(v)?,
+
+            _ => {
+                self.other_properties.insert(k.to_owned(), v.clone());
+            }
+        }
+        Ok(())
     }
 
     /// Serialises this object into a [`plist::Dictionary`]
@@ -342,13 +353,12 @@ impl GDList {
 
     /// Parses a .gmd file to a `Self` object
     pub fn from_gmdl<T: Into<PathBuf>>(path: T) -> Result<Self, GDError> {
-        let file = proper_plist_tags(vec_as_str(&read(path.into())?))?;
-        let xmltree = Value::from_reader_xml(Cursor::new(file.as_bytes()))?;
-
-        Ok(
-            Self::from_dictionary(xmltree.as_dictionary().unwrap()).ok_or(
-                GDError::CorruptedSavefile("Unable to parse file to valid level.".into()),
-            )?,
+        let file = vec_as_str(&read(path.into())?)?;
+        gdplist::parse_with_fallback(
+            &file,
+            PlistParser::Native,
+            |xml| Self::from_gdplist(xml, gdplist::root_value(xml)?),
+            |root| Self::from_dictionary(as_dict(&root)?),
         )
     }
 
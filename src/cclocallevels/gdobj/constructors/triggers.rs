@@ -850,6 +850,7 @@ pub fn random_trigger(
 /// * `reset_remap`: Resets the remapping of group IDs
 /// * `spawn_ordered`: Spawns constituents of group in the order of x-position
 /// * `preview_disable`: prevents the trigger's resulting spawns from being rendered in editor preview
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_trigger(
     config: &GDObjConfig,
     spawn_id: i16,
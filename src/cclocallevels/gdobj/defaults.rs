@@ -127,7 +127,7 @@ pub fn default_object(id: i32) -> GDObject {
     match OBJECT_DEFAULTS_UNIQUE.get(&id) {
         Some(s) => GDObject::parse_str(s),
         None => match check_common_suffix(id) {
-            Some(suf) => GDObject::parse_str(&format!("1,{id}{suf}")),
+            Some(suf) => GDObject::parse_str(format!("1,{id}{suf}")),
             None => GDObject::new(id, &GDObjConfig::default(), vec![]),
         },
     }
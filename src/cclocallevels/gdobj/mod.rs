@@ -1,14 +1,26 @@
 //! This module contains the GDObject struct, used for parsing to/from raw object strings
 //! This module also contains the GDObjConfig metadata descriptor struct for GDObjects
-use std::fmt::{Debug, Display, Write};
+use std::{
+    fmt::{Debug, Display, Write},
+    str::FromStr,
+};
 
-use crate::cclocallevels::{
-    gdobj::{
-        ids::properties::*,
-        meta::{GDObjAttributes, GDObjConfig},
-        structs::{ColourChannel, Event, GDObjPropType, GDValue, Group, MoveEasing, ZLayer},
+use crate::{
+    cclocallevels::{
+        gdobj::{
+            ids::properties::*,
+            meta::{GDObjAttributes, GDObjConfig},
+            structs::{
+                ColourChannel, Event, GDObjPropType, GDValue, Group, MoveEasing, ZLayer, parse_bool,
+            },
+        },
+        properties::{self, OBJECT_NAMES, get_obj_property_type},
+    },
+    core::{
+        GDError,
+        parse::{Diagnostic, DiagnosticKind, Diagnostics, ParseMode, ParseOptions},
+        values::{describe_str, invalid},
     },
-    properties::{self, OBJECT_NAMES, get_obj_property_type},
 };
 
 pub mod defaults;
@@ -22,12 +34,6 @@ pub mod constructors;
 pub mod meta;
 pub mod structs;
 
-macro_rules! parse {
-    ($v:expr => $t:ty) => {
-        $v.parse::<$t>().unwrap_or_default()
-    };
-}
-
 // for debug purposes
 
 // fn parse_with_err_handle<T>(s: &str, p: u16) -> T
@@ -47,6 +53,76 @@ macro_rules! parse {
 //     }
 // }
 
+/// State of a single [`GDObject::parse_with`] call.
+struct ObjectParser {
+    /// `None` for the legacy [`GDObject::parse_str`], which silently falls back to defaults.
+    mode: Option<ParseMode>,
+    diagnostics: Diagnostics,
+}
+
+impl ObjectParser {
+    /// Handles a value that could not be parsed without loss: an error in strict mode, otherwise recorded.
+    fn lossy(&mut self, diagnostic: Diagnostic) -> Result<(), GDError> {
+        match self.mode {
+            None => Ok(()),
+            Some(ParseMode::Strict) => {
+                let (DiagnosticKind::Coerced { expected } | DiagnosticKind::Dropped { expected }) =
+                    diagnostic.kind
+                else {
+                    unreachable!("only value diagnostics are lossy")
+                };
+                let property = diagnostic.property.unwrap_or_default();
+                Err(
+                    invalid(expected, describe_str(&diagnostic.raw.unwrap_or_default()))
+                        .at(&property.to_string()),
+                )
+            }
+            Some(ParseMode::Lenient) => {
+                self.diagnostics.push(diagnostic);
+                Ok(())
+            }
+        }
+    }
+
+    fn value<T: FromStr>(
+        &mut self,
+        property: u16,
+        val: &str,
+        expected: &'static str,
+        default: T,
+    ) -> Result<T, GDError> {
+        match val.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => {
+                self.lossy(Diagnostic::coerced(property, val, expected))?;
+                Ok(default)
+            }
+        }
+    }
+
+    fn bool(&mut self, property: u16, val: &str) -> Result<bool, GDError> {
+        match parse_bool(val) {
+            Some(b) => Ok(b),
+            None => {
+                self.lossy(Diagnostic::coerced(property, val, "boolean (0 or 1)"))?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Parses a `.`-separated group list. Unparsable groups are dropped.
+    fn groups(&mut self, property: u16, val: &str) -> Result<Vec<i16>, GDError> {
+        let mut groups = vec![];
+        for g in val.trim_matches('"').split('.').filter(|g| !g.is_empty()) {
+            match g.parse::<i16>() {
+                Ok(g) => groups.push(g),
+                Err(_) => self.lossy(Diagnostic::dropped(property, g, "group ID"))?,
+            }
+        }
+        Ok(groups)
+    }
+}
+
 /// Container for GD Object properties.
 #[derive(Clone, PartialEq)]
 #[must_use]
@@ -121,9 +197,39 @@ impl Debug for GDObject {
 }
 
 impl GDObject {
-    /// Parses raw object string to `GDObject`
+    /// Parses raw object string to `GDObject`.
+    ///
+    /// Values that cannot be parsed are silently replaced with their default;
+    /// use [`GDObject::parse_str_with_options`] to detect them.
     pub fn parse_str<T: AsRef<str>>(s: T) -> GDObject {
-        let s = s.as_ref();
+        let mut parser = ObjectParser {
+            mode: None,
+            diagnostics: vec![],
+        };
+        match Self::parse_with(s.as_ref(), &mut parser) {
+            Ok(obj) => obj,
+            Err(_) => unreachable!("parsing without a parse mode never fails"),
+        }
+    }
+
+    /// Parses raw object string to `GDObject` according to `options`.
+    ///
+    /// In strict mode, any value that cannot be parsed without loss is an error whose path is the property ID.
+    /// In lenient mode, such values are replaced with a default (or dropped, for list entries) and reported in the
+    /// returned [`Diagnostics`]. An object whose ID cannot be parsed is an error in both modes.
+    pub fn parse_str_with_options<T: AsRef<str>>(
+        s: T,
+        options: &ParseOptions,
+    ) -> Result<(GDObject, Diagnostics), GDError> {
+        let mut parser = ObjectParser {
+            mode: Some(options.mode),
+            diagnostics: vec![],
+        };
+        let obj = Self::parse_with(s.as_ref(), &mut parser)?;
+        Ok((obj, parser.diagnostics))
+    }
+
+    fn parse_with(s: &str, p: &mut ObjectParser) -> Result<GDObject, GDError> {
         let mut obj = GDObject {
             id: 1,
             config: GDObjConfig::default(),
@@ -134,165 +240,189 @@ impl GDObject {
         while let (Some(idx), Some(val)) = (iter.next(), iter.next()) {
             let idx_u16 = match idx.parse::<u16>() {
                 Ok(n) => n,
-                Err(_) => match idx[2..].parse::<u16>() {
-                    Ok(n) => n + 10_000,
-                    Err(_) => 65535,
+                Err(_) => match idx.get(2..).map(str::parse::<u16>) {
+                    Some(Ok(n)) => n + 10_000,
+                    _ => 65535,
                 },
             };
 
             match idx_u16 {
-                OBJECT_ID => obj.id = parse!(val => i32),
-                X_POS => obj.config.pos.0 = val.parse().unwrap_or(0.0),
-                Y_POS => obj.config.pos.1 = val.parse().unwrap_or(0.0),
-                ROTATION => obj.config.angle = val.parse().unwrap_or(0.0),
-                TOUCH_TRIGGERABLE => obj.config.trigger_cfg.touchable = parse!(val => bool),
-                SPAWN_TRIGGERABLE => obj.config.trigger_cfg.spawnable = parse!(val => bool),
-                MULTITRIGGERABLE => obj.config.trigger_cfg.multitriggerable = parse!(val => bool),
+                OBJECT_ID => {
+                    obj.id = match (val.parse::<i32>(), p.mode) {
+                        (Ok(id), _) => id,
+                        (Err(_), None) => 0,
+                        (Err(_), Some(_)) => {
+                            return Err(invalid("object ID", describe_str(val)).at(idx));
+                        }
+                    }
+                }
+                X_POS => obj.config.pos.0 = p.value(idx_u16, val, "number", 0.0)?,
+                Y_POS => obj.config.pos.1 = p.value(idx_u16, val, "number", 0.0)?,
+                ROTATION => obj.config.angle = p.value(idx_u16, val, "number", 0.0)?,
+                TOUCH_TRIGGERABLE => obj.config.trigger_cfg.touchable = p.bool(idx_u16, val)?,
+                SPAWN_TRIGGERABLE => obj.config.trigger_cfg.spawnable = p.bool(idx_u16, val)?,
+                MULTITRIGGERABLE => {
+                    obj.config.trigger_cfg.multitriggerable = p.bool(idx_u16, val)?
+                }
                 GROUPS => {
+                    let groups = p.groups(idx_u16, val)?;
                     obj.config.add_groups(
-                        val.trim_matches('"')
-                            .split('.')
-                            .filter_map(|g| g.parse::<i16>().ok())
+                        groups
+                            .into_iter()
                             .map(Group::Regular)
                             .collect::<Vec<Group>>(),
                     );
                 }
-                X_SCALE => obj.config.scale.0 = val.parse().unwrap_or(1.0),
-                Y_SCALE => obj.config.scale.1 = val.parse().unwrap_or(1.0),
-                EDITOR_LAYER_1 => obj.config.editor_layers.0 = parse!(val => i16),
-                EDITOR_LAYER_2 => obj.config.editor_layers.1 = parse!(val => i16),
+                X_SCALE => obj.config.scale.0 = p.value(idx_u16, val, "number", 1.0)?,
+                Y_SCALE => obj.config.scale.1 = p.value(idx_u16, val, "number", 1.0)?,
+                EDITOR_LAYER_1 => {
+                    obj.config.editor_layers.0 = p.value(idx_u16, val, "16-bit integer", 0)?
+                }
+                EDITOR_LAYER_2 => {
+                    obj.config.editor_layers.1 = p.value(idx_u16, val, "16-bit integer", 0)?
+                }
                 OBJECT_COLOUR => {
-                    obj.config.colour_channels.0 = ColourChannel::from(parse!(val => i16));
+                    obj.config.colour_channels.0 =
+                        ColourChannel::from(p.value(idx_u16, val, "16-bit integer", 0)?);
                 }
                 SECONDARY_COLOUR => {
-                    obj.config.colour_channels.1 = ColourChannel::from(parse!(val => i16));
+                    obj.config.colour_channels.1 =
+                        ColourChannel::from(p.value(idx_u16, val, "16-bit integer", 0)?);
+                }
+                Z_LAYER => {
+                    obj.config.z_layer = ZLayer::from(p.value(idx_u16, val, "32-bit integer", 0)?)
+                }
+                Z_ORDER => obj.config.z_order = p.value(idx_u16, val, "32-bit integer", 0)?,
+                ENTER_EFFECT_CHANNEL => {
+                    obj.config.enter_effect_channel = p.value(idx_u16, val, "16-bit integer", 0)?
+                }
+                OBJECT_MATERIAL => {
+                    obj.config.material_id = p.value(idx_u16, val, "16-bit integer", 0)?
                 }
-                Z_LAYER => obj.config.z_layer = ZLayer::from(parse!(val => i32)),
-                Z_ORDER => obj.config.z_order = parse!(val => i32),
-                ENTER_EFFECT_CHANNEL => obj.config.enter_effect_channel = parse!(val => i16),
-                OBJECT_MATERIAL => obj.config.material_id = parse!(val => i16),
                 DONT_FADE => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::dont_fade, parse!(val => bool)),
+                    .set(GDObjAttributes::dont_fade, p.bool(idx_u16, val)?),
                 DONT_ENTER => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::dont_enter, parse!(val => bool)),
+                    .set(GDObjAttributes::dont_enter, p.bool(idx_u16, val)?),
                 NO_OBJECT_EFFECTS => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::no_effects, parse!(val => bool)),
+                    .set(GDObjAttributes::no_effects, p.bool(idx_u16, val)?),
                 IS_GROUP_PARENT => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::is_group_parent, parse!(val => bool)),
+                    .set(GDObjAttributes::is_group_parent, p.bool(idx_u16, val)?),
                 IS_AREA_PARENT => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::is_area_parent, parse!(val => bool)),
+                    .set(GDObjAttributes::is_area_parent, p.bool(idx_u16, val)?),
                 DONT_BOOST_X => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::dont_boost_x, parse!(val => bool)),
+                    .set(GDObjAttributes::dont_boost_x, p.bool(idx_u16, val)?),
                 DONT_BOOST_Y => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::dont_boost_y, parse!(val => bool)),
+                    .set(GDObjAttributes::dont_boost_y, p.bool(idx_u16, val)?),
                 IS_HIGH_DETAIL => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::high_detail, parse!(val => bool)),
+                    .set(GDObjAttributes::high_detail, p.bool(idx_u16, val)?),
                 NO_TOUCH => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::no_touch, parse!(val => bool)),
+                    .set(GDObjAttributes::no_touch, p.bool(idx_u16, val)?),
                 PASSABLE => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::passable, parse!(val => bool)),
+                    .set(GDObjAttributes::passable, p.bool(idx_u16, val)?),
                 HIDDEN => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::hidden, parse!(val => bool)),
+                    .set(GDObjAttributes::hidden, p.bool(idx_u16, val)?),
                 NONSTICK_X => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::non_stick_x, parse!(val => bool)),
+                    .set(GDObjAttributes::non_stick_x, p.bool(idx_u16, val)?),
                 NONSTICK_Y => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::non_stick_y, parse!(val => bool)),
+                    .set(GDObjAttributes::non_stick_y, p.bool(idx_u16, val)?),
                 EXTRA_STICKY => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::extra_sticky, parse!(val => bool)),
+                    .set(GDObjAttributes::extra_sticky, p.bool(idx_u16, val)?),
                 HAS_EXTENDED_COLLISION => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::extended_collision, parse!(val => bool)),
+                    .set(GDObjAttributes::extended_collision, p.bool(idx_u16, val)?),
                 IS_ICE_BLOCK => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::is_ice_block, parse!(val => bool)),
+                    .set(GDObjAttributes::is_ice_block, p.bool(idx_u16, val)?),
                 GRIP_SLOPE => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::grip_slope, parse!(val => bool)),
+                    .set(GDObjAttributes::grip_slope, p.bool(idx_u16, val)?),
                 NO_GLOW => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::no_glow, parse!(val => bool)),
+                    .set(GDObjAttributes::no_glow, p.bool(idx_u16, val)?),
                 NO_PARTICLES => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::no_particles, parse!(val => bool)),
+                    .set(GDObjAttributes::no_particles, p.bool(idx_u16, val)?),
                 SCALE_STICK => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::scale_stick, parse!(val => bool)),
+                    .set(GDObjAttributes::scale_stick, p.bool(idx_u16, val)?),
                 NO_AUDIO_SCALE => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::no_audio_scale, parse!(val => bool)),
+                    .set(GDObjAttributes::no_audio_scale, p.bool(idx_u16, val)?),
                 SINGLE_PLAYER_TOUCH => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::single_ptouch, parse!(val => bool)),
+                    .set(GDObjAttributes::single_ptouch, p.bool(idx_u16, val)?),
                 CENTER_EFFECT => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::center_effect, parse!(val => bool)),
+                    .set(GDObjAttributes::center_effect, p.bool(idx_u16, val)?),
                 REVERSES_GAMEPLAY => obj
                     .config
                     .attributes
-                    .set(GDObjAttributes::reverse, parse!(val => bool)),
-                MATERIAL_CONTROL_ID => obj.config.control_id = parse!(val => i16),
+                    .set(GDObjAttributes::reverse, p.bool(idx_u16, val)?),
+                MATERIAL_CONTROL_ID => {
+                    obj.config.control_id = p.value(idx_u16, val, "16-bit integer", 0)?
+                }
                 PARENT_GROUPS => {
                     // add groups method handles deduping
+                    let groups = p.groups(idx_u16, val)?;
                     obj.config.add_groups(
-                        val.trim_matches('"')
-                            .split('.')
-                            .filter_map(|g| g.parse::<i16>().ok())
+                        groups
+                            .into_iter()
                             .map(Group::Parent)
                             .collect::<Vec<Group>>(),
                     );
                 }
-                n => obj.set_property_raw(n, val),
+                n => {
+                    let ptype = get_obj_property_type(n).unwrap_or(GDObjPropType::Unknown);
+                    let value = match GDValue::try_from_str(ptype, val) {
+                        Some(v) => v,
+                        None => {
+                            p.lossy(Diagnostic::coerced(n, val, GDValue::expected(ptype)))?;
+                            GDValue::from(ptype, val)
+                        }
+                    };
+                    obj.set_property(n, value);
+                }
             }
         }
 
-        obj
-    }
-
-    fn set_property_raw(&mut self, p: u16, value: &str) {
-        self.set_property(
-            p,
-            GDValue::from(
-                get_obj_property_type(p).unwrap_or(GDObjPropType::Unknown),
-                value,
-            ),
-        );
+        Ok(obj)
     }
 
     /// Sets the prpoerty ID to the value, and craetes it if it doesn't exist
@@ -345,7 +475,9 @@ impl GDObject {
 
     /// Creates a new GDObject from ID, config, and extra proerties
     #[inline]
-    pub fn new(id: i32, config: &GDObjConfig, properties: Vec<(u16, GDValue)>) -> Self {
+    pub fn new(id: i32, config: &GDObjConfig, mut properties: Vec<(u16, GDValue)>) -> Self {
+        // properties are looked up by binary search
+        properties.sort_by_key(|p| p.0);
         GDObject {
             id,
             config: config.clone(),
@@ -395,6 +395,62 @@ impl GDValue {
         }
     }
 
+    /// Converts input string to a variant of this enum based on the property type.
+    ///
+    /// Unlike [`GDValue::from`], this returns `None` instead of falling back to a default value
+    /// if the string is not a valid value of that type.
+    #[must_use]
+    pub fn try_from_str(t: GDObjPropType, s: &str) -> Option<Self> {
+        Some(match t {
+            GDObjPropType::Bool => Self::Bool(parse_bool(s)?),
+            GDObjPropType::Toggle => Self::Toggle(match s {
+                "1" => true,
+                "0" | "-1" => false,
+                _ => return None,
+            }),
+            GDObjPropType::ColourChannel => {
+                Self::ColourChannel(ColourChannel::from(s.parse::<i16>().ok()?))
+            }
+            GDObjPropType::Easing => Self::Easing(MoveEasing::from(s.parse::<i32>().ok()?)),
+            GDObjPropType::Float => Self::Float(s.parse().ok()?),
+            GDObjPropType::Int => Self::Int(s.parse().ok()?),
+            GDObjPropType::EventsList => Self::Events(
+                s.split('.')
+                    .filter(|i| !i.is_empty())
+                    .map(|i| i.parse::<i32>().ok().map(Event::from))
+                    .collect::<Option<_>>()?,
+            ),
+            GDObjPropType::ProbabilitiesList => {
+                Self::ProbabilitiesList(SmallVec::from_vec(try_parse_sibling_items(s)?))
+            }
+            GDObjPropType::SpawnRemapsList => {
+                Self::SpawnRemapsList(SmallVec::from_vec(try_parse_sibling_items(s)?))
+            }
+            GDObjPropType::Group => Self::Group(s.parse().ok()?),
+            GDObjPropType::Item => Self::Item(s.parse().ok()?),
+            GDObjPropType::Text | GDObjPropType::Unknown => Self::String(s.to_owned()),
+        })
+    }
+
+    /// Short description of the values of a property type, used in errors and diagnostics.
+    #[must_use]
+    pub const fn expected(t: GDObjPropType) -> &'static str {
+        match t {
+            GDObjPropType::Bool => "boolean (0 or 1)",
+            GDObjPropType::Toggle => "toggle (1, 0 or -1)",
+            GDObjPropType::ColourChannel => "colour channel ID",
+            GDObjPropType::Easing => "easing ID",
+            GDObjPropType::Float => "number",
+            GDObjPropType::Int => "32-bit integer",
+            GDObjPropType::EventsList => "list of event IDs",
+            GDObjPropType::ProbabilitiesList => "list of group/chance pairs",
+            GDObjPropType::SpawnRemapsList => "list of group remap pairs",
+            GDObjPropType::Group => "group ID",
+            GDObjPropType::Item => "item ID",
+            GDObjPropType::Text | GDObjPropType::Unknown => "string",
+        }
+    }
+
     #[inline]
     /// Converts a vector of [`Group`]s to a [`GDValue`]
     pub fn from_group_list(g: &[Group]) -> Self {
@@ -869,6 +925,20 @@ repr_t!(
     default X1
 );
 
+impl Speed {
+    /// Horizontal speed of the player in units per second
+    #[must_use]
+    pub fn units_per_second(self) -> f64 {
+        match self {
+            Self::X0Point5 => 251.16,
+            Self::X1 => 311.58,
+            Self::X2 => 387.42,
+            Self::X3 => 468.0,
+            Self::X4 => 576.0,
+        }
+    }
+}
+
 /// Config struct for HSV colour settings
 #[derive(Debug, Clone, PartialEq)]
 pub struct HSVColour {
@@ -922,7 +992,7 @@ macro_rules! set_value {
 impl HSVColour {
     /// Parses a string to this object
     pub fn parse(s: &str) -> Option<Self> {
-        let mut vals_iter = s.split("a").into_iter();
+        let mut vals_iter = s.split("a");
         let mut new = Self {
             hue_shift: 0,
             saturation_mult: 1.0,
@@ -934,8 +1004,8 @@ impl HSVColour {
         new.hue_shift = set_value!(vals_iter => i32);
         new.saturation_mult = set_value!(vals_iter => f64);
         new.brightness_mult = set_value!(vals_iter => f64);
-        new.static_bright_scalar = set_value!(vals_iter);
         new.static_sat_scalar = set_value!(vals_iter);
+        new.static_bright_scalar = set_value!(vals_iter);
         Some(new)
     }
 }
@@ -1274,6 +1344,30 @@ where
     tuples
 }
 
+/// Like `parse_sibling_items`, but fails on unparsable items or an unpaired trailing item.
+fn try_parse_sibling_items<T: FromStr, S: FromStr>(s: &str) -> Option<Vec<(T, S)>> {
+    if s.is_empty() {
+        return Some(vec![]);
+    }
+    let items = s.split('.').collect::<Vec<&str>>();
+    if items.len() % 2 != 0 {
+        return None;
+    }
+    items
+        .chunks_exact(2)
+        .map(|pair| Some((pair[0].parse().ok()?, pair[1].parse().ok()?)))
+        .collect()
+}
+
+/// Parses a GD boolean, which is serialised as `1` or `0`.
+pub(crate) fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
 /// Group ID container for regular and parent groups
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
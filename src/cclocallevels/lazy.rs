@@ -0,0 +1,389 @@
+//! This module contains [`LazyCCLocalLevels`], which indexes a savefile and only parses the levels that are asked for.
+
+use std::{fmt::Write, ops::Range, path::PathBuf};
+
+use crate::{
+    cclocallevels::gdlevel::{CCLocalLevels, GDLevel, PLIST_HEADER},
+    core::{
+        GDError,
+        files::{SaveOptions, SavefileFingerprint, write_savefile},
+        gdplist::{self, RawEntry},
+        get_cclocallevels_path,
+        io::{
+            SavefilePlatform, decrypt_file_with_fingerprint, encrypt_savefile_str_for,
+            stringify_xml,
+        },
+        values::{as_int, as_str, describe, invalid},
+    },
+};
+
+/// Lazily parsed CCLocalLevels.dat.
+///
+/// Loading only scans the decrypted savefile once to build an index of the name, ID, folder, order and position of
+/// every level. A [`GDLevel`] is parsed when it is asked for, and only levels that were edited or added are serialised
+/// again on export; everything else is copied from the original text. This is much faster than [`CCLocalLevels`] for
+/// tools that only touch a few levels of a large savefile.
+///
+/// Lists and other root entries are not parsed and are always exported untouched.
+#[derive(Debug, Clone)]
+pub struct LazyCCLocalLevels {
+    xml: String,
+    /// Span of the `LLM_01` value
+    levels_span: Range<usize>,
+    /// Entries of `LLM_01` that are not levels, e.g. `_isArr`
+    other_entries: Vec<Range<usize>>,
+    levels: Vec<LazyLevel>,
+    /// Whether any level was edited, added, removed or moved since loading
+    edited: bool,
+    /// Platform that the savefile is encrypted for when exported. This is detected when loading an encrypted savefile.
+    pub platform: SavefilePlatform,
+    /// Fingerprint of the savefile that this struct was loaded from, if any.
+    /// Exports refuse to overwrite that savefile if it was modified in the meantime.
+    pub fingerprint: Option<SavefileFingerprint>,
+}
+
+/// Index entry of a level in a [`LazyCCLocalLevels`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelIndexEntry {
+    /// Internal key: `k2`
+    pub name: String,
+    /// Internal key: `k1`
+    pub id: i32,
+    /// Internal key: `k84`
+    pub folder: i32,
+    /// Internal key: `k83`
+    pub level_order: i32,
+    /// Byte range of the level's dictionary in the decrypted savefile, or `None` if the level was edited or added
+    pub span: Option<Range<usize>>,
+}
+
+#[derive(Debug, Clone)]
+enum LazyLevel {
+    /// Untouched level in the original text
+    Indexed(LevelIndexEntry),
+    /// Level that was edited or added
+    Loaded(Box<GDLevel>),
+}
+
+impl LazyLevel {
+    fn name(&self) -> &str {
+        match self {
+            Self::Indexed(entry) => &entry.name,
+            Self::Loaded(level) => &level.identity.name,
+        }
+    }
+
+    fn id(&self) -> i32 {
+        match self {
+            Self::Indexed(entry) => entry.id,
+            Self::Loaded(level) => level.identity.id,
+        }
+    }
+
+    fn level_order(&self) -> i32 {
+        match self {
+            Self::Indexed(entry) => entry.level_order,
+            Self::Loaded(level) => level.meta.level_order,
+        }
+    }
+
+    fn entry(&self) -> LevelIndexEntry {
+        match self {
+            Self::Indexed(entry) => entry.clone(),
+            Self::Loaded(level) => LevelIndexEntry {
+                name: level.identity.name.clone(),
+                id: level.identity.id,
+                folder: level.meta.level_folder,
+                level_order: level.meta.level_order,
+                span: None,
+            },
+        }
+    }
+}
+
+impl LazyCCLocalLevels {
+    /// Indexes the levels in CCLocalLevels.dat if retrievable
+    #[inline]
+    pub fn from_local() -> Result<Self, GDError> {
+        Self::from_file(get_cclocallevels_path().ok_or(GDError::MissingSavefile)?)
+    }
+
+    /// Indexes the levels in the given encrypted savefile. The savefile may be from any platform.
+    pub fn from_file(file: PathBuf) -> Result<Self, GDError> {
+        let (decrypted, platform, fingerprint) = decrypt_file_with_fingerprint(file)?;
+        let mut levels = Self::from_decrypted(decrypted)?;
+        levels.platform = platform;
+        levels.fingerprint = Some(fingerprint);
+        Ok(levels)
+    }
+
+    /// Indexes the levels in a raw savefile string.
+    ///
+    /// Only the index keys are checked here. Any other problem with a level is returned when it is parsed.
+    pub fn from_decrypted(s: String) -> Result<Self, GDError> {
+        if !s.starts_with(PLIST_HEADER) {
+            return Err(GDError::CorruptedSavefile("Savefile header does not match the expected header. This may be due to a corrupted savefile or a savefile from a previous version of GD.".into()));
+        };
+
+        let (_, root) = gdplist::root_entries(&s)?;
+        let mut levels_span = None;
+        for entry in &root {
+            if gdplist::entry_key(&s, entry)? == "LLM_01" {
+                levels_span = Some(entry.value.clone());
+            }
+        }
+        let levels_span = levels_span.ok_or(GDError::missing_key("LLM_01"))?;
+        let Some(content) = gdplist::dict_content(&s, levels_span.clone()) else {
+            let found = describe(&gdplist::parse_value(&s, levels_span)?);
+            return Err(invalid("dictionary", found).at("LLM_01"));
+        };
+
+        let mut other_entries = vec![];
+        let mut levels = vec![];
+        for entry in gdplist::dict_entries(&s, content)? {
+            let key = gdplist::entry_key(&s, &entry)?;
+            if key == "_isArr" {
+                other_entries.push(entry.entry);
+            } else {
+                let indexed = index_level(&s, entry).map_err(|e| e.at(&key).at("LLM_01"))?;
+                levels.push(LazyLevel::Indexed(indexed));
+            }
+        }
+
+        Ok(Self {
+            xml: s,
+            levels_span,
+            other_entries,
+            levels,
+            edited: false,
+            platform: SavefilePlatform::default(),
+            fingerprint: None,
+        })
+    }
+
+    /// Returns the number of levels.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns `true` if there are no levels.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// Returns the index entry of the level at `idx`, if there is one.
+    #[must_use]
+    pub fn entry(&self, idx: usize) -> Option<LevelIndexEntry> {
+        self.levels.get(idx).map(LazyLevel::entry)
+    }
+
+    /// Returns the index entries of all levels in order from newest to oldest.
+    pub fn entries(&self) -> impl Iterator<Item = LevelIndexEntry> + '_ {
+        self.levels.iter().map(LazyLevel::entry)
+    }
+
+    /// Returns the position of the first level with the given name.
+    #[must_use]
+    pub fn position_by_name(&self, name: &str) -> Option<usize> {
+        self.levels.iter().position(|l| l.name() == name)
+    }
+
+    /// Returns the position of the first level with the given ID.
+    #[must_use]
+    pub fn position_by_id(&self, id: i32) -> Option<usize> {
+        self.levels.iter().position(|l| l.id() == id)
+    }
+
+    /// Parses the level at `idx`. Returns `Ok(None)` if there is no such level.
+    pub fn get(&self, idx: usize) -> Result<Option<GDLevel>, GDError> {
+        match self.levels.get(idx) {
+            Some(LazyLevel::Indexed(entry)) => self.parse(idx, entry).map(Some),
+            Some(LazyLevel::Loaded(level)) => Ok(Some(level.as_ref().clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the first level with the given name.
+    pub fn get_by_name(&self, name: &str) -> Result<Option<GDLevel>, GDError> {
+        match self.position_by_name(name) {
+            Some(idx) => self.get(idx),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the first level with the given ID.
+    pub fn get_by_id(&self, id: i32) -> Result<Option<GDLevel>, GDError> {
+        match self.position_by_id(id) {
+            Some(idx) => self.get(idx),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a mutable reference to the level at `idx`, parsing it first if needed.
+    ///
+    /// The level counts as edited from then on and is serialised again on export.
+    pub fn get_mut(&mut self, idx: usize) -> Result<Option<&mut GDLevel>, GDError> {
+        if let Some(LazyLevel::Indexed(entry)) = self.levels.get(idx) {
+            let level = self.parse(idx, entry)?;
+            self.levels[idx] = LazyLevel::Loaded(Box::new(level));
+        }
+        match self.levels.get_mut(idx) {
+            Some(LazyLevel::Loaded(level)) => {
+                self.edited = true;
+                Ok(Some(level))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns an iterator that parses every level in order from newest to oldest.
+    pub fn iter(&self) -> impl Iterator<Item = Result<GDLevel, GDError>> + '_ {
+        (0..self.levels.len()).filter_map(|idx| self.get(idx).transpose())
+    }
+
+    /// Replaces the level at `idx`.
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of bounds.
+    pub fn set(&mut self, idx: usize, level: GDLevel) {
+        self.levels[idx] = LazyLevel::Loaded(Box::new(level));
+        self.edited = true;
+    }
+
+    /// Adds a level to the beginning of the savefile.
+    ///
+    /// Like [`CCLocalLevels::add_level`], the level's order is raised above the newest level if it isn't already, so
+    /// the added level is listed first in-game.
+    pub fn add_level(&mut self, mut level: GDLevel) {
+        if let Some(newest) = self.levels.first().map(LazyLevel::level_order)
+            && level.meta.level_order <= newest
+        {
+            level.meta.level_order = newest.saturating_add(1);
+        }
+        self.levels.insert(0, LazyLevel::Loaded(Box::new(level)));
+        self.edited = true;
+    }
+
+    /// Removes the level at `idx` and returns it parsed. Returns `Ok(None)` if there is no such level.
+    pub fn remove(&mut self, idx: usize) -> Result<Option<GDLevel>, GDError> {
+        let Some(level) = self.get(idx)? else {
+            return Ok(None);
+        };
+        self.levels.remove(idx);
+        self.edited = true;
+        Ok(Some(level))
+    }
+
+    /// Parses every level into a [`CCLocalLevels`], keeping the platform and fingerprint.
+    pub fn into_cclocallevels(self) -> Result<CCLocalLevels, GDError> {
+        let (platform, fingerprint) = (self.platform, self.fingerprint.clone());
+        let mut levels = CCLocalLevels::from_decrypted(self.export_to_string())?;
+        levels.platform = platform;
+        levels.fingerprint = fingerprint;
+        Ok(levels)
+    }
+
+    /// Exports this struct as XML to a String.
+    ///
+    /// Levels that were not edited are copied from the original savefile, so a savefile without edits is reproduced
+    /// byte for byte.
+    #[must_use]
+    pub fn export_to_string(&self) -> String {
+        if !self.edited {
+            return self.xml.clone();
+        }
+
+        let mut out = String::with_capacity(self.xml.len());
+        out.push_str(&self.xml[..self.levels_span.start]);
+        out.push_str("<d>");
+        for other in &self.other_entries {
+            out.push_str(&self.xml[other.clone()]);
+        }
+        for (idx, level) in self.levels.iter().enumerate() {
+            let _ = write!(out, "<k>k_{idx}</k>");
+            match level {
+                LazyLevel::Indexed(LevelIndexEntry {
+                    span: Some(span), ..
+                }) => out.push_str(&self.xml[span.clone()]),
+                LazyLevel::Indexed(_) => unreachable!("indexed levels always have a span"),
+                LazyLevel::Loaded(level) => out.push_str(&stringify_xml(&level.to_dict(), false)),
+            }
+        }
+        out.push_str("</d>");
+        out.push_str(&self.xml[self.levels_span.end..]);
+        out
+    }
+
+    /// Exports this struct as encrypted XML to the standard CCLocalLevels.dat location.
+    /// See [`CCLocalLevels::export_to_savefile`].
+    pub fn export_to_savefile(&mut self) -> Result<(), GDError> {
+        let savefile = get_cclocallevels_path().ok_or(GDError::MissingSavefile)?;
+        self.export_to_file_with(savefile, &SaveOptions::without_backups())
+    }
+
+    /// Exports this struct as encrypted XML to a given file. The savefile is encrypted for `self.platform` and written atomically.
+    pub fn export_to_file(&mut self, file: PathBuf) -> Result<(), GDError> {
+        self.export_to_file_with(file, &SaveOptions::without_backups())
+    }
+
+    /// Exports this struct as encrypted XML to a given file using the given save options.
+    /// See [`CCLocalLevels::export_to_file_with`].
+    pub fn export_to_file_with(
+        &mut self,
+        file: PathBuf,
+        options: &SaveOptions,
+    ) -> Result<(), GDError> {
+        let tracked = self.fingerprint.as_ref().is_some_and(|fp| fp.is_for(&file));
+        if tracked
+            && !options.force
+            && let Some(fingerprint) = &self.fingerprint
+        {
+            fingerprint.verify()?;
+        }
+
+        let export_str = encrypt_savefile_str_for(&self.export_to_string(), self.platform);
+        write_savefile(&file, &export_str, options)?;
+
+        if tracked {
+            self.fingerprint = Some(SavefileFingerprint::from_bytes(file, &export_str)?);
+        }
+        Ok(())
+    }
+
+    fn parse(&self, idx: usize, entry: &LevelIndexEntry) -> Result<GDLevel, GDError> {
+        let span = entry.span.clone().unwrap_or_default();
+        GDLevel::from_gdplist(&self.xml, span).map_err(|e| e.at(&format!("k_{idx}")).at("LLM_01"))
+    }
+}
+
+/// Reads the index keys of the level spanning `entry.value`, skipping everything else.
+fn index_level(xml: &str, entry: RawEntry) -> Result<LevelIndexEntry, GDError> {
+    let Some(content) = gdplist::dict_content(xml, entry.value.clone()) else {
+        let found = describe(&gdplist::parse_value(xml, entry.value)?);
+        return Err(invalid("dictionary", found));
+    };
+
+    let mut indexed = LevelIndexEntry {
+        name: String::new(),
+        id: 0,
+        folder: 0,
+        level_order: 0,
+        span: Some(entry.value),
+    };
+    for level_entry in gdplist::dict_entries(xml, content)? {
+        let key = gdplist::entry_key(xml, &level_entry)?;
+        if !matches!(&*key, "k1" | "k2" | "k83" | "k84") {
+            continue;
+        }
+        let value = gdplist::parse_value(xml, level_entry.value)?;
+        match &*key {
+            "k1" => indexed.id = as_int(&value).map_err(|e| e.at(&key))?,
+            "k2" => indexed.name = as_str(&value).map_err(|e| e.at(&key))?.to_owned(),
+            "k83" => indexed.level_order = as_int(&value).map_err(|e| e.at(&key))?,
+            _ => indexed.folder = as_int(&value).map_err(|e| e.at(&key))?,
+        }
+    }
+    Ok(indexed)
+}
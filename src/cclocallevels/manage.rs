@@ -0,0 +1,180 @@
+//! This module contains operations for finding, adding, removing and reordering the levels of a savefile.
+//!
+//! Levels are stored from newest to oldest, and their position in [`CCLocalLevels::levels`] becomes their `k_N` key on
+//! export. GD also stores a [`GDLevelMeta::level_order`] (`k83`) in each level, which grows from the oldest level to
+//! the newest. Every operation that adds or moves levels keeps it that way; see
+//! [`CCLocalLevels::normalise_level_order`].
+//!
+//! [`GDLevelMeta::level_order`]: crate::cclocallevels::gdlevel::GDLevelMeta::level_order
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::cclocallevels::gdlevel::{CCLocalLevels, GDLevel};
+
+impl CCLocalLevels {
+    /// Returns the position of the newest level with the given name.
+    #[must_use]
+    pub fn position_by_name(&self, name: &str) -> Option<usize> {
+        self.levels.iter().position(|l| l.identity.name == name)
+    }
+
+    /// Returns the position of the newest level with the given ID.
+    ///
+    /// Levels that were never uploaded all have the ID 0.
+    #[must_use]
+    pub fn position_by_id(&self, id: i32) -> Option<usize> {
+        self.levels.iter().position(|l| l.identity.id == id)
+    }
+
+    /// Returns the newest level with the given name.
+    #[must_use]
+    pub fn find_by_name(&self, name: &str) -> Option<&GDLevel> {
+        self.position_by_name(name).map(|idx| &self.levels[idx])
+    }
+
+    /// Returns the newest level with the given name, mutably.
+    pub fn find_by_name_mut(&mut self, name: &str) -> Option<&mut GDLevel> {
+        self.position_by_name(name).map(|idx| &mut self.levels[idx])
+    }
+
+    /// Returns the newest level with the given ID.
+    #[must_use]
+    pub fn find_by_id(&self, id: i32) -> Option<&GDLevel> {
+        self.position_by_id(id).map(|idx| &self.levels[idx])
+    }
+
+    /// Returns the newest level with the given ID, mutably.
+    pub fn find_by_id_mut(&mut self, id: i32) -> Option<&mut GDLevel> {
+        self.position_by_id(id).map(|idx| &mut self.levels[idx])
+    }
+
+    /// Returns the positions of all levels whose name matches `pattern`, from newest to oldest.
+    #[cfg(feature = "regex")]
+    #[must_use]
+    pub fn positions_matching(&self, pattern: &Regex) -> Vec<usize> {
+        self.levels
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| pattern.is_match(&l.identity.name))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Returns all levels whose name matches `pattern`, from newest to oldest.
+    #[cfg(feature = "regex")]
+    pub fn find_by_regex<'a>(&'a self, pattern: &'a Regex) -> impl Iterator<Item = &'a GDLevel> {
+        self.levels
+            .iter()
+            .filter(|l| pattern.is_match(&l.identity.name))
+    }
+
+    /// Removes the level at `idx` and returns it, or `None` if there is no such level.
+    pub fn remove_level(&mut self, idx: usize) -> Option<GDLevel> {
+        if idx >= self.levels.len() {
+            return None;
+        }
+        Some(self.levels.remove(idx))
+    }
+
+    /// Copies the level at `idx` to the front of the savefile, like GD does, and returns the position of the copy (0).
+    /// Returns `None` if there is no such level.
+    ///
+    /// The copy gets the first free name in the sequence `Name 2`, `Name 3`, etc. (`Level 2` becomes `Level 3`).
+    /// Since it is a new local level, its ID is reset and it is marked as not uploaded; the ID of an uploaded level is
+    /// kept as the original ID.
+    pub fn duplicate_level(&mut self, idx: usize) -> Option<usize> {
+        let mut copy = self.levels.get(idx)?.clone();
+        copy.identity.name = copy_name(&copy.identity.name, |name| {
+            self.position_by_name(name).is_some()
+        });
+        if copy.identity.id != 0 {
+            copy.identity.original_id = Some(copy.identity.id);
+        }
+        copy.identity.id = 0;
+        copy.flags.uploaded = false;
+
+        self.add_level(copy);
+        Some(0)
+    }
+
+    /// Renames the level at `idx` and returns its previous name, or `None` if there is no such level.
+    pub fn rename_level(&mut self, idx: usize, name: &str) -> Option<String> {
+        let level = self.levels.get_mut(idx)?;
+        Some(std::mem::replace(&mut level.identity.name, name.to_owned()))
+    }
+
+    /// Moves the level at `from` so that it ends up at position `to`, shifting the levels in between.
+    /// Returns `false` if either position is out of bounds.
+    pub fn move_level(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.levels.len() || to >= self.levels.len() {
+            return false;
+        }
+        let level = self.levels.remove(from);
+        self.levels.insert(to, level);
+        self.normalise_level_order();
+        true
+    }
+
+    /// Moves the level at `idx` to the front of the savefile, making it the newest level.
+    /// Returns `false` if there is no such level.
+    pub fn move_to_front(&mut self, idx: usize) -> bool {
+        self.move_level(idx, 0)
+    }
+
+    /// Reorders the levels so that the level at `order[i]` ends up at position `i`.
+    /// Returns `false` and leaves the levels untouched if `order` is not a permutation of the level positions.
+    pub fn reorder_levels(&mut self, order: &[usize]) -> bool {
+        let mut seen = vec![false; self.levels.len()];
+        if order.len() != self.levels.len()
+            || !order.iter().all(|idx| {
+                seen.get_mut(*idx)
+                    .is_some_and(|s| !std::mem::replace(s, true))
+            })
+        {
+            return false;
+        }
+
+        let mut levels = std::mem::take(&mut self.levels)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.levels = order.iter().filter_map(|idx| levels[*idx].take()).collect();
+        self.normalise_level_order();
+        true
+    }
+
+    /// Makes [`level_order`] grow from the oldest level to the newest, which is what GD expects.
+    ///
+    /// Walking from the oldest level, any level whose order is not higher than that of the level below it gets one more
+    /// than that level, so no two levels share an order. Orders that are already consistent are left untouched. This is
+    /// done by every operation that adds or moves levels, including [`CCLocalLevels::add_level`].
+    ///
+    /// [`level_order`]: crate::cclocallevels::gdlevel::GDLevelMeta::level_order
+    pub fn normalise_level_order(&mut self) {
+        let mut below = i32::MIN;
+        for level in self.levels.iter_mut().rev() {
+            if level.meta.level_order <= below {
+                level.meta.level_order = below.saturating_add(1);
+            }
+            below = level.meta.level_order;
+        }
+    }
+}
+
+/// Returns the first name in the sequence `base 2`, `base 3`, ... that is not `taken`,
+/// where `base` is `name` without its trailing number.
+pub(crate) fn copy_name<F: Fn(&str) -> bool>(name: &str, taken: F) -> String {
+    let (base, start) = match name.rsplit_once(' ') {
+        Some((base, n)) if !n.starts_with('0') => match n.parse::<u32>() {
+            Ok(n) => (base, n.saturating_add(1)),
+            Err(_) => (name, 2),
+        },
+        _ => (name, 2),
+    };
+
+    (start..)
+        .map(|n| format!("{base} {n}"))
+        .find(|candidate| !taken(candidate))
+        .expect("ran out of copy names")
+}
@@ -0,0 +1,423 @@
+//! This module contains utilities for merging the contents of multiple CCLocalLevels savefiles.
+
+use std::collections::{HashMap, HashSet};
+
+use plist::Dictionary;
+
+use crate::{
+    cclocallevels::{
+        fidelity::hash,
+        gdlevel::{CCLocalLevels, GDLevel},
+        gdlist::GDList,
+        manage::copy_name,
+    },
+    core::io::stringify_xml,
+};
+
+/// Identifies a level across different versions of the same savefile.
+///
+/// Local levels have no stable ID, so they are identified by their name and by how many
+/// levels with the same name are older than them.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LevelKey {
+    /// Name of the level
+    pub name: String,
+    /// Number of older levels in the savefile with the same name
+    pub occurrence: usize,
+}
+
+/// A level that was changed both in our copy and in the newer savefile.
+/// Our version of the level is kept in the merged savefile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LevelConflict {
+    /// The level that was changed on both sides
+    pub key: LevelKey,
+    /// The level was deleted on one side and modified on the other
+    pub deleted: bool,
+}
+
+impl CCLocalLevels {
+    /// Performs a three-way merge of levels, folding the edits made in `ours` onto `theirs`.
+    ///
+    /// # Arguments
+    /// * `base`: the savefile as it was originally loaded
+    /// * `ours`: `base` with our edits applied
+    /// * `theirs`: the newer savefile, usually reloaded from disk after an export returned [`crate::core::GDError::ConcurrentModification`]
+    ///
+    /// Levels that were added, edited or deleted in `ours` relative to `base` are added, replaced
+    /// or deleted in `theirs`. Everything else (lists, binary version, other properties, fingerprint) is taken from `theirs`.
+    /// If a level was changed on both sides, our version wins and the level is reported as a conflict.
+    /// Level orders are normalised afterwards, like after [`CCLocalLevels::add_level`].
+    ///
+    /// Returns the merged savefile and all conflicts.
+    pub fn merge_three_way(
+        base: &CCLocalLevels,
+        ours: &CCLocalLevels,
+        mut theirs: CCLocalLevels,
+    ) -> (CCLocalLevels, Vec<LevelConflict>) {
+        let base_levels = keyed_levels(&base.levels);
+        let our_levels = keyed_levels(&ours.levels);
+        let their_levels = keyed_levels(&theirs.levels);
+
+        let mut conflicts = vec![];
+        let mut added = vec![];
+        let mut removed = HashSet::new();
+
+        for (key, (our_idx, ours_dict)) in &our_levels {
+            let our_level = &ours.levels[*our_idx];
+            match (base_levels.get(key), their_levels.get(key)) {
+                // unchanged by us
+                (Some((_, base_dict)), _) if base_dict == ours_dict => {}
+                // changed by us
+                (Some((_, base_dict)), Some((their_idx, their_dict))) => {
+                    if their_dict != base_dict && their_dict != ours_dict {
+                        conflicts.push(LevelConflict {
+                            key: key.clone(),
+                            deleted: false,
+                        });
+                    }
+                    theirs.levels[*their_idx] = our_level.clone();
+                }
+                // changed by us, deleted by them
+                (Some(_), None) => {
+                    conflicts.push(LevelConflict {
+                        key: key.clone(),
+                        deleted: true,
+                    });
+                    added.push((*our_idx, our_level.clone()));
+                }
+                // added on both sides
+                (None, Some((their_idx, their_dict))) => {
+                    if their_dict != ours_dict {
+                        conflicts.push(LevelConflict {
+                            key: key.clone(),
+                            deleted: false,
+                        });
+                        theirs.levels[*their_idx] = our_level.clone();
+                    }
+                }
+                // added by us
+                (None, None) => added.push((*our_idx, our_level.clone())),
+            }
+        }
+
+        // levels deleted by us
+        for (key, (_, base_dict)) in &base_levels {
+            if our_levels.contains_key(key) {
+                continue;
+            }
+            if let Some((their_idx, their_dict)) = their_levels.get(key) {
+                if their_dict == base_dict {
+                    removed.insert(*their_idx);
+                } else {
+                    conflicts.push(LevelConflict {
+                        key: key.clone(),
+                        deleted: true,
+                    });
+                }
+            }
+        }
+
+        let mut idx = 0;
+        theirs.levels.retain(|_| {
+            idx += 1;
+            !removed.contains(&(idx - 1))
+        });
+
+        // newly added levels go on top, in the same order as in `ours`
+        added.sort_by_key(|(our_idx, _)| *our_idx);
+        theirs
+            .levels
+            .splice(0..0, added.into_iter().map(|(_, level)| level));
+        theirs.normalise_level_order();
+
+        conflicts.sort_by(|a, b| a.key.cmp(&b.key));
+        (theirs, conflicts)
+    }
+}
+
+/// How [`CCLocalLevels::merge_from`] resolves a level or list that is a duplicate of one that is already in the savefile.
+///
+/// Exact copies (same content hash) are never merged, regardless of the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    /// Keep the existing level and do not merge the other one
+    #[default]
+    Skip,
+    /// Merge the other level under a new name (`Name 2`, `Name 3`, etc.)
+    Rename,
+    /// Replace the existing level with the other one
+    Overwrite,
+    /// Keep whichever level has the higher version (`k16`) and revision (`k46`). The existing level wins ties.
+    KeepNewest,
+}
+
+/// What happened to a level or list while merging.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MergeOutcome {
+    /// It was not in the savefile and was added
+    Added,
+    /// An exact copy was already in the savefile
+    Identical,
+    /// It was a duplicate and the existing one was kept
+    Skipped,
+    /// It was a duplicate and was added under the given name
+    Renamed(String),
+    /// It was a duplicate and replaced the existing one
+    Overwritten,
+}
+
+/// Result of [`CCLocalLevels::merge_from`], in the same order as the levels and lists of the other savefile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Outcome for each level of the other savefile
+    pub levels: Vec<MergeOutcome>,
+    /// Outcome for each list of the other savefile
+    pub lists: Vec<MergeOutcome>,
+}
+
+impl CCLocalLevels {
+    /// Merges the levels and lists of `other` into this savefile.
+    ///
+    /// A level is a duplicate of an existing one if it has the same content, the same name, or the same origin: its
+    /// original ID (`k42`), or its ID if it was not copied from another level. Exact copies are always skipped; other
+    /// duplicates are resolved according to `policy`. Lists are merged the same way.
+    ///
+    /// Merged levels and lists are added to the front, in the same order as in `other`. Overwritten levels and lists
+    /// keep their position, order and folder.
+    /// Lists reference levels by ID (`k96` and the summaries in `k97`), so when a merged level stands in for another
+    /// one with a different ID, lists are re-linked to the level that was kept: lists merged from `other` follow
+    /// skipped levels to our copy, and our own lists follow overwritten levels to their replacement. Our lists are
+    /// never re-linked to a level that was skipped, since duplicates may only share their name.
+    pub fn merge_from(&mut self, other: &CCLocalLevels, policy: MergePolicy) -> MergeReport {
+        let mut relinks = Relinks::default();
+        let (levels, _) = merge_items(&mut self.levels, &other.levels, policy, &mut relinks);
+        let (lists, merged_lists) = merge_items(
+            &mut self.lists,
+            &other.lists,
+            policy,
+            &mut Relinks::default(),
+        );
+
+        for (list, merged) in self.lists.iter_mut().zip(merged_lists) {
+            let relinked = if merged {
+                &relinks.incoming
+            } else {
+                &relinks.existing
+            };
+            if !relinked.is_empty() {
+                relink_list(list, relinked, &self.levels);
+            }
+        }
+        self.normalise_level_order();
+
+        MergeReport { levels, lists }
+    }
+}
+
+/// Levels and lists, as far as merging is concerned.
+trait Mergeable: Clone {
+    fn name(&self) -> &str;
+    fn set_name(&mut self, name: String);
+    fn id(&self) -> i32;
+    /// Original ID, or the ID for items that were not copied
+    fn origin(&self) -> Option<i32>;
+    /// Version and revision
+    fn version(&self) -> (i32, i32);
+    /// Hash of everything but the savefile-specific properties (order and folder)
+    fn content_hash(&self) -> u64;
+    /// Copies the savefile-specific properties of `existing`, which this item replaces
+    fn keep_placement(&mut self, existing: &Self);
+}
+
+impl Mergeable for GDLevel {
+    fn name(&self) -> &str {
+        &self.identity.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.identity.name = name;
+    }
+
+    fn id(&self) -> i32 {
+        self.identity.id
+    }
+
+    fn origin(&self) -> Option<i32> {
+        origin(self.identity.original_id, self.identity.id)
+    }
+
+    fn version(&self) -> (i32, i32) {
+        (
+            self.identity.level_version,
+            self.identity.level_revision.unwrap_or_default(),
+        )
+    }
+
+    fn content_hash(&self) -> u64 {
+        content_hash(self.to_dict())
+    }
+
+    fn keep_placement(&mut self, existing: &Self) {
+        self.meta.level_order = existing.meta.level_order;
+        self.meta.level_folder = existing.meta.level_folder;
+    }
+}
+
+impl Mergeable for GDList {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn origin(&self) -> Option<i32> {
+        origin(self.original, self.id)
+    }
+
+    fn version(&self) -> (i32, i32) {
+        (self.version, self.list_revision)
+    }
+
+    fn content_hash(&self) -> u64 {
+        content_hash(self.to_dict())
+    }
+
+    fn keep_placement(&mut self, existing: &Self) {
+        self.order = existing.order;
+    }
+}
+
+fn origin(original_id: Option<i32>, id: i32) -> Option<i32> {
+    original_id
+        .filter(|id| *id != 0)
+        .or(Some(id))
+        .filter(|id| *id != 0)
+}
+
+fn content_hash(mut dict: Dictionary) -> u64 {
+    dict.retain(|k, _| !matches!(k.as_str(), "k83" | "k84"));
+    hash(&stringify_xml(&dict, false))
+}
+
+/// IDs of items that stand in for another item after merging.
+#[derive(Default)]
+struct Relinks {
+    /// Items of the other savefile that were skipped, mapped to our item that was kept
+    incoming: HashMap<i32, i32>,
+    /// Our items that were overwritten, mapped to the item that replaced them
+    existing: HashMap<i32, i32>,
+}
+
+/// Merges `theirs` into `ours` and records the IDs of items that were replaced by another item in `relinks`.
+///
+/// Also returns, for every item of the merged `ours`, whether it was taken from `theirs`.
+fn merge_items<T: Mergeable>(
+    ours: &mut Vec<T>,
+    theirs: &[T],
+    policy: MergePolicy,
+    relinks: &mut Relinks,
+) -> (Vec<MergeOutcome>, Vec<bool>) {
+    let hashes = ours.iter().map(T::content_hash).collect::<HashSet<_>>();
+    let link = |relinked: &mut HashMap<i32, i32>, from: i32, to: i32| {
+        if from != 0 && to != 0 && from != to {
+            relinked.insert(from, to);
+        }
+    };
+    let mut merged = vec![false; ours.len()];
+
+    let mut added: Vec<T> = vec![];
+    let mut outcomes = Vec::with_capacity(theirs.len());
+    for item in theirs {
+        if hashes.contains(&item.content_hash()) {
+            outcomes.push(MergeOutcome::Identical);
+            continue;
+        }
+
+        let duplicate = ours.iter().position(|existing| {
+            existing.name() == item.name()
+                || (item.origin().is_some() && existing.origin() == item.origin())
+        });
+        let Some(idx) = duplicate else {
+            added.push(item.clone());
+            outcomes.push(MergeOutcome::Added);
+            continue;
+        };
+
+        let overwrite = match policy {
+            MergePolicy::Skip => false,
+            MergePolicy::Overwrite => true,
+            MergePolicy::KeepNewest => item.version() > ours[idx].version(),
+            MergePolicy::Rename => {
+                let mut copy = item.clone();
+                copy.set_name(copy_name(item.name(), |name| {
+                    ours.iter().chain(&added).any(|i| i.name() == name)
+                }));
+                outcomes.push(MergeOutcome::Renamed(copy.name().to_owned()));
+                added.push(copy);
+                continue;
+            }
+        };
+
+        if overwrite {
+            link(&mut relinks.existing, ours[idx].id(), item.id());
+            let mut replacement = item.clone();
+            replacement.keep_placement(&ours[idx]);
+            ours[idx] = replacement;
+            merged[idx] = true;
+            outcomes.push(MergeOutcome::Overwritten);
+        } else {
+            link(&mut relinks.incoming, item.id(), ours[idx].id());
+            outcomes.push(MergeOutcome::Skipped);
+        }
+    }
+
+    merged.splice(0..0, vec![true; added.len()]);
+    ours.splice(0..0, added);
+    (outcomes, merged)
+}
+
+/// Points the level IDs and summaries of `list` at the levels that replaced them.
+fn relink_list(list: &mut GDList, relinked: &HashMap<i32, i32>, levels: &[GDLevel]) {
+    for id in &mut list.level_ids {
+        if let Some(new) = relinked.get(id) {
+            *id = *new;
+        }
+    }
+    for summary in &mut list.levels {
+        if let Some(new) = relinked.get(&summary.identity.id) {
+            summary.identity.id = *new;
+            if let Some(level) = levels.iter().find(|l| l.identity.id == *new) {
+                summary.identity.name.clone_from(&level.identity.name);
+            }
+        }
+    }
+}
+
+/// Maps every level to its key, its index and its serialised form for comparison.
+pub(crate) fn keyed_levels(levels: &[GDLevel]) -> HashMap<LevelKey, (usize, Dictionary)> {
+    let mut occurrences: HashMap<&str, usize> = HashMap::new();
+    let mut keyed = HashMap::with_capacity(levels.len());
+
+    // levels are stored newest first; count occurrences from the oldest level
+    // so that adding a level with an existing name doesn't change the other keys.
+    for (idx, level) in levels.iter().enumerate().rev() {
+        let occurrence = occurrences.entry(level.identity.name.as_str()).or_default();
+        keyed.insert(
+            LevelKey {
+                name: level.identity.name.clone(),
+                occurrence: *occurrence,
+            },
+            (idx, level.to_dict()),
+        );
+        *occurrence += 1;
+    }
+
+    keyed
+}
@@ -1,5 +1,12 @@
 //! This module contains all relevant objects to CCLocalLevels.dat, the savefile responsible for storing player level data.
+pub mod bulk;
+pub mod diff;
+pub mod fidelity;
+pub mod folders;
 pub mod gdlevel;
 pub mod gdlist;
 pub mod gdobj;
+pub mod lazy;
+pub mod manage;
+pub mod merge;
 pub mod properties;
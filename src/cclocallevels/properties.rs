@@ -37,12 +37,16 @@ pub static PROPERTY_TABLE: Map<u16, (&'static str, GDObjPropType)> = phf_map! {
     34u16 => ("Is group parent?", GDObjPropType::Bool),
     35u16 => ("Opacity", GDObjPropType::Float),
     36u16 => ("Is interactable?", GDObjPropType::Bool),
+    41u16 => ("Main HSV enabled", GDObjPropType::Bool),
+    42u16 => ("Detail HSV enabled", GDObjPropType::Bool),
+    43u16 => ("Main HSV", GDObjPropType::Text),
+    44u16 => ("Detail HSV", GDObjPropType::Text),
     45u16 => ("Pulse fade in time", GDObjPropType::Float),
     46u16 => ("Pulse hold time", GDObjPropType::Float),
     47u16 => ("Pulse fade out time", GDObjPropType::Float),
     48u16 => ("HSV is enabled", GDObjPropType::Bool),
     49u16 => ("Copy colour specs", GDObjPropType::Text),
-    50u16 => ("Copy colour from channel", GDObjPropType::Bool),
+    50u16 => ("Copy colour from channel", GDObjPropType::ColourChannel),
     51u16 => ("Target item", GDObjPropType::Group),
     52u16 => ("Pulse group?", GDObjPropType::Bool),
     56u16 => ("Activate group", GDObjPropType::Bool),
@@ -232,7 +236,7 @@ pub static PROPERTY_TABLE: Map<u16, (&'static str, GDObjPropType)> = phf_map! {
     595u16 => ("Don't stop song on death", GDObjPropType::Bool),
     10002u16 => ("Starting gamemode", GDObjPropType::Unknown),
     10003u16 => ("Starting in mini mode?", GDObjPropType::Bool),
-    10004u16 => ("Starting speed", GDObjPropType::Unknown),
+    10004u16 => ("Starting speed", GDObjPropType::Int),
     10008u16 => ("Starting in dual mode?", GDObjPropType::Bool),
     10019u16 => ("Target order", GDObjPropType::Unknown),
     10020u16 => ("Reverse gameplay?", GDObjPropType::Bool),
@@ -385,6 +389,8 @@ pub const OBJECT_NAMES: &[(i32, &str)] = &[
     (1007, "Trigger Alpha"),
     (1049, "Trigger Toggle"),
     (1268, "Trigger Spawn"),
+    (1329, "User coin"),
+    (1334, "Speed portal 4x"),
     (1346, "Trigger Rotation"),
     (1347, "Trigger Follow"),
     (1520, "Trigger Shake"),
@@ -415,6 +421,7 @@ pub const OBJECT_NAMES: &[(i32, &str)] = &[
     (3024, "Trigger Area stop"),
     (3031, "Trigger Middleground change"),
     (3600, "Trigger End"),
+    (3602, "Trigger SFX"),
     (3604, "Trigger Event"),
     (3606, "BG speed config"),
     (3608, "Trigger Spawn particle"),
@@ -1,11 +1,67 @@
 //! Cryptographic algorithms
 
+use aes::{
+    Aes256,
+    cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray},
+};
 use sha1::{Digest, Sha1};
 
+use crate::core::GDError;
+
 const PASSWORD_SALT: &str = "mI29fmAnxgTs";
 
+/// Key used by the macOS and iOS versions of GD to encrypt savefiles with AES-256-ECB.
+const APPLE_SAVEFILE_KEY: &[u8; 32] = b"ipu9TUv54yv]isFMh5@;t.5w34E2Ry@{";
+const AES_BLOCK_SIZE: usize = 16;
+
 /// Generate hash from given password according to the GJP2 cipher and return the hash as a hexdigest
 pub fn generate_gjp2_hexdigest(password: String) -> String {
     let salted = password + PASSWORD_SALT;
     hex::encode(Sha1::digest(salted.as_bytes()))
 }
+
+/// Decrypts a macOS/iOS savefile payload (AES-256-ECB with PKCS#7 padding) to the raw plist bytes.
+///
+/// Returns an error if the payload is not a whole number of AES blocks or if the padding is invalid,
+/// which usually means that the file is not an Apple savefile.
+pub fn decrypt_apple_savefile(mut data: Vec<u8>) -> Result<Vec<u8>, GDError> {
+    if data.is_empty() || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+        return Err(GDError::CorruptedSavefile(format!(
+            "AES payload length ({}) is not a multiple of the block size",
+            data.len()
+        )));
+    }
+
+    let cipher = Aes256::new(GenericArray::from_slice(APPLE_SAVEFILE_KEY));
+    for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    // strip PKCS#7 padding
+    let pad = *data.last().unwrap_or(&0) as usize;
+    if pad == 0
+        || pad > AES_BLOCK_SIZE
+        || data[data.len() - pad..].iter().any(|&b| b as usize != pad)
+    {
+        return Err(GDError::CorruptedSavefile(
+            "Invalid AES padding in savefile".into(),
+        ));
+    }
+    data.truncate(data.len() - pad);
+    Ok(data)
+}
+
+/// Encrypts raw plist bytes to the macOS/iOS savefile format (AES-256-ECB with PKCS#7 padding).
+#[must_use]
+pub fn encrypt_apple_savefile(data: &[u8]) -> Vec<u8> {
+    let pad = AES_BLOCK_SIZE - data.len() % AES_BLOCK_SIZE;
+    let mut encrypted = Vec::with_capacity(data.len() + pad);
+    encrypted.extend_from_slice(data);
+    encrypted.resize(data.len() + pad, pad as u8);
+
+    let cipher = Aes256::new(GenericArray::from_slice(APPLE_SAVEFILE_KEY));
+    for block in encrypted.chunks_exact_mut(AES_BLOCK_SIZE) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    encrypted
+}
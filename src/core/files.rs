@@ -0,0 +1,284 @@
+//! Filesystem helpers for savefiles: crash-safe writes, rotating backups and modification detection.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::core::GDError;
+
+/// Retention policy for timestamped savefile backups.
+///
+/// Backups are stored next to the savefile as `<file name>.<unix time in ms>.bak`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BackupPolicy {
+    /// Number of backups to keep. The oldest backups are deleted once this amount is exceeded.
+    /// A value of 0 disables backups entirely.
+    pub keep: usize,
+}
+
+impl BackupPolicy {
+    /// Policy that never creates backups.
+    pub const NONE: Self = Self { keep: 0 };
+
+    /// Policy that keeps the `keep` most recent backups.
+    #[must_use]
+    pub const fn keep(keep: usize) -> Self {
+        Self { keep }
+    }
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self::keep(5)
+    }
+}
+
+/// Options for writing an encrypted savefile to disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SaveOptions {
+    /// Backups to create before overwriting the savefile. See [`BackupPolicy`].
+    pub backups: BackupPolicy,
+    /// Overwrite the savefile even if it was modified by another program after it was loaded.
+    /// See [`SavefileFingerprint`].
+    pub force: bool,
+}
+
+impl SaveOptions {
+    /// Options that overwrite the savefile without creating any backups.
+    #[must_use]
+    pub fn without_backups() -> Self {
+        Self {
+            backups: BackupPolicy::NONE,
+            force: false,
+        }
+    }
+}
+
+/// Snapshot of a savefile on disk at the time that it was loaded.
+///
+/// This is used to detect whether GD (or another program) rewrote the savefile in the meantime,
+/// so that exporting does not silently discard those changes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SavefileFingerprint {
+    /// Location of the savefile
+    pub path: PathBuf,
+    /// Last modification time of the savefile, if the platform supports it
+    pub modified: Option<SystemTime>,
+    /// Size of the savefile in bytes
+    pub size: u64,
+    /// CRC32 checksum of the savefile contents
+    pub crc32: u32,
+}
+
+impl SavefileFingerprint {
+    /// Fingerprints `data`, which was just read from or written to `path`.
+    pub fn from_bytes<P: Into<PathBuf>>(path: P, data: &[u8]) -> Result<Self, GDError> {
+        let path = path.into();
+        let modified = fs::metadata(&path)?.modified().ok();
+        Ok(Self {
+            path,
+            modified,
+            size: data.len() as u64,
+            crc32: crc32fast::hash(data),
+        })
+    }
+
+    /// Reads the file at `path`, returning its contents and fingerprint.
+    pub fn read<P: Into<PathBuf>>(path: P) -> Result<(Vec<u8>, Self), GDError> {
+        let path = path.into();
+        let data = fs::read(&path)?;
+        let fingerprint = Self::from_bytes(path, &data)?;
+        Ok((data, fingerprint))
+    }
+
+    /// Returns whether the file on disk still matches this fingerprint.
+    ///
+    /// A file whose modification time changed but whose contents did not is still considered unchanged.
+    /// A file that no longer exists is considered unchanged as well, since there is nothing to overwrite.
+    pub fn is_current(&self) -> Result<bool, GDError> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        if metadata.len() != self.size {
+            return Ok(false);
+        }
+        if self.modified.is_some() && metadata.modified().ok() == self.modified {
+            return Ok(true);
+        }
+        Ok(crc32fast::hash(&fs::read(&self.path)?) == self.crc32)
+    }
+
+    /// Returns whether this is the fingerprint of the file at `path`, however either path is written
+    /// (e.g. relative or absolute, or through a symlink).
+    #[must_use]
+    pub fn is_for(&self, path: &Path) -> bool {
+        self.path == path
+            || canonical_path(&self.path).is_some_and(|own| canonical_path(path) == Some(own))
+    }
+
+    /// Returns [`GDError::ConcurrentModification`] if the file on disk no longer matches this fingerprint.
+    pub fn verify(&self) -> Result<(), GDError> {
+        match self.is_current()? {
+            true => Ok(()),
+            false => Err(GDError::ConcurrentModification(self.path.clone())),
+        }
+    }
+}
+
+/// Canonicalises `path`, or its parent directory if the file does not exist (yet).
+fn canonical_path(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path).ok().or_else(|| {
+        let parent = match path.parent()? {
+            p if p.as_os_str().is_empty() => Path::new("."),
+            p => p,
+        };
+        Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+    })
+}
+
+/// A timestamped backup of a savefile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Backup {
+    /// Location of the backup file
+    pub path: PathBuf,
+    /// Time that the backup was created
+    pub created: SystemTime,
+}
+
+/// Writes `data` to `path` without ever leaving a partially-written file at `path`.
+///
+/// The data is written to a temporary file in the same directory, flushed to disk,
+/// and then renamed over the target. If any step fails, the original file is left untouched.
+pub fn write_atomic<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), GDError> {
+    let path = path.as_ref();
+    let dir = parent_dir(path);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = (|| {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, path)?;
+        sync_dir(dir);
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Writes `data` to `path` atomically (see [`write_atomic`]), backing up the existing file according to `options` first.
+pub fn write_savefile<P: AsRef<Path>>(
+    path: P,
+    data: &[u8],
+    options: &SaveOptions,
+) -> Result<(), GDError> {
+    let path = path.as_ref();
+    if options.backups.keep > 0 && path.exists() {
+        create_backup(path)?;
+        prune_backups(path, options.backups.keep)?;
+    }
+    write_atomic(path, data)
+}
+
+/// Copies the savefile at `path` to a new timestamped backup and returns it.
+pub fn create_backup<P: AsRef<Path>>(path: P) -> Result<Backup, GDError> {
+    let path = path.as_ref();
+    let mut created = SystemTime::now();
+    // two saves in the same millisecond must not share a backup
+    let mut backup_path = backup_path_for(path, created);
+    while backup_path.exists() {
+        created += Duration::from_millis(1);
+        backup_path = backup_path_for(path, created);
+    }
+
+    write_atomic(&backup_path, &fs::read(path)?)?;
+    Ok(Backup {
+        path: backup_path,
+        created,
+    })
+}
+
+/// Returns all timestamped backups of the savefile at `path`, newest first.
+pub fn list_backups<P: AsRef<Path>>(path: P) -> Result<Vec<Backup>, GDError> {
+    let path = path.as_ref();
+    let Some(file_name) = path.file_name().map(|f| f.to_string_lossy().into_owned()) else {
+        return Ok(vec![]);
+    };
+    let prefix = format!("{file_name}.");
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(parent_dir(path))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(millis) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".bak"))
+            .and_then(|ts| ts.parse::<u64>().ok())
+        {
+            backups.push(Backup {
+                path: entry.path(),
+                created: UNIX_EPOCH + Duration::from_millis(millis),
+            });
+        }
+    }
+
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created));
+    Ok(backups)
+}
+
+/// Restores `backup` over the savefile at `path`. The restore itself is atomic.
+pub fn restore_backup<P: AsRef<Path>>(path: P, backup: &Backup) -> Result<(), GDError> {
+    write_atomic(path, &fs::read(&backup.path)?)
+}
+
+/// Deletes all but the `keep` newest backups of the savefile at `path`.
+pub fn prune_backups<P: AsRef<Path>>(path: P, keep: usize) -> Result<(), GDError> {
+    for backup in list_backups(path)?.into_iter().skip(keep) {
+        fs::remove_file(backup.path)?;
+    }
+    Ok(())
+}
+
+fn backup_path_for(path: &Path, time: SystemTime) -> PathBuf {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{millis}.bak"));
+    PathBuf::from(name)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+// makes the rename itself durable. not possible on windows, where directories can't be opened as files
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
@@ -0,0 +1,362 @@
+//! Byte-level scanning and parsing of GD's abbreviated plist dialect (`<k>`, `<s>`, `<d>` and friends).
+//!
+//! The scanner locates elements without decoding them, so that the original text of a savefile can be reused.
+//! The parser builds values straight from the decrypted text, without rewriting the tags for the plist crate first.
+//! Documents it rejects (e.g. ones with comments or CDATA sections) are handed to the plist crate instead.
+
+use std::{borrow::Cow, io::Cursor, ops::Range};
+
+use base64::{Engine, engine::general_purpose};
+use plist::{Date, Dictionary, Integer, Value};
+
+use crate::core::{
+    GDError,
+    parse::PlistParser,
+    rewrite_plist_tags,
+    values::{describe, invalid},
+};
+
+/// A key-value pair of a dictionary, as byte ranges into the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RawEntry {
+    /// The key's text without its tags. Entities are not decoded.
+    pub key: Range<usize>,
+    /// The whole entry, from the opening key tag to the end of the value
+    pub entry: Range<usize>,
+    /// The value element, including its tags
+    pub value: Range<usize>,
+}
+
+/// A single tag.
+struct Tag<'a> {
+    name: &'a str,
+    /// Position after the tag's `>`
+    end: usize,
+    closing: bool,
+    self_closing: bool,
+}
+
+fn corrupted(what: &str, pos: usize) -> GDError {
+    GDError::CorruptedSavefile(format!("Malformed plist: {what} at byte {pos}"))
+}
+
+fn skip_whitespace(xml: &str, pos: usize) -> usize {
+    pos + xml
+        .get(pos..)
+        .map_or(0, |rest| rest.len() - rest.trim_start().len())
+}
+
+fn read_tag(xml: &str, pos: usize) -> Result<Tag<'_>, GDError> {
+    let rest = xml
+        .get(pos..)
+        .filter(|r| r.starts_with('<'))
+        .ok_or_else(|| corrupted("expected a tag", pos))?;
+    let len = rest
+        .find('>')
+        .ok_or_else(|| corrupted("unterminated tag", pos))?;
+    let inner = &rest[1..len];
+
+    let closing = inner.starts_with('/');
+    let self_closing = inner.ends_with('/');
+    let name = inner
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+
+    Ok(Tag {
+        name,
+        end: pos + len + 1,
+        closing,
+        self_closing,
+    })
+}
+
+fn is_container(name: &str) -> bool {
+    matches!(name, "d" | "dict" | "array")
+}
+
+fn is_key(name: &str) -> bool {
+    matches!(name, "k" | "key")
+}
+
+/// Returns the end of the element that starts at `pos`.
+///
+/// The content of leaf elements (strings, keys, numbers) is skipped without being tokenized, so stray `<`s in strings
+/// do not confuse the scanner.
+pub(crate) fn element_end(xml: &str, pos: usize) -> Result<usize, GDError> {
+    let tag = read_tag(xml, pos)?;
+    if tag.closing {
+        return Err(corrupted("unexpected closing tag", pos));
+    }
+    if tag.self_closing {
+        return Ok(tag.end);
+    }
+
+    if is_container(tag.name) {
+        let mut p = tag.end;
+        loop {
+            p = skip_whitespace(xml, p);
+            if xml[p..].starts_with("</") {
+                let close = read_tag(xml, p)?;
+                if close.name != tag.name {
+                    return Err(corrupted("mismatched closing tag", p));
+                }
+                return Ok(close.end);
+            }
+            p = element_end(xml, p)?;
+        }
+    }
+
+    let close = format!("</{}>", tag.name);
+    xml[tag.end..]
+        .find(&close)
+        .map(|len| tag.end + len + close.len())
+        .ok_or_else(|| corrupted("unterminated element", pos))
+}
+
+/// Returns the content of the dictionary element spanning `value`, or `None` if it is not a dictionary.
+pub(crate) fn dict_content(xml: &str, value: Range<usize>) -> Option<Range<usize>> {
+    let tag = read_tag(xml, value.start).ok()?;
+    if !matches!(tag.name, "d" | "dict") || tag.closing {
+        return None;
+    }
+    if tag.self_closing {
+        return Some(tag.end..tag.end);
+    }
+    Some(tag.end..value.end - tag.name.len() - 3)
+}
+
+/// Returns every entry of the dictionary content spanning `content`, in document order.
+pub(crate) fn dict_entries(xml: &str, content: Range<usize>) -> Result<Vec<RawEntry>, GDError> {
+    let mut entries = vec![];
+    let mut p = skip_whitespace(xml, content.start);
+
+    while p < content.end {
+        let tag = read_tag(xml, p)?;
+        if !is_key(tag.name) || tag.closing {
+            return Err(corrupted("expected a key", p));
+        }
+        let key_end = if tag.self_closing {
+            tag.end
+        } else {
+            let close = if tag.name == "k" { "</k>" } else { "</key>" };
+            let len = xml[tag.end..]
+                .find(close)
+                .ok_or_else(|| corrupted("unterminated key", p))?;
+            tag.end + len + close.len()
+        };
+        let key = if tag.self_closing {
+            tag.end..tag.end
+        } else {
+            tag.end..key_end - tag.name.len() - 3
+        };
+
+        let value_start = skip_whitespace(xml, key_end);
+        let value_end = element_end(xml, value_start)?;
+        entries.push(RawEntry {
+            key,
+            entry: p..value_end,
+            value: value_start..value_end,
+        });
+        p = skip_whitespace(xml, value_end);
+    }
+
+    Ok(entries)
+}
+
+/// Returns the span of the root element of a plist document.
+pub(crate) fn root_value(xml: &str) -> Result<Range<usize>, GDError> {
+    let plist = xml
+        .find("<plist")
+        .ok_or_else(|| corrupted("missing <plist> element", 0))?;
+    let root_start = skip_whitespace(xml, read_tag(xml, plist)?.end);
+    Ok(root_start..element_end(xml, root_start)?)
+}
+
+/// Returns the content range and every entry of the root dictionary of a plist document.
+pub(crate) fn root_entries(xml: &str) -> Result<(Range<usize>, Vec<RawEntry>), GDError> {
+    let root = root_value(xml)?;
+    let content = dict_content(xml, root.clone())
+        .ok_or_else(|| corrupted("root element is not a dictionary", root.start))?;
+    Ok((content.clone(), dict_entries(xml, content)?))
+}
+
+/// Returns the decoded key of `entry`.
+pub(crate) fn entry_key<'a>(xml: &'a str, entry: &RawEntry) -> Result<Cow<'a, str>, GDError> {
+    decode_text(xml, entry.key.clone())
+}
+
+/// Calls `f` with the decoded key and parsed value of every entry of the dictionary spanning `value`, in document
+/// order. Returns [`GDError::InvalidValue`] if `value` is not a dictionary.
+///
+/// Only one value is alive at a time, so a level can be read without building its whole dictionary first.
+pub(crate) fn for_each_entry<F: FnMut(&str, Value) -> Result<(), GDError>>(
+    xml: &str,
+    value: Range<usize>,
+    mut f: F,
+) -> Result<(), GDError> {
+    let content = match dict_content(xml, value.clone()) {
+        Some(content) => content,
+        None => return Err(invalid("dictionary", describe(&parse_value(xml, value)?))),
+    };
+    for entry in dict_entries(xml, content)? {
+        f(&entry_key(xml, &entry)?, parse_value(xml, entry.value)?)?;
+    }
+    Ok(())
+}
+
+/// Parses the element spanning `value` (as returned by [`element_end`]) into a plist value.
+pub(crate) fn parse_value(xml: &str, value: Range<usize>) -> Result<Value, GDError> {
+    let tag = read_tag(xml, value.start)?;
+    if tag.closing {
+        return Err(corrupted("unexpected closing tag", value.start));
+    }
+    let content = if tag.self_closing {
+        tag.end..tag.end
+    } else {
+        tag.end..value.end - tag.name.len() - 3
+    };
+    let text = || decode_text(xml, content.clone());
+
+    Ok(match tag.name {
+        "s" | "string" => Value::String(text()?.into_owned()),
+        "i" | "integer" => Value::Integer(
+            parse_integer(text()?.trim())
+                .ok_or_else(|| corrupted("invalid integer", value.start))?,
+        ),
+        "r" | "real" => Value::Real(
+            text()?
+                .trim()
+                .parse()
+                .map_err(|_| corrupted("invalid real", value.start))?,
+        ),
+        "t" | "true" => Value::Boolean(true),
+        "f" | "false" => Value::Boolean(false),
+        "d" | "dict" => {
+            let mut dict = Dictionary::new();
+            for entry in dict_entries(xml, content)? {
+                dict.insert(
+                    entry_key(xml, &entry)?.into_owned(),
+                    parse_value(xml, entry.value)?,
+                );
+            }
+            Value::Dictionary(dict)
+        }
+        "array" => {
+            let mut array = vec![];
+            let mut p = skip_whitespace(xml, content.start);
+            while p < content.end {
+                let end = element_end(xml, p)?;
+                array.push(parse_value(xml, p..end)?);
+                p = skip_whitespace(xml, end);
+            }
+            Value::Array(array)
+        }
+        "date" => Value::Date(
+            Date::from_xml_format(text()?.trim())
+                .map_err(|_| corrupted("invalid date", value.start))?,
+        ),
+        "data" => {
+            let mut encoded = text()?.into_owned();
+            encoded.retain(|c| !c.is_ascii_whitespace());
+            Value::Data(
+                general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|_| corrupted("invalid data", value.start))?,
+            )
+        }
+        _ => return Err(corrupted("unknown element", value.start)),
+    })
+}
+
+/// Parses a whole plist document into its root value.
+pub(crate) fn parse_document(xml: &str) -> Result<Value, GDError> {
+    parse_value(xml, root_value(xml)?)
+}
+
+/// Parses `xml` with `native` if `parser` is [`PlistParser::Native`]. If that parser rejects the document as
+/// malformed, or if `parser` is [`PlistParser::PlistCrate`], the tags are rewritten and the document is parsed with
+/// the plist crate, whose root value is passed to `fallback`.
+pub(crate) fn parse_with_fallback<T>(
+    xml: &str,
+    parser: PlistParser,
+    native: impl FnOnce(&str) -> Result<T, GDError>,
+    fallback: impl FnOnce(Value) -> Result<T, GDError>,
+) -> Result<T, GDError> {
+    if parser == PlistParser::Native {
+        match native(xml) {
+            Err(GDError::CorruptedSavefile(_)) => {}
+            parsed => return parsed,
+        }
+    }
+    let rewritten = rewrite_plist_tags(xml)?;
+    fallback(Value::from_reader_xml(Cursor::new(rewritten.as_bytes()))?)
+}
+
+/// Parses integers the same way as the plist crate: as an `i64` if possible, otherwise as a `u64`.
+/// Hexadecimal integers with a `0x` prefix are unsigned.
+fn parse_integer(s: &str) -> Option<Integer> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok().map(Integer::from);
+    }
+    match s.parse::<i64>() {
+        Ok(n) => Some(n.into()),
+        Err(_) => s.parse::<u64>().ok().map(Integer::from),
+    }
+}
+
+/// Decodes the entities in the text spanning `range`.
+///
+/// Unescaped `&`s that do not start an entity (as written by older versions of this crate) are kept as they are.
+/// Markup inside text, such as CDATA sections, is rejected so that the plist crate can handle it.
+fn decode_text(xml: &str, range: Range<usize>) -> Result<Cow<'_, str>, GDError> {
+    let text = &xml[range.clone()];
+    if text.contains("<!") || text.contains("<?") {
+        return Err(corrupted("markup inside text", range.start));
+    }
+    if !text.contains('&') {
+        return Ok(Cow::Borrowed(text));
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        match decode_entity(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(Cow::Owned(out))
+}
+
+/// Decodes the entity at the start of `s`, returning the character and the length of the entity.
+fn decode_entity(s: &str) -> Option<(char, usize)> {
+    let end = s.char_indices().take(12).find(|(_, c)| *c == ';')?.0;
+    let c = match &s[1..end] {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        name => {
+            let num = name.strip_prefix('#')?;
+            let code = match num.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => num.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some((c, end + 1))
+}
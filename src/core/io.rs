@@ -1,54 +1,120 @@
 //! This module contains all of the de/serialization code for GD savefiles.
 use std::{
     fmt::Write,
-    fs,
-    io::{Read, Write as IoWrite},
+    io::{BufRead, BufReader, Read, Write as IoWrite},
     path::PathBuf,
 };
 
-use crate::core::{GDError, b64_encode};
-use base64::{Engine, engine::general_purpose};
-use flate2::{Compression, read::DeflateDecoder, write::ZlibEncoder};
+use crate::core::{
+    GDError,
+    crypto::{decrypt_apple_savefile, encrypt_apple_savefile},
+    files::SavefileFingerprint,
+    stream::{GdDecryptReader, GdEncryptWriter, check_gzip_trailer, read_gzip_header},
+};
+use base64::{
+    Engine,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig, general_purpose},
+};
+use flate2::read::ZlibDecoder;
 use plist::{Dictionary, Value};
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
-fn zlib_compress(s: &str) -> Vec<u8> {
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(s.as_bytes()).unwrap();
-    encoder.finish().unwrap()
+/// Start of every XOR-11 encrypted savefile: `H4sI` (the base64-encoded gzip magic) xored with 11.
+const WINDOWS_SAVEFILE_MAGIC: &[u8; 4] = b"C?xB";
+
+/// Encryption scheme that a GD savefile was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SavefilePlatform {
+    /// XOR with key 11 over base64-encoded gzip. Used on Windows, Linux (through Proton) and Android.
+    #[default]
+    Windows,
+    /// AES-256-ECB over the raw plist. Used on macOS and iOS.
+    Apple,
+}
+
+impl SavefilePlatform {
+    /// Detects the platform of an encrypted savefile payload.
+    ///
+    /// Windows savefiles are recognised by their gzip magic, or by consisting only of XORed base64 text. Apple
+    /// savefiles are AES blocks, which are mostly not text. Returns `None` if the payload matches neither format,
+    /// such as a damaged Windows savefile whose length happens to be a multiple of 16.
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        }
+        let text = windows_text_len(data);
+        if data.starts_with(WINDOWS_SAVEFILE_MAGIC) || text == data.len() {
+            Some(Self::Windows)
+        } else if data.len().is_multiple_of(16) && text < data.len() / 2 {
+            Some(Self::Apple)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns how many bytes of `data` are base64, padding, whitespace or NUL after XORing with 11, as in a Windows
+/// savefile. About a quarter of the bytes of an Apple savefile are.
+fn windows_text_len(data: &[u8]) -> usize {
+    data.iter()
+        .map(|b| b ^ 11)
+        .filter(|c| c.is_ascii_alphanumeric() || b"+/-_=\0".contains(c) || c.is_ascii_whitespace())
+        .count()
 }
 
 /// Returns the encrypted level string as `Vec<u8>` from a `GDLevel` object string
 #[must_use]
 pub fn encrypt_level_str(s: &str) -> Vec<u8> {
-    let compress = zlib_compress(s);
-    let mut data = b"H4sIAAAAAA".to_vec();
-    data.extend_from_slice(&compress[2..compress.len() - 4]);
-    let crc_checksum = crc32fast::hash(s.as_bytes()).to_le_bytes();
-    let size = s.len().to_le_bytes();
-
-    data.extend_from_slice(&crc_checksum);
-    data.extend_from_slice(&size);
-    let base64 = b64_encode(data);
-
-    let mut header = b"H4sIAAAAAAAAC".to_vec();
-    header.extend_from_slice(&base64.as_bytes()[13..]);
-    header
+    encrypt_with(
+        GdEncryptWriter::without_xor(Vec::with_capacity(s.len() / 2)),
+        s,
+    )
 }
 
 /// Returns the encrypted savefile string from a stringified `Levels` struct
 #[must_use]
 pub fn encrypt_savefile_str(s: &str) -> Vec<u8> {
-    let mut encrypted = encrypt_level_str(s);
-    #[cfg(feature = "parallel")]
-    encrypted.par_iter_mut().for_each(|c| *c ^= 0x000b);
+    encrypt_with(GdEncryptWriter::new(Vec::with_capacity(s.len() / 2)), s)
+}
 
-    #[cfg(not(feature = "parallel"))]
-    for byte in &mut encrypted {
-        *byte ^= 0x000b;
+fn encrypt_with(mut writer: GdEncryptWriter<Vec<u8>>, s: &str) -> Vec<u8> {
+    // writing to a `Vec` cannot fail
+    writer
+        .write_all(s.as_bytes())
+        .and_then(|()| writer.finish())
+        .expect("writing to a Vec failed")
+}
+
+/// Returns the encrypted savefile string from a stringified `Levels` struct for the given platform.
+#[must_use]
+pub fn encrypt_savefile_str_for(s: &str, platform: SavefilePlatform) -> Vec<u8> {
+    match platform {
+        SavefilePlatform::Windows => encrypt_savefile_str(s),
+        SavefilePlatform::Apple => encrypt_apple_savefile(s.as_bytes()),
+    }
+}
+
+/// Encrypts a stringified `Levels` struct for the given platform and writes it to `writer`, returning the writer.
+///
+/// Windows savefiles are encrypted while they are written, see [`GdEncryptWriter`], so only `s` is held in memory.
+pub fn encrypt_savefile_to<W: IoWrite>(
+    s: &str,
+    platform: SavefilePlatform,
+    mut writer: W,
+) -> Result<W, GDError> {
+    match platform {
+        SavefilePlatform::Windows => {
+            let mut encrypted = GdEncryptWriter::new(writer);
+            encrypted.write_all(s.as_bytes())?;
+            Ok(encrypted.finish()?)
+        }
+        SavefilePlatform::Apple => {
+            writer.write_all(&encrypt_apple_savefile(s.as_bytes()))?;
+            Ok(writer)
+        }
     }
-    encrypted
 }
 
 /// Parses an XML dictionary to a string that matches GD savefile format.
@@ -71,29 +137,10 @@ pub fn stringify_xml(dict: &Dictionary, root: bool) -> String {
     });
 
     for (key, value) in dict {
-        let _ = write!(dict_str, "<k>{key}</k>");
-        match value {
-            Value::String(s) => {
-                let _ = write!(dict_str, "<s>{s}</s>");
-            }
-            Value::Integer(int) => {
-                let _ = write!(dict_str, "<i>{int}</i>");
-            }
-            Value::Dictionary(dict) => {
-                dict_str.push_str(&stringify_xml(dict, false));
-            }
-            Value::Boolean(b) => {
-                if *b {
-                    dict_str.push_str("<t />");
-                } else {
-                    dict_str.push_str("<f />");
-                }
-            }
-            Value::Real(float) => {
-                let _ = write!(dict_str, "<r>{float}</r>");
-            }
-            _ => {}
-        }
+        dict_str.push_str("<k>");
+        escape_xml_into(&mut dict_str, key);
+        dict_str.push_str("</k>");
+        write_xml_value(&mut dict_str, value);
     }
 
     dict_str.push_str(match root {
@@ -103,28 +150,286 @@ pub fn stringify_xml(dict: &Dictionary, root: bool) -> String {
     dict_str
 }
 
+/// Appends a single plist value in GD's abbreviated format.
+pub(crate) fn write_xml_value(out: &mut String, value: &Value) {
+    match value {
+        Value::String(s) => {
+            out.push_str("<s>");
+            escape_xml_into(out, s);
+            out.push_str("</s>");
+        }
+        Value::Integer(int) => {
+            let _ = write!(out, "<i>{int}</i>");
+        }
+        Value::Dictionary(dict) => {
+            out.push_str(&stringify_xml(dict, false));
+        }
+        Value::Boolean(b) => {
+            if *b {
+                out.push_str("<t />");
+            } else {
+                out.push_str("<f />");
+            }
+        }
+        Value::Real(float) => {
+            let _ = write!(out, "<r>{}</r>", format_real(*float));
+        }
+        // GD itself stores lists as `_isArr` dicts, but plain plist values are kept intact
+        Value::Array(values) => {
+            out.push_str("<array>");
+            for v in values {
+                write_xml_value(out, v);
+            }
+            out.push_str("</array>");
+        }
+        Value::Date(date) => {
+            let _ = write!(out, "<date>{}</date>", date.to_xml_format());
+        }
+        Value::Data(data) => {
+            let _ = write!(
+                out,
+                "<data>{}</data>",
+                general_purpose::STANDARD.encode(data)
+            );
+        }
+        Value::Uid(uid) => {
+            let _ = write!(out, "<d><k>CF$UID</k><i>{}</i></d>", uid.get());
+        }
+        _ => {}
+    }
+}
+
+/// Appends `s` to `out`, escaping it the same way as GD: `&`, `<` and `>` become entities,
+/// as do control characters other than tabs and newlines.
+pub(crate) fn escape_xml_into(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "&#{};", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Formats a float in the layout of GD's (C's `%g`): no trailing zeros, switching to scientific notation for very
+/// large or small numbers. Unlike `%g`, which keeps 6 significant digits, the shortest digits that parse back to
+/// exactly `f` are written, so values survive a load/save cycle.
+#[must_use]
+pub fn format_real(f: f64) -> String {
+    /// Significant digits that an `f64` may need to round-trip; larger exponents use scientific notation
+    const MAX_DIGITS: i32 = 17;
+
+    if f.is_nan() {
+        return "nan".to_owned();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+    if f == 0.0 {
+        return if f.is_sign_negative() { "-0" } else { "0" }.to_owned();
+    }
+
+    // `{:e}` and `{}` both write the shortest digits that round-trip
+    let sci = format!("{f:e}");
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let exp = exp.parse::<i32>().unwrap_or(0);
+
+    if (-4..MAX_DIGITS).contains(&exp) {
+        f.to_string()
+    } else {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exp.abs())
+    }
+}
+
+/// Repairs text content that is not valid XML, which older versions of this crate wrote for strings containing
+/// `&`, `<` or `>`. Stray `&`s that do not start an entity are escaped, as are `<` and `>` inside `<string>`
+/// and `<key>` elements. Files without such content are returned unchanged.
+pub(crate) fn repair_xml_entities(s: String) -> String {
+    const ELEMENTS: [(&str, &str); 2] = [("<string>", "</string>"), ("<key>", "</key>")];
+
+    let mut repaired: Option<String> = None;
+    let mut copied = 0;
+    let mut pos = 0;
+    // start of the next occurrence of each element's content; only searched again once passed,
+    // so that the whole file is scanned once per element
+    let find = |pos: usize, open: &str| s[pos..].find(open).map(|idx| pos + idx + open.len());
+    let mut next = ELEMENTS.map(|(open, _)| find(0, open));
+
+    loop {
+        for (n, (open, _)) in next.iter_mut().zip(ELEMENTS) {
+            if n.is_some_and(|n| n < pos) {
+                *n = find(pos, open);
+            }
+        }
+        let Some((content_start, close)) = next
+            .iter()
+            .zip(ELEMENTS)
+            .filter_map(|(n, (_, close))| n.map(|n| (n, close)))
+            .min_by_key(|(n, _)| *n)
+        else {
+            break;
+        };
+        let Some(len) = s[content_start..].find(close) else {
+            break;
+        };
+        let content_end = content_start + len;
+        let content = &s[content_start..content_end];
+
+        if needs_repair(content) {
+            let out = repaired.get_or_insert_with(|| String::with_capacity(s.len() + 64));
+            out.push_str(&s[copied..content_start]);
+            repair_text_into(out, content);
+            copied = content_end;
+        }
+        pos = content_end + close.len();
+    }
+
+    match repaired {
+        Some(mut out) => {
+            out.push_str(&s[copied..]);
+            out
+        }
+        None => s,
+    }
+}
+
+fn needs_repair(content: &str) -> bool {
+    content.contains(['<', '>'])
+        || content
+            .match_indices('&')
+            .any(|(idx, _)| entity_len(&content[idx..]).is_none())
+}
+
+fn repair_text_into(out: &mut String, content: &str) {
+    let mut rest = content;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '&' => match entity_len(rest) {
+                Some(len) => {
+                    out.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                None => out.push_str("&amp;"),
+            },
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+/// Returns the length of the XML entity at the start of `s`, if `s` starts with a valid one.
+fn entity_len(s: &str) -> Option<usize> {
+    let (end, _) = s.char_indices().take(12).find(|(_, c)| *c == ';')?;
+    let name = &s[1..end];
+    let valid = match name.strip_prefix('#') {
+        Some(num) => match num.strip_prefix('x') {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+    };
+    valid.then_some(end + 1)
+}
+
 // ------------ deserialiser ------------
 
-/// Decompresses a `Vec<u8>` of a base64ed gzip-compressed payload.
+/// Compression container of a payload, as detected by [`decompress_detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionContainer {
+    /// Gzip stream (`H4sI…` once base64-encoded). This is what GD writes.
+    Gzip,
+    /// Zlib stream (`eJ…` once base64-encoded)
+    Zlib,
+    /// Raw deflate stream without any header or trailer
+    RawDeflate,
+}
+
+/// Base64 decoder that accepts both padded and unpadded input.
+fn base64_engine(alphabet: &base64::alphabet::Alphabet) -> GeneralPurpose {
+    GeneralPurpose::new(
+        alphabet,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    )
+}
+
+/// Decompresses a base64-encoded compressed payload, such as level data or a decrypted savefile.
 ///
 /// # Arguments
 /// * `data`: compressed input data
 ///
-/// Returns decompressed base64ed gzip as a `Vec<u8>` if it sucessfully decoded, otherwise returns `GDError`
-pub fn decompress(mut data: Vec<u8>) -> Result<Vec<u8>, GDError> {
-    data.retain(|c| *c != 0);
+/// Returns the decompressed payload as a `Vec<u8>` if it sucessfully decoded, otherwise returns `GDError`.
+/// See [`decompress_detect`] for the supported formats.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>, GDError> {
+    decompress_detect(data).map(|(decompressed, _)| decompressed)
+}
 
-    // decode DEFLATE payload
-    let decoded = general_purpose::URL_SAFE.decode(data)?;
-    // remove gzip header
-    let sliced = &decoded[10..];
+/// Decompresses a compressed payload, detecting its encoding and container.
+///
+/// The payload may be URL-safe or standard base64 (padded or not), or unencoded gzip. The container may be gzip
+/// (with any optional header fields), zlib or raw deflate. The CRC32 and size in a gzip trailer are checked against the
+/// decompressed data; a mismatch is a [`GDError::GzipTrailerMismatch`].
+///
+/// Returns the decompressed payload and the container that it was stored in.
+pub fn decompress_detect(mut data: Vec<u8>) -> Result<(Vec<u8>, CompressionContainer), GDError> {
+    let decoded = if data.starts_with(&[0x1f, 0x8b]) {
+        data
+    } else {
+        data.retain(|c| *c != 0 && !c.is_ascii_whitespace());
+        let alphabet = match data.iter().any(|c| matches!(c, b'+' | b'/')) {
+            true => &base64::alphabet::STANDARD,
+            false => &base64::alphabet::URL_SAFE,
+        };
+        base64_engine(alphabet).decode(data)?
+    };
 
-    let mut decoder = DeflateDecoder::new(sliced);
-    let mut decompressed_buf = Vec::with_capacity(decoder.total_out() as usize);
+    match decoded.as_slice() {
+        [0x1f, 0x8b, ..] => Ok((gunzip(&decoded)?, CompressionContainer::Gzip)),
+        [cmf, flg, ..] if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 => {
+            // a raw deflate stream can start with a valid zlib header by chance
+            let mut decompressed = vec![];
+            match ZlibDecoder::new(decoded.as_slice()).read_to_end(&mut decompressed) {
+                Ok(_) => Ok((decompressed, CompressionContainer::Zlib)),
+                Err(_) => Ok((inflate(&decoded)?.0, CompressionContainer::RawDeflate)),
+            }
+        }
+        _ => Ok((inflate(&decoded)?.0, CompressionContainer::RawDeflate)),
+    }
+}
 
-    decoder.read_to_end(&mut decompressed_buf)?;
+/// Inflates a raw deflate stream, returning the decompressed data and the number of bytes of `data` it spans.
+fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), GDError> {
+    let mut decoder = flate2::bufread::DeflateDecoder::new(data);
+    let mut decompressed = Vec::with_capacity(data.len() * 4);
+    decoder.read_to_end(&mut decompressed)?;
+    Ok((decompressed, decoder.total_in() as usize))
+}
 
-    Ok(decompressed_buf)
+/// Decompresses a gzip stream, skipping its optional header fields and validating its trailer.
+fn gunzip(mut data: &[u8]) -> Result<Vec<u8>, GDError> {
+    read_gzip_header(&mut data)?;
+    let (decompressed, len) = inflate(data)?;
+    // anything after the trailer (e.g. the oversized trailer written by older versions of this crate) is ignored
+    let trailer = data
+        .get(len..len + 8)
+        .and_then(|t| <[u8; 8]>::try_from(t).ok())
+        .ok_or_else(|| {
+            GDError::CorruptedSavefile("Gzip stream is missing its CRC32 and size trailer".into())
+        })?;
+    check_gzip_trailer(
+        trailer,
+        crc32fast::hash(&decompressed),
+        decompressed.len() as u32,
+    )?;
+    Ok(decompressed)
 }
 
 /// Decrypts data by xoring with key 11 and decompressing it.
@@ -135,7 +440,7 @@ pub fn decompress(mut data: Vec<u8>) -> Result<Vec<u8>, GDError> {
 ///
 /// Returns the raw file contents as a `Vec<u8>`
 #[inline]
-#[must_use]
+#[must_use = "the decrypted savefile is returned rather than written anywhere"]
 pub fn decrypt(mut data: Vec<u8>) -> Result<Vec<u8>, GDError> {
     #[cfg(feature = "parallel")]
     data.par_iter_mut().for_each(|c| *c ^= 0x000b);
@@ -147,20 +452,71 @@ pub fn decrypt(mut data: Vec<u8>) -> Result<Vec<u8>, GDError> {
     decompress(data)
 }
 
-/// Returns CCLocalLevels.dat decrypted if it exists
+/// Decrypts a savefile payload of either platform, detecting the platform from the payload itself.
+///
+/// Returns the raw file contents and the platform that the payload was encrypted for.
+pub fn decrypt_savefile(data: Vec<u8>) -> Result<(Vec<u8>, SavefilePlatform), GDError> {
+    match SavefilePlatform::detect(&data) {
+        // the whole payload is already in memory, so `decompress` can fall back from zlib to raw deflate
+        Some(SavefilePlatform::Windows) => Ok((decrypt(data)?, SavefilePlatform::Windows)),
+        Some(SavefilePlatform::Apple) => {
+            Ok((decrypt_apple_savefile(data)?, SavefilePlatform::Apple))
+        }
+        None if data.is_empty() => Err(GDError::CorruptedSavefile("Savefile is empty".into())),
+        None if windows_text_len(&data) >= data.len() / 2 => Err(GDError::CorruptedSavefile(
+            "Savefile looks like a damaged Windows savefile: it contains bytes that are not XORed base64".into(),
+        )),
+        None => Err(GDError::CorruptedSavefile(
+            "Savefile format is unknown: it is neither a Windows nor an Apple savefile".into(),
+        )),
+    }
+}
+
+/// Returns the savefile decrypted if it exists.
+///
+/// Both Windows/Linux and macOS/iOS savefiles are supported; the platform is detected automatically.
 pub fn decrypt_file(file: PathBuf) -> Result<String, GDError> {
-    let savefile = match fs::read(file) {
-        Ok(v) => v,
-        Err(e) => return Err(GDError::Io(e)),
-    };
-    let mut data = decrypt(savefile)?;
-    data.retain(|c| *c < 128); // sometimes there are some weird chars in the ccgamemanager file
+    decrypt_file_with_platform(file).map(|(s, _)| s)
+}
+
+/// Like [`decrypt_file`], but also returns the platform that the savefile was encrypted for.
+pub fn decrypt_file_with_platform(file: PathBuf) -> Result<(String, SavefilePlatform), GDError> {
+    decrypt_file_with_fingerprint(file).map(|(s, platform, _)| (s, platform))
+}
 
+/// Like [`decrypt_file_with_platform`], but also returns the fingerprint of the savefile as it was read.
+/// The fingerprint can be used to detect whether the savefile was modified afterwards.
+pub fn decrypt_file_with_fingerprint(
+    file: PathBuf,
+) -> Result<(String, SavefilePlatform, SavefileFingerprint), GDError> {
+    let (savefile, fingerprint) = SavefileFingerprint::read(file)?;
+    let (data, platform) = decrypt_savefile(savefile)?;
+    Ok((decrypted_str(data)?, platform, fingerprint))
+}
+
+/// Decrypts a savefile of either platform from `reader`, returning its contents and the platform it was encrypted for.
+///
+/// Windows savefiles are decrypted while they are read, see [`GdDecryptReader`], so the encrypted savefile is never
+/// held in memory as a whole; the decrypted savefile is. Apple savefiles are read whole.
+pub fn decrypt_reader<R: Read>(reader: R) -> Result<(String, SavefilePlatform), GDError> {
+    let mut reader = BufReader::new(reader);
+    let mut data = vec![];
+    if reader.fill_buf()?.starts_with(WINDOWS_SAVEFILE_MAGIC) {
+        GdDecryptReader::new(reader).read_to_end(&mut data)?;
+        return Ok((decrypted_str(data)?, SavefilePlatform::Windows));
+    }
+    reader.read_to_end(&mut data)?;
+    let (data, platform) = decrypt_savefile(data)?;
+    Ok((decrypted_str(data)?, platform))
+}
+
+fn decrypted_str(mut data: Vec<u8>) -> Result<String, GDError> {
+    data.retain(|c| *c < 128); // sometimes there are some weird chars in the ccgamemanager file
     Ok(String::from_utf8(data)?)
 }
 
-/// Warning when using this fn: if the data isn't valid UTF8, the fn WILL panic!
+/// Converts UTF-8 bytes to a `String`, returning an error if they are not valid UTF-8.
 #[inline]
-pub(crate) fn vec_as_str(data: &[u8]) -> String {
-    core::str::from_utf8(data).unwrap().to_string()
+pub(crate) fn vec_as_str(data: &[u8]) -> Result<String, GDError> {
+    Ok(String::from_utf8(data.to_vec())?)
 }
@@ -0,0 +1,359 @@
+//! Discovery of GD savefiles across platforms, Steam libraries and Wine prefixes.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::core::LINUX_GD_FILES;
+
+/// Environment variable that lists extra directories to search for savefiles.
+/// Multiple directories are separated like `PATH` (`:` on unix, `;` on windows).
+pub const SAVE_DIR_ENV: &str = "GDLIB_SAVE_DIR";
+
+/// Steam app ID of Geometry Dash.
+const GD_STEAM_APP_ID: &str = "322170";
+/// Location of the savefile directory relative to a Wine/Proton user directory.
+const WINE_USER_GD_DIR: &str = "AppData/Local/GeometryDash";
+/// Steam installs that may contain a Proton prefix for GD, relative to the home directory.
+const STEAM_ROOTS: [(&str, SaveDirSource); 5] = [
+    (".local/share/Steam", SaveDirSource::Steam),
+    (".steam/steam", SaveDirSource::Steam),
+    (".steam/root", SaveDirSource::Steam),
+    (
+        ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+        SaveDirSource::SteamFlatpak,
+    ),
+    (
+        ".var/app/com.valvesoftware.Steam/data/Steam",
+        SaveDirSource::SteamFlatpak,
+    ),
+];
+
+/// Savefiles that GD stores in its save directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SavefileKind {
+    /// CCLocalLevels.dat: created levels and lists
+    LocalLevels,
+    /// CCGameManager.dat: player stats, settings and everything else
+    GameManager,
+}
+
+impl SavefileKind {
+    /// Name of this savefile in the save directory.
+    #[must_use]
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::LocalLevels => "CCLocalLevels.dat",
+            Self::GameManager => "CCGameManager.dat",
+        }
+    }
+}
+
+/// Where a save directory was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SaveDirSource {
+    /// Directory added with [`SavefileLocator::dir`]
+    Explicit,
+    /// Directory listed in [`SAVE_DIR_ENV`]
+    Environment,
+    /// `%LOCALAPPDATA%\GeometryDash` on windows
+    LocalAppData,
+    /// `~/Library/Application Support/GeometryDash` on macOS
+    MacOS,
+    /// Proton prefix of a native Steam install
+    Steam,
+    /// Proton prefix of a Flatpak Steam install
+    SteamFlatpak,
+    /// Proton prefix in an additional Steam library folder (from `libraryfolders.vdf`)
+    SteamLibrary,
+    /// Wine or Lutris prefix
+    Wine,
+}
+
+/// A savefile found by [`SavefileLocator`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavefileCandidate {
+    /// Location of the savefile
+    pub path: PathBuf,
+    /// Where the savefile's directory was found
+    pub source: SaveDirSource,
+    /// Size of the savefile in bytes
+    pub size: u64,
+    /// Last modification time of the savefile, if the platform supports it
+    pub modified: Option<SystemTime>,
+}
+
+/// Searches all known locations for GD savefiles.
+///
+/// Directories are searched in the following order:
+/// 1. directories added with [`SavefileLocator::dir`]
+/// 2. directories listed in the `GDLIB_SAVE_DIR` environment variable
+/// 3. the standard save directories of windows and macOS, Steam/Proton prefixes (including Flatpak Steam and
+///    additional Steam library folders), and Wine/Lutris prefixes.
+///
+/// # Example
+/// ```no_run
+/// use gdlib::core::locator::{SavefileKind, SavefileLocator};
+///
+/// for candidate in SavefileLocator::new().candidates(SavefileKind::LocalLevels) {
+///     println!("{} ({:?}, {} bytes)", candidate.path.display(), candidate.source, candidate.size);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SavefileLocator {
+    dirs: Vec<PathBuf>,
+    home: Option<PathBuf>,
+    /// Whether `home` was set with [`SavefileLocator::home`] rather than read from the environment
+    explicit_home: bool,
+    use_env: bool,
+    standard_locations: bool,
+}
+
+impl Default for SavefileLocator {
+    fn default() -> Self {
+        Self {
+            dirs: vec![],
+            home: home_dir(),
+            explicit_home: false,
+            use_env: true,
+            standard_locations: true,
+        }
+    }
+}
+
+impl SavefileLocator {
+    /// Creates a locator that searches the environment variable and all standard locations.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory to search before all other locations. `~` is expanded to the home directory when searching, so
+    /// it does not matter whether [`SavefileLocator::home`] is called before or after this.
+    #[must_use]
+    pub fn dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the home directory that `~` and the standard locations are resolved against.
+    ///
+    /// The standard locations then no longer depend on the environment: `%LOCALAPPDATA%` is taken to be
+    /// `AppData/Local` in `home`, and `WINEPREFIX` is ignored.
+    #[must_use]
+    pub fn home<P: Into<PathBuf>>(mut self, home: P) -> Self {
+        self.home = Some(home.into());
+        self.explicit_home = true;
+        self
+    }
+
+    /// Sets whether environment variables are read: the directories listed in `GDLIB_SAVE_DIR`, and `LOCALAPPDATA`
+    /// and `WINEPREFIX` for the standard locations. Enabled by default.
+    #[must_use]
+    pub fn use_env(mut self, use_env: bool) -> Self {
+        self.use_env = use_env;
+        self
+    }
+
+    /// Sets whether the standard locations are searched. Enabled by default.
+    #[must_use]
+    pub fn standard_locations(mut self, standard_locations: bool) -> Self {
+        self.standard_locations = standard_locations;
+        self
+    }
+
+    /// Returns all directories that may contain savefiles, in search order and without duplicates.
+    /// The directories are not guaranteed to exist.
+    #[must_use]
+    pub fn save_dirs(&self) -> Vec<(PathBuf, SaveDirSource)> {
+        let mut dirs = vec![];
+        dirs.extend(self.dirs.iter().map(|d| {
+            (
+                expand_home_in(d, self.home.as_deref()),
+                SaveDirSource::Explicit,
+            )
+        }));
+
+        if self.use_env
+            && let Some(paths) = env::var_os(SAVE_DIR_ENV)
+        {
+            dirs.extend(
+                env::split_paths(&paths)
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .map(|p| {
+                        (
+                            expand_home_in(&p, self.home.as_deref()),
+                            SaveDirSource::Environment,
+                        )
+                    }),
+            );
+        }
+
+        if self.standard_locations {
+            self.push_standard_dirs(&mut dirs);
+        }
+
+        let mut seen = vec![];
+        dirs.retain(|(dir, _)| {
+            // symlinked steam roots (~/.steam/steam) would otherwise show up twice
+            let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.clone());
+            if seen.contains(&canonical) {
+                false
+            } else {
+                seen.push(canonical);
+                true
+            }
+        });
+        dirs
+    }
+
+    /// Returns every existing savefile of the given kind, in search order.
+    #[must_use]
+    pub fn candidates(&self, kind: SavefileKind) -> Vec<SavefileCandidate> {
+        self.save_dirs()
+            .into_iter()
+            .filter_map(|(dir, source)| {
+                let path = dir.join(kind.file_name());
+                let metadata = fs::metadata(&path).ok().filter(|m| m.is_file())?;
+                Some(SavefileCandidate {
+                    path,
+                    source,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the path of the first existing savefile of the given kind.
+    #[must_use]
+    pub fn find(&self, kind: SavefileKind) -> Option<PathBuf> {
+        self.candidates(kind).into_iter().next().map(|c| c.path)
+    }
+
+    /// Returns the value of an environment variable that a standard location is resolved from, unless the locator
+    /// should not depend on the environment.
+    fn standard_env_var(&self, key: &str) -> Option<PathBuf> {
+        if self.use_env && !self.explicit_home {
+            env::var_os(key)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+        } else {
+            None
+        }
+    }
+
+    fn push_standard_dirs(&self, dirs: &mut Vec<(PathBuf, SaveDirSource)>) {
+        let local_appdata = match &self.home {
+            Some(home) if self.explicit_home => Some(home.join("AppData/Local")),
+            _ => self.standard_env_var("LOCALAPPDATA"),
+        };
+        if let Some(local_appdata) = local_appdata {
+            dirs.push((
+                local_appdata.join("GeometryDash"),
+                SaveDirSource::LocalAppData,
+            ));
+        }
+
+        let Some(home) = &self.home else {
+            return;
+        };
+
+        dirs.push((
+            home.join("Library/Application Support/GeometryDash"),
+            SaveDirSource::MacOS,
+        ));
+
+        // the original hard-coded location comes first so that existing setups keep resolving to the same file
+        dirs.push((
+            expand_home_in(Path::new(LINUX_GD_FILES), Some(home)),
+            SaveDirSource::Steam,
+        ));
+        for (root, source) in STEAM_ROOTS {
+            let root = home.join(root);
+            dirs.push((proton_save_dir(&root), source));
+            for library in steam_library_folders(&root) {
+                dirs.push((proton_save_dir(&library), SaveDirSource::SteamLibrary));
+            }
+        }
+
+        let mut prefixes = vec![home.join(".wine")];
+        if let Some(prefix) = self.standard_env_var("WINEPREFIX") {
+            prefixes.push(expand_home_in(&prefix, Some(home)));
+        }
+        // lutris installs every game into its own prefix in ~/Games by default
+        if let Ok(games) = fs::read_dir(home.join("Games")) {
+            prefixes.extend(games.flatten().map(|e| e.path()));
+        }
+        for prefix in prefixes {
+            dirs.extend(
+                wine_save_dirs(&prefix)
+                    .into_iter()
+                    .map(|d| (d, SaveDirSource::Wine)),
+            );
+        }
+    }
+}
+
+/// Expands a leading `~` in `path` to the current user's home directory.
+#[must_use]
+pub fn expand_home<P: AsRef<Path>>(path: P) -> PathBuf {
+    expand_home_in(path.as_ref(), home_dir().as_deref())
+}
+
+fn expand_home_in(path: &Path, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+}
+
+fn proton_save_dir(library: &Path) -> PathBuf {
+    library
+        .join("steamapps/compatdata")
+        .join(GD_STEAM_APP_ID)
+        .join("pfx/drive_c/users/steamuser")
+        .join(WINE_USER_GD_DIR)
+}
+
+/// Returns the save directories of every user in a Wine prefix.
+fn wine_save_dirs(prefix: &Path) -> Vec<PathBuf> {
+    let Ok(users) = fs::read_dir(prefix.join("drive_c/users")) else {
+        return vec![];
+    };
+    users
+        .flatten()
+        .map(|user| user.path().join(WINE_USER_GD_DIR))
+        .collect()
+}
+
+/// Returns the additional library folders listed in a Steam install's `libraryfolders.vdf`.
+fn steam_library_folders(steam_root: &Path) -> Vec<PathBuf> {
+    ["steamapps/libraryfolders.vdf", "config/libraryfolders.vdf"]
+        .iter()
+        .filter_map(|f| fs::read_to_string(steam_root.join(f)).ok())
+        .flat_map(|vdf| parse_library_folders(&vdf))
+        .collect()
+}
+
+/// Extracts the `"path"` values from a `libraryfolders.vdf` file.
+fn parse_library_folders(vdf: &str) -> Vec<PathBuf> {
+    vdf.lines()
+        .filter_map(|line| {
+            let mut quoted = line.split('"').skip(1).step_by(2);
+            match (quoted.next(), quoted.next()) {
+                (Some("path"), Some(path)) => Some(PathBuf::from(path.replace("\\\\", "\\"))),
+                _ => None,
+            }
+        })
+        .collect()
+}
@@ -1,19 +1,26 @@
 //! This module contains various utilities for debugging and processing structs
 use aho_corasick::AhoCorasick;
 use base64::{DecodeError, Engine};
+use locator::{SavefileKind, SavefileLocator};
 use std::{
-    env,
     error::Error,
     fmt::{Debug, Display},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 pub mod crypto;
+pub mod files;
+pub(crate) mod gdplist;
 pub mod io;
+pub mod locator;
+pub mod parse;
 pub mod rand;
+pub mod stream;
 pub mod structs;
+pub(crate) mod values;
 
-/// Standard file path of GD savefiles on linux.
+/// Standard file path of GD savefiles on linux (Steam through Proton). The leading `~` is the user's home directory;
+/// see [`locator::expand_home`].
 pub const LINUX_GD_FILES: &str = "~/.local/share/Steam/steamapps/compatdata/322170/pfx/drive_c/users/steamuser/AppData/Local/GeometryDash";
 
 macro_rules! count_exprs {
@@ -64,6 +71,64 @@ pub enum GDError {
     AhoCorasick(aho_corasick::BuildError),
     /// `FromUtf8Error` when converting decrypted bytes to string
     FromUtf8Error(std::string::FromUtf8Error),
+    /// The savefile at the given path was modified by another program after it was loaded
+    ConcurrentModification(PathBuf),
+    /// A value does not have the expected type or format
+    InvalidValue {
+        /// Slash-separated path of keys leading to the value, e.g. `LLM_01/k_12/k4`
+        path: String,
+        /// Description of the expected type or format
+        expected: &'static str,
+        /// Description of the value that was found
+        found: String,
+    },
+    /// A required key is missing
+    MissingKey {
+        /// Slash-separated path of the missing key, e.g. `LLM_02`
+        path: String,
+    },
+    /// The CRC32 or size in the trailer of a gzip stream does not match the decompressed data
+    GzipTrailerMismatch {
+        /// The field that does not match: `CRC32` or `ISIZE`
+        field: &'static str,
+        /// Value stored in the trailer
+        expected: u32,
+        /// Value computed from the decompressed data
+        found: u32,
+    },
+}
+
+impl GDError {
+    /// Returns the key path of the value that caused this error, if known.
+    #[must_use]
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Self::InvalidValue { path, .. } | Self::MissingKey { path } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Prefixes the key path of this error with `key`.
+    /// Parsers call this as errors propagate up, so that the final path leads from the root of the file to the offending value.
+    #[must_use]
+    pub fn at(mut self, key: &str) -> Self {
+        if let Self::InvalidValue { path, .. } | Self::MissingKey { path } = &mut self {
+            *path = if path.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{key}/{path}")
+            };
+        }
+        self
+    }
+
+    /// Returns a [`GDError::MissingKey`] error for `key`.
+    #[must_use]
+    pub fn missing_key(key: &str) -> Self {
+        Self::MissingKey {
+            path: key.to_owned(),
+        }
+    }
 }
 
 impl Error for GDError {
@@ -74,7 +139,12 @@ impl Error for GDError {
             Self::BadPlist(e) => e.source(),
             Self::AhoCorasick(e) => Some(e),
             Self::FromUtf8Error(e) => Some(e),
-            Self::CorruptedSavefile(_) | Self::MissingSavefile => None,
+            Self::CorruptedSavefile(_)
+            | Self::MissingSavefile
+            | Self::ConcurrentModification(_)
+            | Self::InvalidValue { .. }
+            | Self::MissingKey { .. }
+            | Self::GzipTrailerMismatch { .. } => None,
         }
     }
 }
@@ -86,6 +156,13 @@ impl From<DecodeError> for GDError {
 }
 impl From<std::io::Error> for GDError {
     fn from(value: std::io::Error) -> Self {
+        // errors raised inside readers and writers such as `GdDecryptReader` are passed through as they were
+        if value.get_ref().is_some_and(|e| e.is::<GDError>()) {
+            return *value
+                .into_inner()
+                .and_then(|e| e.downcast::<GDError>().ok())
+                .expect("checked above");
+        }
         Self::Io(value)
     }
 }
@@ -121,53 +198,62 @@ impl Display for GDError {
             Self::FromUtf8Error(e) => write!(f, "UTF-8 conversion error: {e}"),
             Self::CorruptedSavefile(e) => write!(f, "Corrupted savefile: {e}"),
             Self::MissingSavefile => write!(f, "No available save file found!"),
+            Self::ConcurrentModification(p) => write!(
+                f,
+                "Savefile {} was modified by another program after it was loaded",
+                p.display()
+            ),
+            Self::InvalidValue {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Invalid value at `{path}`: expected {expected}, found {found}"
+            ),
+            Self::MissingKey { path } => write!(f, "Missing key `{path}`"),
+            Self::GzipTrailerMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Gzip {field} mismatch: trailer has {expected:#010x}, decompressed data has {found:#010x}"
+            ),
         }
     }
 }
 
-/// Returns path of CCLocalLevels.dat if it exists
+/// Returns path of CCLocalLevels.dat if it exists.
+///
+/// See [`SavefileLocator`] for the locations that are searched.
 #[must_use]
-// -- TODO --: The fn only supports Windows, need to implement other OSes
 pub fn get_cclocallevels_path() -> Option<PathBuf> {
-    // TODO: need better way to validate/ensure Windows platforms
-    // TODO: need better way to check all Windows locations for gd?
-    // TODO: make into const
-    if let Ok(local_appdata) = env::var("LOCALAPPDATA")
-        && Path::new(&local_appdata).exists()
-    {
-        Some(format!("{local_appdata}/GeometryDash/CCLocalLevels.dat").into())
-    } else {
-        let linux_path = PathBuf::from(format!("{LINUX_GD_FILES}/CCLocalLevels.dat"));
-        if linux_path.exists() {
-            return Some(linux_path);
-        }
-
-        None
-    }
+    SavefileLocator::new().find(SavefileKind::LocalLevels)
 }
 
-/// Returns path to CCGameManager.dat if it exists
+/// Returns path to CCGameManager.dat if it exists.
+///
+/// See [`SavefileLocator`] for the locations that are searched.
+#[must_use]
 pub fn get_ccgamemanager_path() -> Option<PathBuf> {
-    if let Ok(local_appdata) = env::var("LOCALAPPDATA") {
-        let path = PathBuf::from(format!("{local_appdata}/GeometryDash/CCGameManager.dat"));
-        if path.exists() {
-            return Some(path);
-        }
-    }
-
-    let linux_path = PathBuf::from(format!("{LINUX_GD_FILES}/CCGameManager.dat"));
-    if linux_path.exists() {
-        return Some(linux_path);
-    }
-
-    None
+    SavefileLocator::new().find(SavefileKind::GameManager)
 }
 
-/// Replaces Robtop's plist format with actual plist tags; i.e. `<s>` becomes `<string>`
+/// Replaces Robtop's plist format with actual plist tags; i.e. `<s>` becomes `<string>`.
+///
+/// Unescaped `&`, `<` and `>` in strings and keys (as written by older versions of this crate) are escaped
+/// so that such files can still be read.
 pub fn proper_plist_tags(s: String) -> Result<String, GDError> {
+    rewrite_plist_tags(&s)
+}
+
+pub(crate) fn rewrite_plist_tags(s: &str) -> Result<String, GDError> {
     // replace gd plist with proper plist; use aho-corasick for single-pass instead of many .replace()s
     let ac = AhoCorasick::new(PLIST_TAGS_FIND)?;
-    Ok(ac.replace_all(&s, &PLIST_TAGS_REPLACE))
+    Ok(io::repair_xml_entities(
+        ac.replace_all(s, &PLIST_TAGS_REPLACE),
+    ))
 }
 
 /// Quick function for decoding base64 bytes
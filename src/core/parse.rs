@@ -0,0 +1,219 @@
+//! Options for parsing savefiles and levels, and the diagnostics that are collected while parsing.
+
+use std::fmt::Display;
+
+use crate::core::GDError;
+
+/// How a parser handles values that it cannot parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ParseMode {
+    /// Any value that cannot be parsed without loss is an error. This is the default, and what the savefile parsers
+    /// without options (such as [`CCLocalLevels::from_decrypted`]) use. The level data, object and header parsers
+    /// without options (such as [`GDObject::parse_str`]) instead silently replace or drop such values.
+    ///
+    /// [`CCLocalLevels::from_decrypted`]: crate::cclocallevels::gdlevel::CCLocalLevels::from_decrypted
+    /// [`GDObject::parse_str`]: crate::cclocallevels::gdobj::GDObject::parse_str
+    #[default]
+    Strict,
+    /// Values that cannot be parsed are replaced with a default or dropped, and broken levels and objects are skipped.
+    /// Every such decision is recorded as a [`Diagnostic`].
+    Lenient,
+}
+
+/// Which parser reads the plist text of savefiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PlistParser {
+    /// A parser for GD's abbreviated plist dialect that reads straight from the decrypted text, without building the
+    /// whole document tree first. Documents that it rejects as malformed are retried with [`PlistParser::PlistCrate`].
+    #[default]
+    Native,
+    /// Rewrites GD's tags into standard plist tags and parses the result with the plist crate.
+    PlistCrate,
+}
+
+/// Options for the `*_with_options` parsers, such as [`GDObject::parse_str_with_options`].
+///
+/// The default options are the same as [`ParseOptions::strict`]. See [`ParseMode::Strict`] for which of the parsers
+/// without options behave the same way.
+///
+/// [`GDObject::parse_str_with_options`]: crate::cclocallevels::gdobj::GDObject::parse_str_with_options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ParseOptions {
+    /// How unparsable values are handled
+    pub mode: ParseMode,
+    /// Keep the original text of the savefile, so that exporting it reproduces everything that was not edited byte for
+    /// byte. See [`SavefileSource`]. Disabled by default, since it keeps a copy of the whole savefile in memory.
+    ///
+    /// [`SavefileSource`]: crate::cclocallevels::fidelity::SavefileSource
+    pub fidelity: bool,
+    /// Which parser reads the plist text
+    pub parser: PlistParser,
+}
+
+impl ParseOptions {
+    /// Options that reject any lossy coercion.
+    #[must_use]
+    pub const fn strict() -> Self {
+        Self {
+            mode: ParseMode::Strict,
+            fidelity: false,
+            parser: PlistParser::Native,
+        }
+    }
+
+    /// Options that skip broken values, objects and levels and keep going.
+    #[must_use]
+    pub const fn lenient() -> Self {
+        Self {
+            mode: ParseMode::Lenient,
+            fidelity: false,
+            parser: PlistParser::Native,
+        }
+    }
+
+    /// Sets whether the original text of the savefile is kept. See [`ParseOptions::fidelity`].
+    #[must_use]
+    pub const fn with_fidelity(mut self, fidelity: bool) -> Self {
+        self.fidelity = fidelity;
+        self
+    }
+
+    /// Sets which parser reads the plist text. See [`PlistParser`].
+    #[must_use]
+    pub const fn with_parser(mut self, parser: PlistParser) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Returns `true` if these options use [`ParseMode::Strict`].
+    #[must_use]
+    pub const fn is_strict(&self) -> bool {
+        matches!(self.mode, ParseMode::Strict)
+    }
+}
+
+/// What a lenient parser did with a value it could not parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The value is not a valid `expected` and was replaced with a default value.
+    Coerced {
+        /// Description of the expected type or format
+        expected: &'static str,
+    },
+    /// The value is not a valid `expected` and was dropped.
+    Dropped {
+        /// Description of the expected type or format
+        expected: &'static str,
+    },
+    /// The object could not be parsed and was skipped.
+    SkippedObject {
+        /// Why the object could not be parsed
+        reason: String,
+    },
+    /// The level could not be parsed and was skipped.
+    SkippedLevel {
+        /// Why the level could not be parsed
+        reason: String,
+    },
+    /// The list could not be parsed and was skipped.
+    SkippedList {
+        /// Why the list could not be parsed
+        reason: String,
+    },
+}
+
+/// A single problem that was encountered by a lenient parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Index of the level in the savefile, if the problem is inside a level
+    pub level: Option<usize>,
+    /// Index of the object in the level data, if the problem is inside an object
+    pub object: Option<usize>,
+    /// ID of the object property or level header property, if the problem is a property value
+    pub property: Option<u16>,
+    /// Slash-separated key path of the value in the savefile, if known
+    pub key: Option<String>,
+    /// The raw value that could not be parsed, if there is one
+    pub raw: Option<String>,
+    /// What happened to the value
+    pub kind: DiagnosticKind,
+}
+
+/// List of diagnostics in the order they were encountered.
+pub type Diagnostics = Vec<Diagnostic>;
+
+impl Diagnostic {
+    pub(crate) fn new(kind: DiagnosticKind) -> Self {
+        Self {
+            level: None,
+            object: None,
+            property: None,
+            key: None,
+            raw: None,
+            kind,
+        }
+    }
+
+    /// Diagnostic for a property value that was replaced with a default.
+    pub(crate) fn coerced(property: u16, raw: &str, expected: &'static str) -> Self {
+        Self {
+            property: Some(property),
+            raw: Some(raw.to_owned()),
+            ..Self::new(DiagnosticKind::Coerced { expected })
+        }
+    }
+
+    /// Diagnostic for a property value that was dropped.
+    pub(crate) fn dropped(property: u16, raw: &str, expected: &'static str) -> Self {
+        Self {
+            property: Some(property),
+            raw: Some(raw.to_owned()),
+            ..Self::new(DiagnosticKind::Dropped { expected })
+        }
+    }
+
+    /// Diagnostic for a level, object or list that was skipped because of `err`.
+    /// The key path and offending value are taken from the error.
+    pub(crate) fn skipped(err: &GDError, kind: fn(String) -> DiagnosticKind) -> Self {
+        let raw = match err {
+            GDError::InvalidValue { found, .. } => Some(found.clone()),
+            _ => None,
+        };
+        Self {
+            key: err.path().map(str::to_owned),
+            raw,
+            ..Self::new(kind(err.to_string()))
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(level) = self.level {
+            write!(f, "level {level}: ")?;
+        }
+        if let Some(object) = self.object {
+            write!(f, "object {object}: ")?;
+        }
+        if let Some(property) = self.property {
+            write!(f, "property {property}: ")?;
+        } else if let Some(key) = &self.key {
+            write!(f, "`{key}`: ")?;
+        }
+        let raw = self.raw.as_deref().unwrap_or_default();
+        match &self.kind {
+            DiagnosticKind::Coerced { expected } => {
+                write!(
+                    f,
+                    "{raw:?} is not a valid {expected}, replaced with default"
+                )
+            }
+            DiagnosticKind::Dropped { expected } => {
+                write!(f, "{raw:?} is not a valid {expected}, dropped")
+            }
+            DiagnosticKind::SkippedObject { reason } => write!(f, "skipped object ({reason})"),
+            DiagnosticKind::SkippedLevel { reason } => write!(f, "skipped level ({reason})"),
+            DiagnosticKind::SkippedList { reason } => write!(f, "skipped list ({reason})"),
+        }
+    }
+}
@@ -14,7 +14,6 @@ pub fn next_seed(seed: u64) -> u64 {
 
 /// Mutating version of [`next_seed`]
 #[inline(always)]
-#[must_use]
 pub fn next_seed_mut(seed: &mut u64) {
     *seed = seed.wrapping_mul(LCG_MULTIPLIER).wrapping_add(LCG_CONSTANT);
 }
@@ -0,0 +1,314 @@
+//! Streaming versions of the XOR, base64 and compression stages of savefile encryption.
+//!
+//! [`GdDecryptReader`] and [`GdEncryptWriter`] wrap any reader or writer, so that a savefile can be decrypted while it
+//! is read or encrypted while it is written, without holding the intermediate stages in memory. Only the stages
+//! themselves are streamed: callers that collect the decrypted savefile into a `String`, or build the XML before
+//! encrypting it, still hold that in memory.
+//! Only Windows savefiles (and level data) are supported; see [`crate::core::crypto`] for Apple savefiles.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    read::DecoderReader,
+    write::EncoderWriter,
+};
+use flate2::{
+    Compression, GzBuilder,
+    bufread::{DeflateDecoder, ZlibDecoder},
+    write::GzEncoder,
+};
+
+use crate::core::GDError;
+
+/// XOR key of Windows savefiles
+const SAVEFILE_KEY: u8 = 11;
+/// Operating system field of the gzip header written by GD (NTFS)
+const GZIP_OS: u8 = 11;
+
+/// URL-safe base64 that accepts both padded and unpadded input.
+static BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Reader that decrypts a Windows savefile (or, with [`GdDecryptReader::without_xor`], level data) as it is read.
+///
+/// The input is XORed, base64-decoded and decompressed incrementally. Like [`crate::core::io::decompress`], both
+/// the standard and the URL-safe base64 alphabet are accepted, and the payload may be gzip (with any optional header
+/// fields), zlib or raw deflate. Since the stream cannot be rewound, a raw deflate stream that happens to start with
+/// a valid zlib header is read as zlib, where `decompress` would fall back to raw deflate.
+///
+/// The gzip trailer is validated at the end of the stream; a mismatch is an [`io::Error`] that converts into
+/// [`GDError::GzipTrailerMismatch`].
+pub struct GdDecryptReader<R: Read> {
+    state: DecryptState<R>,
+}
+
+type Decoded<R> = BufReader<DecoderReader<'static, GeneralPurpose, XorReader<R>>>;
+
+enum DecryptState<R: Read> {
+    /// The container has not been detected yet
+    Header(Decoded<R>),
+    Gzip {
+        decoder: DeflateDecoder<Decoded<R>>,
+        crc: crc32fast::Hasher,
+        size: u32,
+    },
+    Zlib(ZlibDecoder<Decoded<R>>),
+    RawDeflate(DeflateDecoder<Decoded<R>>),
+    Done,
+    /// Only used while switching states
+    Empty,
+}
+
+impl<R: Read> GdDecryptReader<R> {
+    /// Decrypts a Windows savefile: XOR with key 11, base64 and gzip, zlib or raw deflate.
+    pub fn new(reader: R) -> Self {
+        Self::with_key(reader, SAVEFILE_KEY)
+    }
+
+    /// Decodes level data: base64 and gzip, zlib or raw deflate, without XOR.
+    pub fn without_xor(reader: R) -> Self {
+        Self::with_key(reader, 0)
+    }
+
+    fn with_key(reader: R, key: u8) -> Self {
+        let xor = XorReader { inner: reader, key };
+        Self {
+            state: DecryptState::Header(BufReader::new(DecoderReader::new(xor, &BASE64))),
+        }
+    }
+}
+
+impl<R: Read> Read for GdDecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match &mut self.state {
+                DecryptState::Header(_) => {
+                    let DecryptState::Header(mut decoded) =
+                        std::mem::replace(&mut self.state, DecryptState::Empty)
+                    else {
+                        unreachable!()
+                    };
+                    self.state = match *decoded.fill_buf()? {
+                        [0x1f, 0x8b, ..] => {
+                            read_gzip_header(&mut decoded).map_err(into_io)?;
+                            DecryptState::Gzip {
+                                decoder: DeflateDecoder::new(decoded),
+                                crc: crc32fast::Hasher::new(),
+                                size: 0,
+                            }
+                        }
+                        [cmf, flg, ..]
+                            if cmf & 0x0f == 8
+                                && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0 =>
+                        {
+                            DecryptState::Zlib(ZlibDecoder::new(decoded))
+                        }
+                        _ => DecryptState::RawDeflate(DeflateDecoder::new(decoded)),
+                    };
+                }
+                DecryptState::Gzip { decoder, crc, size } => {
+                    let n = decoder.read(buf)?;
+                    if n > 0 {
+                        crc.update(&buf[..n]);
+                        *size = size.wrapping_add(n as u32);
+                        return Ok(n);
+                    }
+                    let mut trailer = [0; 8];
+                    decoder.get_mut().read_exact(&mut trailer).map_err(|_| {
+                        into_io(GDError::CorruptedSavefile(
+                            "Gzip stream is missing its CRC32 and size trailer".into(),
+                        ))
+                    })?;
+                    check_gzip_trailer(trailer, crc.clone().finalize(), *size).map_err(into_io)?;
+                    self.state = DecryptState::Done;
+                }
+                DecryptState::Zlib(decoder) => return decoder.read(buf),
+                DecryptState::RawDeflate(decoder) => return decoder.read(buf),
+                DecryptState::Done | DecryptState::Empty => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Writer that encrypts a Windows savefile (or, with [`GdEncryptWriter::without_xor`], level data) as it is written.
+///
+/// The output matches what GD writes: gzip, URL-safe base64 and XOR with key 11.
+/// [`GdEncryptWriter::finish`] must be called to write the end of the stream.
+pub struct GdEncryptWriter<W: Write> {
+    inner: GzEncoder<EncoderWriter<'static, GeneralPurpose, XorWriter<W>>>,
+}
+
+impl<W: Write> GdEncryptWriter<W> {
+    /// Encrypts a Windows savefile: gzip, URL-safe base64 and XOR with key 11.
+    pub fn new(writer: W) -> Self {
+        Self::with_key(writer, SAVEFILE_KEY)
+    }
+
+    /// Encodes level data: gzip and URL-safe base64, without XOR.
+    pub fn without_xor(writer: W) -> Self {
+        Self::with_key(writer, 0)
+    }
+
+    fn with_key(writer: W, key: u8) -> Self {
+        let xor = XorWriter { inner: writer, key };
+        Self {
+            inner: GzBuilder::new()
+                .operating_system(GZIP_OS)
+                .write(EncoderWriter::new(xor, &BASE64), Compression::default()),
+        }
+    }
+
+    /// Writes the gzip trailer and base64 padding, and returns the wrapped writer.
+    pub fn finish(self) -> io::Result<W> {
+        let mut encoder = self.inner.finish()?;
+        let mut xor = encoder.finish()?;
+        xor.inner.flush()?;
+        Ok(xor.inner)
+    }
+}
+
+impl<W: Write> Write for GdEncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// XORs every byte and drops NULs and whitespace, which GD sometimes leaves around the base64 payload.
+/// Standard base64 is translated to the URL-safe alphabet, since the two only differ in `+`/`-` and `/`/`_`.
+struct XorReader<R: Read> {
+    inner: R,
+    key: u8,
+}
+
+impl<R: Read> Read for XorReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut len = 0;
+            for idx in 0..n {
+                let c = match buf[idx] ^ self.key {
+                    b'+' => b'-',
+                    b'/' => b'_',
+                    c => c,
+                };
+                if c != 0 && !c.is_ascii_whitespace() {
+                    buf[len] = c;
+                    len += 1;
+                }
+            }
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+struct XorWriter<W: Write> {
+    inner: W,
+    key: u8,
+}
+
+impl<W: Write> Write for XorWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunk = [0; 4096];
+        for part in buf.chunks(chunk.len()) {
+            for (out, c) in chunk.iter_mut().zip(part) {
+                *out = c ^ self.key;
+            }
+            self.inner.write_all(&chunk[..part.len()])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn into_io(e: GDError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reads a gzip header, including its optional fields, leaving `reader` at the start of the deflate stream.
+pub(crate) fn read_gzip_header<R: BufRead>(reader: &mut R) -> Result<(), GDError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let truncated =
+        || GDError::CorruptedSavefile("Compressed payload is shorter than its gzip header".into());
+
+    let mut header = [0; 10];
+    reader.read_exact(&mut header).map_err(|_| truncated())?;
+    let [id1, id2, method, flags, ..] = header;
+    if [id1, id2] != [0x1f, 0x8b] {
+        return Err(GDError::CorruptedSavefile(
+            "Compressed payload is not a gzip stream".into(),
+        ));
+    }
+    if method != 8 {
+        return Err(GDError::CorruptedSavefile(format!(
+            "Unsupported gzip compression method {method}"
+        )));
+    }
+
+    if flags & FEXTRA != 0 {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len).map_err(|_| truncated())?;
+        let len = u64::from(u16::from_le_bytes(len));
+        if io::copy(&mut reader.take(len), &mut io::sink())? != len {
+            return Err(truncated());
+        }
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let mut field = vec![];
+            reader.read_until(0, &mut field)?;
+            if field.last() != Some(&0) {
+                return Err(truncated());
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        reader.read_exact(&mut [0; 2]).map_err(|_| truncated())?;
+    }
+    Ok(())
+}
+
+/// Checks the CRC32 and size in a gzip trailer against those of the decompressed data.
+pub(crate) fn check_gzip_trailer(trailer: [u8; 8], crc: u32, size: u32) -> Result<(), GDError> {
+    let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let expected_size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    if crc != expected_crc {
+        return Err(GDError::GzipTrailerMismatch {
+            field: "CRC32",
+            expected: expected_crc,
+            found: crc,
+        });
+    }
+    // ISIZE is the size modulo 2^32
+    if size != expected_size {
+        return Err(GDError::GzipTrailerMismatch {
+            field: "ISIZE",
+            expected: expected_size,
+            found: size,
+        });
+    }
+    Ok(())
+}
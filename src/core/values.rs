@@ -0,0 +1,84 @@
+//! Typed accessors for plist values which return a [`GDError::InvalidValue`] describing the offending value on failure.
+//!
+//! The errors returned here have an empty key path; callers attach the key with [`GDError::at`].
+
+use plist::{Dictionary, Value};
+
+use crate::core::GDError;
+
+/// Maximum length of a string value that is included in an error message.
+const MAX_DESCRIBED_LEN: usize = 64;
+
+/// Returns an `InvalidValue` error with an empty key path.
+pub(crate) fn invalid<T: ToString>(expected: &'static str, found: T) -> GDError {
+    GDError::InvalidValue {
+        path: String::new(),
+        expected,
+        found: found.to_string(),
+    }
+}
+
+/// Short, human-readable description of a plist value for error messages.
+pub(crate) fn describe(v: &Value) -> String {
+    match v {
+        Value::String(s) => describe_str(s),
+        Value::Integer(i) => format!("integer {i}"),
+        Value::Real(r) => format!("real {r}"),
+        Value::Boolean(b) => format!("boolean {b}"),
+        Value::Dictionary(d) => format!("dictionary with {} keys", d.len()),
+        Value::Array(a) => format!("array with {} elements", a.len()),
+        Value::Data(d) => format!("{} bytes of data", d.len()),
+        Value::Date(_) => "date".to_owned(),
+        Value::Uid(_) => "uid".to_owned(),
+        _ => "unknown value".to_owned(),
+    }
+}
+
+/// Short description of a string for error messages. Long strings are truncated.
+pub(crate) fn describe_str(s: &str) -> String {
+    match s.char_indices().nth(MAX_DESCRIBED_LEN) {
+        Some((end, _)) => format!("string {:?}... ({} bytes)", &s[..end], s.len()),
+        None => format!("string {s:?}"),
+    }
+}
+
+pub(crate) fn as_int(v: &Value) -> Result<i32, GDError> {
+    v.as_signed_integer()
+        .and_then(|i| i32::try_from(i).ok())
+        .ok_or_else(|| invalid("32-bit integer", describe(v)))
+}
+
+pub(crate) fn as_bool(v: &Value) -> Result<bool, GDError> {
+    v.as_boolean()
+        .ok_or_else(|| invalid("boolean", describe(v)))
+}
+
+pub(crate) fn as_str(v: &Value) -> Result<&str, GDError> {
+    v.as_string().ok_or_else(|| invalid("string", describe(v)))
+}
+
+pub(crate) fn as_dict(v: &Value) -> Result<&Dictionary, GDError> {
+    v.as_dictionary()
+        .ok_or_else(|| invalid("dictionary", describe(v)))
+}
+
+/// Accepts both reals and integers, since GD omits the fractional part of whole numbers.
+pub(crate) fn as_float(v: &Value) -> Result<f32, GDError> {
+    match v {
+        Value::Real(f) => Ok(*f as f32),
+        Value::Integer(i) => i
+            .as_signed()
+            .map(|i| i as f32)
+            .ok_or_else(|| invalid("number", describe(v))),
+        _ => Err(invalid("number", describe(v))),
+    }
+}
+
+/// Parses a string (such as one segment of a comma-separated list) to `T`.
+pub(crate) fn parse_str<T: std::str::FromStr>(
+    s: &str,
+    expected: &'static str,
+) -> Result<T, GDError> {
+    s.parse::<T>()
+        .map_err(|_| invalid(expected, describe_str(s)))
+}
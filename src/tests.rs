@@ -1,26 +1,67 @@
 //! Unit tests for the crate
-use std::{fs, time::Instant};
+use std::{fs, path::PathBuf, time::Instant};
 
 use crate::{
-    ccgamemanager::CCGameManager,
+    ccgamemanager::{CCGameManager, GDPlatformerUI},
     cclocallevels::{
-        gdlevel::{CCLocalLevels, GDLevel, leveldata::HeaderValue},
+        bulk::{FileNaming, MANIFEST_FILE},
+        diff::{ObjectDiff, diff_level_data, diff_savefiles},
+        folders::{Folder, Folders},
+        gdlevel::{
+            CCLocalLevels, GDLevel, PLIST_FOOTER, PLIST_HEADER,
+            builder::GDLevelBuilder,
+            enums::{Length, OfficialSong},
+            header::LevelHeaderBuilder,
+            leveldata::{
+                DEFAULT_LEVEL_HEADERS, GDEncryptedLevelData, GDLevelData, GDLevelHeader,
+                GDLevelState, GuidelineColour, GuidelineString, HeaderValue, KS_OFFSET,
+                PlayerColour,
+            },
+            palette::Palette,
+            recolour::{Recolour, RecolourTarget},
+            timeline::Timeline,
+        },
+        gdlist::GDList,
         gdobj::{
-            self,
+            self, GDObject,
             constructors::{
                 misc::default_block,
-                triggers::{advanced_random_trigger, event_trigger, move_trigger},
+                triggers::{
+                    advanced_random_trigger, colour_trigger, event_trigger, move_trigger,
+                    pulse_trigger,
+                },
             },
+            ids,
             ids::{objects::TRIGGER_ADVANCED_RANDOM, properties::RANDOM_PROBABILITIES_LIST},
             meta::{GDObjAttributes, GDObjConfig},
             structs::{
-                ColourChannel, DefaultMove, Event, ExtraID2, Group, MoveEasing, MoveMode, ZLayer,
+                Colour, ColourChannel, ColourTriggerConfig, CopyColourConfig, DefaultMove, Event,
+                ExtraID2, GDValue, Gamemode, Group, HSVColour, MoveEasing, MoveMode, PulseChannel,
+                PulseMode, PulseTarget, Speed, ZLayer,
             },
         },
+        lazy::LazyCCLocalLevels,
+        merge::{MergeOutcome, MergePolicy},
+    },
+    core::{
+        GDError, b64_decode, b64_encode,
+        files::{BackupPolicy, SaveOptions, list_backups, restore_backup},
+        io::{
+            CompressionContainer, SavefilePlatform, decompress, decompress_detect, decrypt,
+            decrypt_file_with_platform, decrypt_savefile, encrypt_level_str, encrypt_savefile_str,
+            encrypt_savefile_str_for, format_real, stringify_xml,
+        },
+        locator::{SaveDirSource, SavefileKind, SavefileLocator},
+        parse::{DiagnosticKind, ParseOptions, PlistParser},
+        proper_plist_tags,
+        rand::check_seed_advanced_random,
+        stream::{GdDecryptReader, GdEncryptWriter},
     },
-    core::rand::check_seed_advanced_random,
 };
 
+/// Smallest decrypted CCLocalLevels.dat that GD accepts: one level, no lists.
+const MINIMAL_SAVEFILE: &str = r#"<?xml version="1.0"?><plist version="1.0" gjver="2.0"><dict><k>LLM_01</k><d><k>_isArr</k><t /><k>k_0</k><d><k>kCEK</k><i>4</i><k>k2</k><s>minimal</s><k>k5</k><s>gdlib</s></d></d><k>LLM_02</k><i>47</i><k>LLM_03</k><d><k>_isArr</k><t /></d></dict></plist>"#;
+
 fn benchmark<F: Fn() -> R, R>(name: &str, f: F) -> R {
     let start = Instant::now();
     let result = f();
@@ -34,7 +75,7 @@ fn benchmark<F: Fn() -> R, R>(name: &str, f: F) -> R {
 #[test]
 fn read_objs() {
     let level = GDLevel::from_gmd("test_gmds/All Object IDs.gmd").unwrap();
-    let data = level.get_decrypted_data().unwrap();
+    let data = level.get_decrypted_data().unwrap().unwrap();
 
     for (idx, obj) in data.objects.iter().enumerate() {
         println!("{idx}: {obj:?}");
@@ -72,11 +113,19 @@ fn level_display_test() {
     println!("GDLevel info: {level}");
     println!(
         "Unused groups: {:?}",
-        level.get_decrypted_data().unwrap().get_unused_groups()
+        level
+            .get_decrypted_data()
+            .unwrap()
+            .unwrap()
+            .get_unused_groups()
     );
     println!(
         "Used groups: {:?}",
-        level.get_decrypted_data().unwrap().get_used_groups()
+        level
+            .get_decrypted_data()
+            .unwrap()
+            .unwrap()
+            .get_used_groups()
     );
 }
 
@@ -113,7 +162,7 @@ fn adv_random() {
 #[test]
 fn big_level_parse() {
     let level = GDLevel::from_gmd("test_gmds/big.gmd").unwrap();
-    benchmark("Big level parse", || level.get_decrypted_data());
+    benchmark("Big level parse", || level.get_decrypted_data().unwrap());
 }
 
 #[test]
@@ -136,7 +185,7 @@ fn ref_vs_copy_benchmark() {
             ref_time += start.elapsed().as_nanos();
         }
     }
-    let objs = level.get_decrypted_data().unwrap().objects.len();
+    let objs = level.get_decrypted_data().unwrap().unwrap().objects.len();
     let avg_copy_time = copy_time as f64 / (1_000 * count) as f64;
     let avg_ref_time = ref_time as f64 / (1_000 * count) as f64;
 
@@ -175,7 +224,7 @@ fn event_trigger_test() {
 fn advanced_random_predict() {
     let level = GDLevel::from_gmd("test_gmds/advrand test.gmd").unwrap();
     // find adv random trigger
-    let data = level.get_decrypted_data().unwrap();
+    let data = level.get_decrypted_data().unwrap().unwrap();
     let adv_rand = data
         .objects
         .iter()
@@ -194,10 +243,259 @@ fn advanced_random_predict() {
     );
 }
 
+#[test]
+fn savefile_platform_roundtrip() {
+    for platform in [SavefilePlatform::Windows, SavefilePlatform::Apple] {
+        let path = format!("test_gmds/generated_{platform:?}_savefile.dat");
+        fs::write(&path, encrypt_savefile_str_for(MINIMAL_SAVEFILE, platform)).unwrap();
+
+        let (decrypted, detected) = decrypt_file_with_platform(path.clone().into()).unwrap();
+        assert_eq!(detected, platform);
+        assert_eq!(decrypted, MINIMAL_SAVEFILE);
+
+        let levels = CCLocalLevels::from_file(path.into()).unwrap();
+        assert_eq!(levels.platform, platform);
+        assert_eq!(levels.levels[0].identity.name, "minimal");
+    }
+
+    // a damaged Windows savefile is not mistaken for an Apple one, whatever its length
+    let mut damaged = encrypt_savefile_str(MINIMAL_SAVEFILE);
+    damaged.truncate(damaged.len() / 16 * 16);
+    damaged[0] = 0xff;
+    damaged[20] = 0x80;
+    assert_eq!(SavefilePlatform::detect(&damaged), None);
+    assert!(matches!(
+        decrypt_savefile(damaged),
+        Err(GDError::CorruptedSavefile(msg)) if msg.contains("damaged Windows savefile")
+    ));
+    assert_eq!(SavefilePlatform::detect(&[]), None);
+}
+
+#[test]
+fn savefile_backup_rotation() {
+    let dir = "test_gmds/generated_backups";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let path = PathBuf::from(format!("{dir}/CCLocalLevels.dat"));
+
+    let mut levels = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_string()).unwrap();
+    let options = SaveOptions {
+        backups: BackupPolicy::keep(2),
+        ..Default::default()
+    };
+    for i in 0..4 {
+        levels.levels[0].identity.name = format!("save {i}");
+        levels.export_to_file_with(path.clone(), &options).unwrap();
+    }
+
+    // only the savefile and its backups remain, no temporary files
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+    let backups = list_backups(&path).unwrap();
+    assert_eq!(backups.len(), 2);
+
+    restore_backup(&path, &backups[1]).unwrap();
+    let restored = CCLocalLevels::from_file(path).unwrap();
+    assert_eq!(restored.levels[0].identity.name, "save 1");
+}
+
+#[test]
+fn savefile_concurrent_modification() {
+    let dir = "test_gmds/generated_concurrent";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let path = PathBuf::from(format!("{dir}/CCLocalLevels.dat"));
+    fs::write(
+        &path,
+        encrypt_savefile_str_for(MINIMAL_SAVEFILE, SavefilePlatform::Windows),
+    )
+    .unwrap();
+
+    let named = |name: &str| {
+        let mut level = GDLevel::default();
+        level.identity.name = name.into();
+        level
+    };
+
+    let base = CCLocalLevels::from_file(path.clone()).unwrap();
+    let mut ours = base.clone();
+    ours.add_level(named("ours"));
+
+    // another program saves a new level in the meantime
+    let mut other = CCLocalLevels::from_file(path.clone()).unwrap();
+    other.add_level(named("theirs"));
+    other.export_to_file(path.clone()).unwrap();
+
+    assert!(matches!(
+        ours.export_to_file(path.clone()),
+        Err(GDError::ConcurrentModification(_))
+    ));
+    // the same file written differently is still checked
+    assert!(matches!(
+        ours.export_to_file(fs::canonicalize(&path).unwrap()),
+        Err(GDError::ConcurrentModification(_))
+    ));
+    assert!(matches!(
+        ours.export_to_file(PathBuf::from(format!(
+            "./{dir}/../generated_concurrent/CCLocalLevels.dat"
+        ))),
+        Err(GDError::ConcurrentModification(_))
+    ));
+
+    let theirs = CCLocalLevels::from_file(path.clone()).unwrap();
+    let (mut merged, conflicts) = CCLocalLevels::merge_three_way(&base, &ours, theirs);
+    assert!(conflicts.is_empty());
+    // both sides gave their new level the same order, but the merged orders still grow towards the newest level
+    assert!(
+        merged
+            .levels
+            .windows(2)
+            .all(|l| l[0].meta.level_order > l[1].meta.level_order)
+    );
+    merged.export_to_file(path.clone()).unwrap();
+
+    let names = CCLocalLevels::from_file(path)
+        .unwrap()
+        .levels
+        .into_iter()
+        .map(|l| l.identity.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["ours", "theirs", "minimal"]);
+}
+
+#[test]
+fn savefile_locator_probes_steam_libraries() {
+    let root = fs::canonicalize("test_gmds")
+        .unwrap()
+        .join("generated_locator");
+    let _ = fs::remove_dir_all(&root);
+    let home = root.join("home");
+    let library = root.join("SteamLibrary");
+    let explicit = root.join("explicit");
+
+    let flatpak_steam = home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam");
+    fs::create_dir_all(flatpak_steam.join("steamapps")).unwrap();
+    fs::write(
+        flatpak_steam.join("steamapps/libraryfolders.vdf"),
+        format!(
+            "\"libraryfolders\"\n{{\n\t\"1\"\n\t{{\n\t\t\"path\"\t\t\"{}\"\n\t}}\n}}\n",
+            library.display()
+        ),
+    )
+    .unwrap();
+
+    let library_save_dir = library
+        .join("steamapps/compatdata/322170/pfx/drive_c/users/steamuser/AppData/Local/GeometryDash");
+    for dir in [&library_save_dir, &explicit] {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("CCLocalLevels.dat"), "data").unwrap();
+    }
+
+    let candidates = SavefileLocator::new()
+        .home(&home)
+        .use_env(false)
+        .dir(&explicit)
+        .candidates(SavefileKind::LocalLevels);
+    let found = candidates
+        .iter()
+        .map(|c| (c.path.clone(), c.source, c.size))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (
+                explicit.join("CCLocalLevels.dat"),
+                SaveDirSource::Explicit,
+                4
+            ),
+            (
+                library_save_dir.join("CCLocalLevels.dat"),
+                SaveDirSource::SteamLibrary,
+                4
+            ),
+        ]
+    );
+
+    // with an explicit home, the standard locations do not depend on the environment
+    let dirs = SavefileLocator::new().home(&home).save_dirs();
+    assert!(dirs.contains(&(
+        home.join("AppData/Local/GeometryDash"),
+        SaveDirSource::LocalAppData
+    )));
+    assert!(
+        dirs.iter()
+            .filter(|(_, source)| {
+                ![SaveDirSource::Environment, SaveDirSource::SteamLibrary].contains(source)
+            })
+            .all(|(dir, _)| dir.starts_with(&home))
+    );
+
+    // `~` is expanded against the locator's home directory, even if it is set after the directory
+    let dirs = SavefileLocator::new()
+        .home(&home)
+        .standard_locations(false)
+        .use_env(false)
+        .dir("~/GeometryDash")
+        .save_dirs();
+    assert_eq!(dirs, [(home.join("GeometryDash"), SaveDirSource::Explicit)]);
+    let dirs = SavefileLocator::new()
+        .standard_locations(false)
+        .use_env(false)
+        .dir("~/GeometryDash")
+        .home(&home)
+        .save_dirs();
+    assert_eq!(dirs, [(home.join("GeometryDash"), SaveDirSource::Explicit)]);
+}
+
+#[test]
+fn structured_parse_errors() {
+    let corrupt = MINIMAL_SAVEFILE.replace("<k>k5</k><s>gdlib</s>", "<k>k18</k><s>many</s>");
+    match CCLocalLevels::from_decrypted(corrupt) {
+        Err(GDError::InvalidValue {
+            path,
+            expected,
+            found,
+        }) => {
+            assert_eq!(path, "LLM_01/k_0/k18");
+            assert_eq!(expected, "32-bit integer");
+            assert_eq!(found, "string \"many\"");
+        }
+        other => panic!("expected an invalid value error, got {other:?}"),
+    }
+
+    let missing = MINIMAL_SAVEFILE.replace("<k>LLM_02</k><i>47</i>", "");
+    assert_eq!(
+        CCLocalLevels::from_decrypted(missing).unwrap_err().path(),
+        Some("LLM_02")
+    );
+
+    let dpad = "60,60,1,255,50.5,x,0"
+        .parse::<GDPlatformerUI>()
+        .unwrap_err();
+    assert_eq!(dpad.path(), Some("5"));
+
+    let gamemanager = |entries: &str| {
+        CCGameManager::from_raw_string(format!("{PLIST_HEADER}{entries}{PLIST_FOOTER}"))
+    };
+    let gm = gamemanager(
+        "<dict><k>playerName</k><s>gdlib</s><k>dpad02</k><s>60,60,1,255,50.5,0</s><k>GLM_12</k><d><k>likes_1_2_3_4</k><s>1</s></d><k>new</k><i>1</i></dict>",
+    )
+    .unwrap();
+    assert_eq!(gm.player_info.username, "gdlib");
+    assert_eq!(gm.config.dpads[1].pos.0, 50.5);
+    assert_eq!(gm.config.glm12_unknown, [[1, 2, 3, 4]]);
+    assert_eq!(gm.other_properties.keys().collect::<Vec<_>>(), ["new"]);
+    let err = gamemanager("<dict><k>GLM_12</k><d><k>likes_1_x</k><s>1</s></d></dict>").unwrap_err();
+    assert_eq!(err.path(), Some("GLM_12/likes_1_x"));
+    assert!(matches!(
+        gamemanager("<array></array>"),
+        Err(GDError::CorruptedSavefile(msg)) if msg.contains("not a dict")
+    ));
+}
+
 #[test]
 #[ignore]
 fn print_list_info() {
-    let mut cc = CCLocalLevels::from_local().unwrap();
+    let cc = CCLocalLevels::from_local().unwrap();
     println!("{:#?}", cc.lists);
 }
 
@@ -212,7 +510,7 @@ fn cc_game_manager_parse() {
 #[ignore]
 fn _temp_read_objs() {
     let level = GDLevel::from_gmd("test_gmds/empty test level.gmd").unwrap();
-    let data = level.get_decrypted_data().unwrap();
+    let data = level.get_decrypted_data().unwrap().unwrap();
 
     for (idx, obj) in data.objects.iter().enumerate() {
         println!("{idx}: {obj:?}");
@@ -223,7 +521,7 @@ fn _temp_read_objs() {
 #[ignore]
 fn _temp_level_header() -> anyhow::Result<()> {
     let level = GDLevel::from_gmd("test_gmds/level.gmd")?;
-    let data = level.get_decrypted_data().unwrap();
+    let data = level.get_decrypted_data().unwrap().unwrap();
     let colour_string = data
         .headers
         .get_property(gdobj::ids::level_header::COLOURS)
@@ -237,3 +535,1361 @@ fn _temp_level_header() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn parse_modes_and_diagnostics() {
+    let strict = ParseOptions::strict();
+    let lenient = ParseOptions::lenient();
+    // the defaults behave like the savefile parsers that take no options
+    assert_eq!(ParseOptions::default(), strict);
+
+    // the real test levels contain no lossy values
+    let mut level = GDLevel::from_gmd("test_gmds/level.gmd").unwrap();
+    assert!(
+        level
+            .decrypt_level_data_with_options(&strict)
+            .unwrap()
+            .is_empty()
+    );
+
+    // booleans are serialised as 1/0
+    assert!(GDObject::parse_str("1,1,11,1").config.trigger_cfg.touchable);
+
+    let broken = "1,1,2,abc,3,15,57,4.x.5";
+    let (obj, diagnostics) = GDObject::parse_str_with_options(broken, &lenient).unwrap();
+    assert_eq!(obj.config.pos, (0.0, 15.0));
+    assert_eq!(obj.config.groups.len(), 2);
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|d| (d.property, d.raw.as_deref(), &d.kind))
+            .collect::<Vec<_>>(),
+        [
+            (
+                Some(2),
+                Some("abc"),
+                &DiagnosticKind::Coerced { expected: "number" }
+            ),
+            (
+                Some(57),
+                Some("x"),
+                &DiagnosticKind::Dropped {
+                    expected: "group ID"
+                }
+            ),
+        ]
+    );
+    let err = GDObject::parse_str_with_options(broken, &strict).unwrap_err();
+    assert_eq!(err.path(), Some("2"));
+
+    // lenient level parsing skips only the object whose ID is broken
+    let raw = format!("{DEFAULT_LEVEL_HEADERS};1,1,2,15;1,x,2,30;1,8,2,45,3,abc;");
+    let data = String::from_utf8(encrypt_level_str(&raw)).unwrap();
+    let (parsed, diagnostics) = GDLevelData::parse_with_options(&data, &lenient).unwrap();
+    assert_eq!(
+        parsed.objects.iter().map(|o| o.id).collect::<Vec<_>>(),
+        [1, 8]
+    );
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].object, Some(1));
+    assert!(matches!(
+        diagnostics[0].kind,
+        DiagnosticKind::SkippedObject { .. }
+    ));
+    assert_eq!(
+        (diagnostics[1].object, diagnostics[1].property),
+        (Some(2), Some(3))
+    );
+    assert_eq!(
+        GDLevelData::parse_with_options(&data, &strict)
+            .unwrap_err()
+            .path(),
+        Some("objects/1/1")
+    );
+
+    // lenient savefile parsing skips only the broken level
+    let savefile = MINIMAL_SAVEFILE.replace(
+        "<k>k_0</k>",
+        "<k>k_0</k><d><k>k18</k><s>many</s></d><k>k_1</k>",
+    );
+    let (levels, diagnostics) =
+        CCLocalLevels::from_decrypted_with_options(savefile.clone(), &lenient).unwrap();
+    assert_eq!(levels.levels.len(), 1);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].level, Some(0));
+    assert_eq!(diagnostics[0].key.as_deref(), Some("LLM_01/k_0/k18"));
+    assert!(CCLocalLevels::from_decrypted_with_options(savefile, &strict).is_err());
+}
+
+#[test]
+fn xml_escaping_and_reals() {
+    // special characters survive an export
+    let mut levels = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_string()).unwrap();
+    levels.levels[0].identity.name = "Tom & Jerry <3> &amp;".to_owned();
+    let exported = levels.export_to_string();
+    assert!(exported.contains("<s>Tom &amp; Jerry &lt;3&gt; &amp;amp;</s>"));
+    let reparsed = CCLocalLevels::from_decrypted(exported).unwrap();
+    assert_eq!(reparsed.levels[0].identity.name, "Tom & Jerry <3> &amp;");
+
+    // unescaped files written by older versions are repaired on read
+    let unescaped = MINIMAL_SAVEFILE.replace("<s>minimal</s>", "<s>a & b <c> &lt;</s>");
+    let levels = CCLocalLevels::from_decrypted(unescaped).unwrap();
+    assert_eq!(levels.levels[0].identity.name, "a & b <c> <");
+
+    // reals are formatted like GD (`%g`), but without losing precision
+    for (f, formatted) in [
+        (0.8, "0.8"),
+        (1.0, "1"),
+        (-73.123456, "-73.123456"),
+        (1234567.0, "1234567"),
+        (999999.5, "999999.5"),
+        (12345.678, "12345.678"),
+        (0.0001, "0.0001"),
+        (0.00001234, "1.234e-05"),
+        (1e20, "1e+20"),
+    ] {
+        assert_eq!(format_real(f), formatted);
+    }
+    for f in [
+        12345.678,
+        0.1 + 0.2,
+        1.0 / 3.0,
+        -98765.4321012,
+        6.02214076e23,
+    ] {
+        assert_eq!(format_real(f).parse::<f64>().unwrap(), f);
+    }
+    let mut dict = plist::Dictionary::new();
+    dict.insert("r".to_owned(), plist::Value::Real(12345.678901));
+    let xml = proper_plist_tags(format!("<plist>{}</plist>", stringify_xml(&dict, true))).unwrap();
+    let parsed = plist::Value::from_reader_xml(xml.as_bytes()).unwrap();
+    assert_eq!(parsed, plist::Value::Dictionary(dict));
+
+    // values that GD does not use itself are kept instead of dropped
+    let mut dict = plist::Dictionary::new();
+    dict.insert(
+        "array".to_owned(),
+        plist::Value::Array(vec![plist::Value::from(1), plist::Value::from("x")]),
+    );
+    dict.insert(
+        "date".to_owned(),
+        plist::Value::Date(plist::Date::from_xml_format("2024-01-02T03:04:05Z").unwrap()),
+    );
+    dict.insert("data".to_owned(), plist::Value::Data(vec![1, 2, 3]));
+    let xml = proper_plist_tags(format!("<plist>{}</plist>", stringify_xml(&dict, true))).unwrap();
+    let parsed = plist::Value::from_reader_xml(xml.as_bytes()).unwrap();
+    assert_eq!(parsed, plist::Value::Dictionary(dict));
+}
+
+#[test]
+fn fidelity_roundtrip() {
+    let gmd = fs::read_to_string("test_gmds/level.gmd").unwrap();
+    // newer than the minimal level, as GD would store it
+    let level = format!(
+        "{}<k>k83</k><i>1</i>",
+        &gmd[gmd.find("<dict>").unwrap() + 6..gmd.rfind("</dict>").unwrap()]
+    );
+    let minimal =
+        "<k>kCEK</k><i>4</i><k>k2</k><s>minimal</s><k>k5</k><s>gdlib</s><k>kI3</k><r>0.8</r>";
+    let savefile = format!(
+        "{PLIST_HEADER}<dict><k>LLM_01</k><d><k>_isArr</k><t /><k>k_0</k><d>{level}</d><k>k_1</k><d>{minimal}</d></d><k>LLM_02</k><i>47</i><k>LLM_03</k><d><k>_isArr</k><t /></d><k>LLM_99</k><s>kept</s></dict>{PLIST_FOOTER}"
+    );
+    let options = ParseOptions::strict().with_fidelity(true);
+
+    // an untouched savefile is reproduced byte for byte
+    let (mut levels, _) =
+        CCLocalLevels::from_decrypted_with_options(savefile.clone(), &options).unwrap();
+    assert_eq!(levels.export_to_string(), savefile);
+    let mut plain = CCLocalLevels::from_decrypted(savefile.clone()).unwrap();
+    assert_ne!(plain.export_to_string(), savefile);
+
+    // untouched levels keep their original text when another level is edited or added
+    levels.levels[1].identity.name = "edited".to_owned();
+    let mut added = GDLevel::default();
+    added.identity.name = "added".to_owned();
+    levels.add_level(added);
+    let exported = levels.export_to_string();
+    assert!(exported.contains(&format!("<k>k_1</k><d>{level}</d><k>k_2</k>")));
+    assert!(exported.contains("<k>LLM_99</k><s>kept</s>"));
+    assert!(!exported.contains(minimal));
+
+    let reparsed = CCLocalLevels::from_decrypted(exported).unwrap();
+    let names = reparsed
+        .levels
+        .iter()
+        .map(|l| l.identity.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["added", "gdlib level", "edited"]);
+}
+
+#[test]
+fn unknown_root_keys_roundtrip() {
+    let savefile = MINIMAL_SAVEFILE.replace(
+        "</dict></plist>",
+        "<k>LLM_04</k><i>12</i><k>MOD_data</k><d><k>a</k><s>b</s></d></dict></plist>",
+    );
+
+    let mut levels = CCLocalLevels::from_decrypted(savefile.clone()).unwrap();
+    assert_eq!(
+        levels.other_properties.keys().collect::<Vec<_>>(),
+        ["LLM_04", "MOD_data"]
+    );
+    let reparsed = CCLocalLevels::from_decrypted(levels.export_to_string()).unwrap();
+    assert_eq!(reparsed.other_properties, levels.other_properties);
+
+    // in fidelity mode, edits to other properties are spliced into the original text
+    let options = ParseOptions::strict().with_fidelity(true);
+    let (mut levels, _) = CCLocalLevels::from_decrypted_with_options(savefile, &options).unwrap();
+    levels.other_properties.remove("MOD_data");
+    levels
+        .other_properties
+        .insert("LLM_04".to_owned(), plist::Value::from(13));
+    levels
+        .other_properties
+        .insert("MOD_new".to_owned(), plist::Value::from("<new>"));
+    assert_eq!(
+        levels.export_to_string(),
+        MINIMAL_SAVEFILE.replace(
+            "</dict></plist>",
+            "<k>LLM_04</k><i>13</i><k>MOD_new</k><s>&lt;new&gt;</s></dict></plist>"
+        )
+    );
+}
+
+#[test]
+fn native_plist_parser_matches_plist_crate() {
+    let gmd = fs::read_to_string("test_gmds/level.gmd").unwrap();
+    let level = &gmd[gmd.find("<dict>").unwrap() + 6..gmd.rfind("</dict>").unwrap()];
+    let gmdl = fs::read_to_string("test_gmds/Summer Vacation.gmdl").unwrap();
+    let list = &gmdl[gmdl.find("<dict>").unwrap() + 6..gmdl.rfind("</dict>").unwrap()];
+    let savefile = format!(
+        "{PLIST_HEADER}<dict><k>LLM_01</k><d><k>_isArr</k><t /><k>k_0</k><d>{level}</d><k>k_1</k><d><k>k2</k><s>R&D &amp; &#60;3 &#x263A;</s><k>kI3</k><r>1e+06</r></d></d><k>LLM_02</k><i>47</i><k>LLM_03</k><d><k>_isArr</k><t /><k>k_0</k><d>{list}</d></d><k>a&amp;b</k><array><i>-1</i><r>0.5</r><f /><d /></array><k>when</k><date>2024-01-02T03:04:05Z</date><k>blob</k><data>Z2Q=</data></dict>{PLIST_FOOTER}"
+    );
+    let parse = |xml: String, parser| {
+        let options = ParseOptions::strict().with_parser(parser);
+        CCLocalLevels::from_decrypted_with_options(xml, &options)
+            .unwrap()
+            .0
+    };
+
+    let mut native = parse(savefile.clone(), PlistParser::Native);
+    let expected = parse(savefile.clone(), PlistParser::PlistCrate).export_to_string();
+    assert_eq!(native.export_to_string(), expected);
+    assert_eq!(native.levels[1].identity.name, "R&D & <3 \u{263A}");
+    assert_eq!(native.lists.len(), 1);
+    assert_eq!(
+        native.other_properties.keys().collect::<Vec<_>>(),
+        ["a&b", "when", "blob"]
+    );
+
+    // documents that the native parser does not understand are handed to the plist crate
+    let commented = savefile.replace("<k>LLM_02</k>", "<!-- version --><k>LLM_02</k>");
+    assert_eq!(
+        parse(commented, PlistParser::Native).export_to_string(),
+        expected
+    );
+
+    // value errors are not retried
+    let broken = savefile.replace("<k>kI3</k><r>1e+06</r>", "<k>kI3</k><s>far</s>");
+    let err = CCLocalLevels::from_decrypted(broken).unwrap_err();
+    assert_eq!(err.path(), Some("LLM_01/k_1/kI3"));
+}
+
+#[test]
+fn lazy_savefile_loading() {
+    let gmd = fs::read_to_string("test_gmds/level.gmd").unwrap();
+    let level = format!(
+        "<k>k83</k><i>7</i>{}",
+        &gmd[gmd.find("<dict>").unwrap() + 6..gmd.rfind("</dict>").unwrap()]
+    );
+    let minimal = "<k>kCEK</k><i>4</i><k>k1</k><i>77</i><k>k2</k><s>minimal</s><k>k83</k><i>2</i><k>k84</k><i>3</i>";
+    let savefile = format!(
+        "{PLIST_HEADER}<dict><k>LLM_01</k><d><k>_isArr</k><t /><k>k_0</k><d>{level}</d><k>k_1</k><d>{minimal}</d></d><k>LLM_02</k><i>47</i><k>LLM_03</k><d><k>_isArr</k><t /></d></dict>{PLIST_FOOTER}"
+    );
+
+    let mut levels = LazyCCLocalLevels::from_decrypted(savefile.clone()).unwrap();
+    assert_eq!(levels.len(), 2);
+    let entry = levels.entry(1).unwrap();
+    assert_eq!(
+        (
+            entry.name.as_str(),
+            entry.id,
+            entry.folder,
+            entry.level_order
+        ),
+        ("minimal", 77, 3, 2)
+    );
+    assert_eq!(
+        levels.get_by_id(77).unwrap().unwrap().identity.name,
+        "minimal"
+    );
+    assert!(levels.get_by_name("missing").unwrap().is_none());
+    assert_eq!(levels.iter().filter(Result::is_ok).count(), 2);
+    assert_eq!(levels.export_to_string(), savefile);
+
+    // only edited and added levels are serialised again
+    levels.get_mut(1).unwrap().unwrap().identity.name = "edited".to_owned();
+    let mut new = GDLevel::default();
+    new.identity.name = "new".to_owned();
+    levels.add_level(new);
+    assert_eq!(levels.position_by_name("edited"), Some(2));
+    let exported = levels.export_to_string();
+    assert!(exported.contains(&format!("<k>k_1</k><d>{level}</d>")));
+    assert!(!exported.contains(minimal));
+
+    let names = CCLocalLevels::from_decrypted(exported)
+        .unwrap()
+        .levels
+        .into_iter()
+        .map(|l| l.identity.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["new", "gdlib level", "edited"]);
+
+    // added levels are ordered above the newest level, like `CCLocalLevels::add_level` does
+    assert_eq!(levels.entry(0).unwrap().level_order, 8);
+    let mut newer = GDLevel::default();
+    newer.meta.level_order = 20;
+    levels.add_level(newer);
+    assert_eq!(levels.entry(0).unwrap().level_order, 20);
+}
+
+#[test]
+fn decompress_detects_container() {
+    use base64::{Engine, engine::general_purpose};
+    use flate2::{
+        Compression, GzBuilder,
+        write::{DeflateEncoder, ZlibEncoder},
+    };
+    use std::io::Write;
+
+    let level =
+        "kS38,1_0_2_0_3_0_11_255_12_255_13_255_4_-1_6_1000_7_1_15_1_18_0_8_1|;1,1,2,15,3,15;";
+    let mut gzip = GzBuilder::new()
+        .filename("level.txt")
+        .extra(vec![1, 2, 3])
+        .comment("gdlib")
+        .write(vec![], Compression::default());
+    gzip.write_all(level.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+    zlib.write_all(level.as_bytes()).unwrap();
+    let zlib = zlib.finish().unwrap();
+    let mut deflate = DeflateEncoder::new(vec![], Compression::default());
+    deflate.write_all(level.as_bytes()).unwrap();
+    let deflate = deflate.finish().unwrap();
+
+    let cases = [
+        (encrypt_level_str(level), CompressionContainer::Gzip),
+        (
+            general_purpose::URL_SAFE.encode(&gzip).into_bytes(),
+            CompressionContainer::Gzip,
+        ),
+        (gzip.clone(), CompressionContainer::Gzip),
+        (
+            general_purpose::STANDARD.encode(&zlib).into_bytes(),
+            CompressionContainer::Zlib,
+        ),
+        (
+            general_purpose::URL_SAFE_NO_PAD.encode(&zlib).into_bytes(),
+            CompressionContainer::Zlib,
+        ),
+        (
+            general_purpose::STANDARD.encode(&deflate).into_bytes(),
+            CompressionContainer::RawDeflate,
+        ),
+    ];
+    for (data, container) in cases {
+        let (decompressed, detected) = decompress_detect(data).unwrap();
+        assert_eq!(detected, container);
+        assert_eq!(decompressed, level.as_bytes());
+    }
+
+    // the trailer is validated
+    let mut bad_crc = gzip.clone();
+    let len = bad_crc.len();
+    bad_crc[len - 8] ^= 0xff;
+    assert!(matches!(
+        decompress(general_purpose::URL_SAFE.encode(&bad_crc).into_bytes()),
+        Err(GDError::GzipTrailerMismatch { field: "CRC32", .. })
+    ));
+    let mut bad_size = gzip.clone();
+    bad_size[len - 1] ^= 0xff;
+    assert!(matches!(
+        decompress(bad_size),
+        Err(GDError::GzipTrailerMismatch { field: "ISIZE", .. })
+    ));
+
+    // short input is an error rather than a panic
+    assert!(matches!(
+        decompress(b"H4sIAA".to_vec()),
+        Err(GDError::CorruptedSavefile(_))
+    ));
+    assert!(decompress(gzip[..len - 4].to_vec()).is_err());
+}
+
+#[test]
+fn streaming_encryption() {
+    use std::io::{Read, Write};
+
+    /// Reader that returns at most 3 bytes at a time
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let mut writer = GdEncryptWriter::new(vec![]);
+    for chunk in MINIMAL_SAVEFILE.as_bytes().chunks(7) {
+        writer.write_all(chunk).unwrap();
+    }
+    let encrypted = writer.finish().unwrap();
+    assert_eq!(encrypted, encrypt_savefile_str(MINIMAL_SAVEFILE));
+    assert_eq!(
+        decrypt(encrypted.clone()).unwrap(),
+        MINIMAL_SAVEFILE.as_bytes()
+    );
+
+    let mut decrypted = String::new();
+    GdDecryptReader::new(Trickle(&encrypted))
+        .read_to_string(&mut decrypted)
+        .unwrap();
+    assert_eq!(decrypted, MINIMAL_SAVEFILE);
+
+    let mut level_data = String::new();
+    GdDecryptReader::without_xor(encrypt_level_str("1,1,2,15;").as_slice())
+        .read_to_string(&mut level_data)
+        .unwrap();
+    assert_eq!(level_data, "1,1,2,15;");
+
+    // savefiles can be parsed from and exported to any reader or writer
+    let mut levels = CCLocalLevels::from_reader(Trickle(&encrypted)).unwrap();
+    assert_eq!(levels.levels[0].identity.name, "minimal");
+    let exported = levels.export_to_writer(vec![]).unwrap();
+    let reparsed = CCLocalLevels::from_reader(exported.as_slice()).unwrap();
+    assert_eq!(reparsed.levels[0].identity.name, "minimal");
+
+    // like `decompress`, the reader accepts both base64 alphabets and zlib or raw deflate
+    {
+        use base64::{Engine, engine::general_purpose};
+        use flate2::{
+            Compression,
+            write::{DeflateEncoder, ZlibEncoder},
+        };
+
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(MINIMAL_SAVEFILE.as_bytes()).unwrap();
+        let zlib = zlib.finish().unwrap();
+        let mut deflate = DeflateEncoder::new(vec![], Compression::default());
+        deflate.write_all(MINIMAL_SAVEFILE.as_bytes()).unwrap();
+        let deflate = deflate.finish().unwrap();
+        let gzip = b64_decode(encrypt_level_str(MINIMAL_SAVEFILE)).unwrap();
+
+        for payload in [
+            general_purpose::STANDARD.encode(&zlib),
+            general_purpose::URL_SAFE_NO_PAD.encode(&deflate),
+            general_purpose::STANDARD.encode(&gzip),
+        ] {
+            let xored = payload.bytes().map(|b| b ^ 11).collect::<Vec<_>>();
+            let mut decrypted = String::new();
+            GdDecryptReader::new(Trickle(&xored))
+                .read_to_string(&mut decrypted)
+                .unwrap();
+            assert_eq!(decrypted, MINIMAL_SAVEFILE);
+        }
+
+        // `decrypt_savefile` accepts the standard alphabet as well
+        let standard = general_purpose::STANDARD
+            .encode(&gzip)
+            .bytes()
+            .map(|b| b ^ 11)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decrypt_savefile(standard).unwrap(),
+            (
+                MINIMAL_SAVEFILE.as_bytes().to_vec(),
+                SavefilePlatform::Windows
+            )
+        );
+    }
+
+    // a corrupted trailer is reported precisely, even through `io::Read`
+    let mut gzip = b64_decode(encrypt_level_str("tampered")).unwrap();
+    *gzip.last_mut().unwrap() ^= 1;
+    let err = GdDecryptReader::without_xor(b64_encode(gzip).as_bytes())
+        .read_to_end(&mut vec![])
+        .unwrap_err();
+    assert!(matches!(
+        GDError::from(err),
+        GDError::GzipTrailerMismatch { field: "ISIZE", .. }
+    ));
+}
+
+#[test]
+fn level_management() {
+    let mut levels = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    levels.levels[0].meta.level_order = 4;
+    levels.levels[0].identity.id = 1234;
+    let named = |name: &str| {
+        let mut level = GDLevel::default();
+        level.identity.name = name.to_owned();
+        level
+    };
+    levels.add_level(named("Level 2"));
+    levels.add_level(named("other"));
+
+    let names = |levels: &CCLocalLevels| {
+        levels
+            .levels
+            .iter()
+            .map(|l| (l.identity.name.clone(), l.meta.level_order))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(levels.position_by_name("minimal"), Some(2));
+    assert_eq!(levels.find_by_id(1234).unwrap().identity.name, "minimal");
+    assert!(levels.find_by_name("missing").is_none());
+
+    // copies are named after the original and added to the front as new local levels
+    assert_eq!(levels.duplicate_level(1), Some(0));
+    assert_eq!(levels.duplicate_level(3), Some(0));
+    let copy = &levels.levels[0];
+    assert_eq!(copy.identity.name, "minimal 2");
+    assert_eq!(
+        (copy.identity.id, copy.identity.original_id),
+        (0, Some(1234))
+    );
+    assert_eq!(
+        names(&levels),
+        [
+            ("minimal 2".to_owned(), 8),
+            ("Level 3".to_owned(), 7),
+            ("other".to_owned(), 6),
+            ("Level 2".to_owned(), 5),
+            ("minimal".to_owned(), 4),
+        ]
+    );
+
+    // a copy of the newest level does not share its order
+    assert_eq!(levels.duplicate_level(0), Some(0));
+    assert_eq!(levels.levels[0].identity.name, "minimal 3");
+    assert_eq!(levels.levels[0].meta.level_order, 9);
+    assert_eq!(levels.remove_level(0).unwrap().meta.level_order, 9);
+
+    assert_eq!(levels.rename_level(2, "renamed"), Some("other".to_owned()));
+    assert!(levels.move_to_front(4));
+    assert!(!levels.move_level(0, 5));
+    assert!(!levels.reorder_levels(&[0, 0, 1, 2, 3]));
+    assert!(levels.reorder_levels(&[4, 3, 2, 1, 0]));
+    assert_eq!(levels.remove_level(0).unwrap().identity.name, "Level 2");
+    assert!(levels.remove_level(4).is_none());
+    assert_eq!(
+        names(&levels),
+        [
+            ("renamed".to_owned(), 12),
+            ("Level 3".to_owned(), 11),
+            ("minimal 2".to_owned(), 10),
+            ("minimal".to_owned(), 9),
+        ]
+    );
+
+    #[cfg(feature = "regex")]
+    {
+        let pattern = regex::Regex::new("^minimal").unwrap();
+        assert_eq!(levels.positions_matching(&pattern), [2, 3]);
+        assert_eq!(levels.find_by_regex(&pattern).count(), 2);
+    }
+
+    // the order of the levels is their order on export
+    let reparsed = CCLocalLevels::from_decrypted(levels.export_to_string()).unwrap();
+    assert_eq!(names(&reparsed), names(&levels));
+}
+
+#[test]
+fn folder_management() {
+    let gamemanager = format!(
+        "{PLIST_HEADER}<dict><k>playerName</k><s>gdlib</s><k>GLM_19</k><d><k>2</k><s>old</s></d><k>bgVolume</k><r>0.5</r></dict>{PLIST_FOOTER}"
+    );
+    let mut levels = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    levels.levels[0].meta.level_folder = 2;
+    for name in ["a", "b", "c"] {
+        let mut level = GDLevel::default();
+        level.identity.name = name.to_owned();
+        levels.add_level(level);
+    }
+
+    let mut folders = Folders::from_decrypted(levels, gamemanager.clone()).unwrap();
+    assert_eq!(
+        folders.folders(),
+        [Folder {
+            id: 2,
+            name: Some("old".to_owned()),
+            levels: vec![3],
+        }]
+    );
+    assert_eq!(folders.unfiled_levels(), [0, 1, 2]);
+
+    // folder 1 is the first one without a name or levels
+    let generated = folders.create_folder("generated");
+    assert_eq!(generated, 1);
+    assert_eq!(folders.move_levels(&[0, 2, 7], generated), 2);
+    assert!(folders.move_level(3, 0));
+    assert!(folders.rename_folder(2, "renamed"));
+    assert!(!folders.rename_folder(0, "unfiled"));
+    assert_eq!(folders.create_folder("third"), 3);
+    assert_eq!(folders.folder_by_name("renamed"), Some(2));
+    assert_eq!(folders.levels_in(generated), [0, 2]);
+    assert_eq!(folders.unfiled_levels(), [1, 3]);
+
+    // only GLM_19 changes in CCGameManager.dat
+    let exported = folders.export_gamemanager_to_string().unwrap();
+    assert_eq!(
+        exported,
+        gamemanager.replace(
+            "<d><k>2</k><s>old</s></d>",
+            "<d><k>1</k><s>generated</s><k>2</k><s>renamed</s><k>3</k><s>third</s></d>"
+        )
+    );
+    let reparsed = CCGameManager::from_raw_string(exported).unwrap();
+    assert_eq!(
+        reparsed.config.local_levels_foldernames,
+        folders.folder_names()
+    );
+
+    assert_eq!(folders.delete_folder(1), Some("generated".to_owned()));
+    assert_eq!(folders.unfiled_levels(), [0, 1, 2, 3]);
+
+    // the key is added if no folder was named before
+    let without_names = gamemanager.replace("<k>GLM_19</k><d><k>2</k><s>old</s></d>", "");
+    let levels = folders.levels.clone();
+    let mut folders = Folders::from_decrypted(levels, without_names).unwrap();
+    assert!(folders.folder_names().is_empty());
+    folders.create_folder("new");
+    let exported = folders.export_gamemanager_to_string().unwrap();
+    assert!(exported.ends_with("<k>GLM_19</k><d><k>1</k><s>new</s></d></dict></plist>"));
+}
+
+#[test]
+fn merge_from_other_savefile() {
+    let level = |name: &str, id: i32, version: i32| {
+        let mut level = GDLevel::default();
+        level.identity.name = name.to_owned();
+        level.identity.id = id;
+        level.identity.level_version = version;
+        level
+    };
+    let list = |name: &str, ids: &[i32]| GDList {
+        name: name.to_owned(),
+        level_ids: ids.to_vec(),
+        levels: ids.iter().map(|id| level("summary", *id, 1)).collect(),
+        ..GDList::default()
+    };
+
+    let mut ours = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    ours.add_level(level("shared", 0, 1));
+    ours.add_level(level("online", 100, 1));
+    ours.lists.push(list("ours", &[100]));
+
+    let mut theirs = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    theirs.levels[0].meta.level_order = 12;
+    theirs.add_level(level("shared", 0, 2));
+    let mut reupload = level("reupload", 200, 3);
+    reupload.identity.original_id = Some(100);
+    theirs.add_level(reupload);
+    theirs.add_level(level("new", 0, 1));
+    theirs.lists.push(list("theirs", &[200]));
+
+    let outcomes = |policy| {
+        let mut merged = ours.clone();
+        let report = merged.merge_from(&theirs, policy);
+        (merged, report)
+    };
+
+    // exact copies are never merged, even though their order differs
+    let (merged, report) = outcomes(MergePolicy::Skip);
+    assert_eq!(
+        report.levels,
+        [
+            MergeOutcome::Added,
+            MergeOutcome::Skipped,
+            MergeOutcome::Skipped,
+            MergeOutcome::Identical
+        ]
+    );
+    assert_eq!(report.lists, [MergeOutcome::Added]);
+    assert_eq!(merged.levels.len(), 4);
+    assert_eq!(merged.levels[0].identity.name, "new");
+    // the merged list now points at our copy of the level
+    assert_eq!(merged.lists[0].level_ids, [100]);
+    assert_eq!(merged.lists[0].levels[0].identity.name, "online");
+    assert!(
+        merged
+            .levels
+            .windows(2)
+            .all(|l| l[0].meta.level_order >= l[1].meta.level_order)
+    );
+
+    let (merged, report) = outcomes(MergePolicy::Rename);
+    assert_eq!(
+        report.levels[..3],
+        [
+            MergeOutcome::Added,
+            MergeOutcome::Renamed("reupload 2".to_owned()),
+            MergeOutcome::Renamed("shared 2".to_owned()),
+        ]
+    );
+    assert_eq!(merged.levels.len(), 6);
+    assert_eq!(merged.lists[1].level_ids, [100]);
+
+    // overwritten levels keep their place, and our list follows the new ID
+    let (merged, report) = outcomes(MergePolicy::Overwrite);
+    assert_eq!(report.levels[1], MergeOutcome::Overwritten);
+    assert_eq!(merged.levels[1].identity.name, "reupload");
+    assert_eq!(merged.lists[1].level_ids, [200]);
+    assert_eq!(merged.lists[1].levels[0].identity.name, "reupload");
+
+    let mut older = theirs.clone();
+    older.levels[1].identity.level_version = 1;
+    let mut merged = ours.clone();
+    let report = merged.merge_from(&older, MergePolicy::KeepNewest);
+    assert_eq!(
+        report.levels[1..3],
+        [MergeOutcome::Skipped, MergeOutcome::Overwritten]
+    );
+    assert_eq!(merged.levels[2].identity.level_version, 2);
+
+    // lists that we already had are not re-linked to a level that was skipped
+    let mut bookmarked = ours.clone();
+    bookmarked.lists.push(list("bookmarks", &[200]));
+    bookmarked.merge_from(&theirs, MergePolicy::Skip);
+    let bookmarks = bookmarked
+        .lists
+        .iter()
+        .find(|l| l.name == "bookmarks")
+        .unwrap();
+    assert_eq!(bookmarks.level_ids, [200]);
+    assert_eq!(bookmarks.levels[0].identity.id, 200);
+    assert_eq!(bookmarks.levels[0].identity.name, "summary");
+}
+
+#[test]
+fn structural_diff() {
+    let raw = format!(
+        "{DEFAULT_LEVEL_HEADERS};1,1,2,15,3,15;1,1,2,45,3,15;1,8,2,75,3,15;1,1,2,105,3,15,21,4;"
+    );
+    let old_data = GDLevelData::parse(String::from_utf8(encrypt_level_str(&raw)).unwrap()).unwrap();
+    let mut new_data = old_data.clone();
+    // moved, deleted, recoloured, added
+    new_data.objects[0].config.pos = (30.0, 15.0);
+    let _ = new_data.objects.remove(2);
+    new_data.objects[2].config.colour_channels.0 = ColourChannel::from(5);
+    new_data
+        .objects
+        .push(GDObject::parse_str("1,1,2,135,3,15;"));
+    new_data
+        .headers
+        .set_property(ids::level_header::AUDIO_TRACK, HeaderValue::Int(5));
+
+    let diff = diff_level_data(&old_data, &new_data);
+    assert_eq!(
+        diff.headers
+            .iter()
+            .map(|h| (h.name.as_ref(), h.old.clone(), h.new.clone()))
+            .collect::<Vec<_>>(),
+        [("Audio track", None, Some(HeaderValue::Int(5)))]
+    );
+    let summary = diff
+        .objects
+        .iter()
+        .map(|o| match o {
+            ObjectDiff::Removed { index, object } => format!("-{index} {}", object.id),
+            ObjectDiff::Added { index, object } => format!("+{index} {}", object.id),
+            ObjectDiff::Modified {
+                old_index,
+                new_index,
+                changes,
+                ..
+            } => {
+                let changes = changes
+                    .iter()
+                    .map(|c| format!("{}={}", c.name, c.new.as_ref().unwrap()))
+                    .collect::<Vec<_>>();
+                format!("~{old_index}>{new_index} {}", changes.join(" "))
+            }
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        ["-2 8", "~0>0 x pos=30.0", "~3>2 Object colour=5", "+3 1"]
+    );
+    assert!(diff_level_data(&old_data, &old_data).is_empty());
+
+    // objects that only differ in the order of their properties are unchanged
+    let mut reordered = old_data.clone();
+    reordered.objects[0] = GDObject::parse_str("1,1,2,15,3,15,36,1,85,2;");
+    let mut original = reordered.clone();
+    original.objects[0].properties.reverse();
+    assert_ne!(
+        reordered.objects[0].serialise_to_string(),
+        original.objects[0].serialise_to_string()
+    );
+    assert!(diff_level_data(&original, &reordered).is_empty());
+
+    let mut old = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    old.levels[0].content.data = Some(GDLevelState::Decrypted(Box::new(old_data)));
+    let mut removed = GDLevel::default();
+    removed.identity.name = "removed".to_owned();
+    old.add_level(removed);
+
+    let mut new = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    new.levels[0].content.data = Some(GDLevelState::Decrypted(Box::new(new_data)));
+    new.levels[0].identity.description = Some("generated".to_owned());
+    let mut added = GDLevel::default();
+    added.identity.name = "added".to_owned();
+    new.add_level(added);
+
+    let diff = diff_savefiles(&old, &new).unwrap();
+    assert_eq!(diff.added[0].name, "added");
+    assert_eq!(diff.removed[0].name, "removed");
+    let (key, level_diff) = &diff.changed[0];
+    assert_eq!(key.name, "minimal");
+    assert_eq!(
+        level_diff
+            .fields
+            .iter()
+            .map(|f| f.key.as_str())
+            .collect::<Vec<_>>(),
+        ["k3"]
+    );
+    assert_eq!(level_diff.data.objects.len(), 4);
+
+    let text = diff.to_string();
+    assert!(text.contains("- level \"removed\"\n+ level \"added\"\n~ level \"minimal\"\n"));
+    assert!(text.contains("    ~ object 3 -> 2: "));
+    assert!(text.contains("        Object colour: 4 -> 5\n"));
+}
+
+#[test]
+fn bulk_export_and_import() {
+    let dir = "test_gmds/generated_bulk";
+    let _ = fs::remove_dir_all(dir);
+
+    let mut levels = CCLocalLevels::from_decrypted(MINIMAL_SAVEFILE.to_owned()).unwrap();
+    levels.levels[0].meta.level_folder = 3;
+    // long names are cut to the byte limit of file names without splitting a character
+    let long = "日".repeat(100);
+    for name in ["Level", "level", "a/b: c?", "CON", "", "dots...", &long] {
+        let mut level = GDLevel::default();
+        level.identity.name = name.to_owned();
+        levels.add_level(level);
+    }
+    levels.lists.push(GDList {
+        name: "Level".to_owned(),
+        order: 2,
+        ..GDList::default()
+    });
+    levels
+        .other_properties
+        .insert("LLM_99".to_owned(), plist::Value::from(7));
+
+    levels.export_all_to_dir(dir, FileNaming::Name).unwrap();
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|f| f.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "Level (2).gmd",
+            "Level.gmdl",
+            "Unnamed.gmd",
+            "_CON.gmd",
+            "a_b_ c_.gmd",
+            "dots.gmd",
+            "level.gmd",
+            MANIFEST_FILE,
+            "minimal.gmd",
+            &format!("{}.gmd", "日".repeat(33)),
+        ]
+    );
+
+    // the import rebuilds the same savefile, including the order and folders
+    let mut imported = CCLocalLevels::import_dir(dir).unwrap();
+    assert_eq!(imported.export_to_string(), levels.export_to_string());
+
+    levels
+        .export_all_to_dir(dir, FileNaming::IndexedName)
+        .unwrap();
+    assert!(fs::exists(format!("{dir}/7 minimal.gmd")).unwrap());
+    let mut imported = CCLocalLevels::import_dir(dir).unwrap();
+    assert_eq!(imported.export_to_string(), levels.export_to_string());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn refresh_level_metadata() {
+    let raw = format!(
+        "{DEFAULT_LEVEL_HEADERS};1,1,2,15,3,15;1,1329,2,45,3,15;1,1934,2,75,3,15,392,5;1,1934,2,105,3,15,392,7;1,3602,2,135,3,15,392,100;"
+    );
+    let mut level = GDLevel::default();
+    level.content.custom_song_id = Some(5);
+    let stored = String::from_utf8(encrypt_level_str(&raw)).unwrap();
+    let stored_size = (stored.len() as f64 * 0.152).floor() as i32;
+    level.content.data = Some(GDLevelState::Encrypted(GDEncryptedLevelData {
+        data: stored,
+    }));
+
+    level.refresh_metadata().unwrap();
+    assert_eq!(level.content.object_count, 5);
+    assert!(!level.content.high_object_count);
+    assert_eq!(level.content.song_list, [5, 7]);
+    assert_eq!(level.content.sfx_list, Some(vec![100]));
+    assert_eq!(level.coins.total_coins, 1);
+    // encrypted data is sized by the level string that is exported as k4
+    assert_eq!(level.meta.level_size, stored_size);
+    assert!(level.flags.has_been_modified);
+    // encrypted data stays encrypted
+    assert!(matches!(
+        level.content.data,
+        Some(GDLevelState::Encrypted(_))
+    ));
+
+    // decrypted data is sized by its encrypted length too
+    level.decrypt_level_data().unwrap();
+    level.refresh_metadata().unwrap();
+    let serialised = level
+        .get_decrypted_data()
+        .unwrap()
+        .unwrap()
+        .serialise_to_string();
+    assert_eq!(
+        level.meta.level_size,
+        (encrypt_level_str(&serialised).len() as f64 * 0.152).floor() as i32
+    );
+
+    // stale metadata is only replaced on export when opted in
+    let config = GDObjConfig::default();
+    level.add_objects([default_block(&config), default_block(&config)]);
+    assert_eq!(
+        level.to_dict().get("k48").unwrap().as_signed_integer(),
+        Some(5)
+    );
+    level.refresh_metadata_on_export = true;
+    assert_eq!(
+        level.to_dict().get("k48").unwrap().as_signed_integer(),
+        Some(7)
+    );
+    assert_eq!(level.content.object_count, 5);
+}
+
+#[test]
+fn level_header_serialisation() {
+    let colours = "1_0_2_0_3_0_4_-1_6_1000_11_255_12_255_13_255|";
+    let mut headers = GDLevelHeader::parse(&format!("kS38,{colours}")).unwrap();
+    headers.set_property(ids::level_header::AUDIO_TRACK, HeaderValue::Int(5));
+    headers.set_property(ids::level_header::PLAFORMER_MODE, HeaderValue::Bool(true));
+
+    // kA and kS properties are separated by a single comma, and booleans are stored as 1/0
+    let serialised = headers.to_string();
+    assert_eq!(serialised, format!("kA1,5,kA22,1,kS38,{colours}"));
+    assert_eq!(GDLevelHeader::parse(&serialised).unwrap(), headers);
+}
+
+#[test]
+fn level_builder() {
+    let level = GDLevelBuilder::new("Built")
+        .description("made by a builder")
+        .creator("gdlib")
+        .custom_song(1_000_001)
+        .platformer(true)
+        .gamemode(Gamemode::Robot)
+        .speed(Speed::X2)
+        .background(12)
+        .middleground(0)
+        .colour(ColourChannel::Background, Colour::from_rgb((1, 2, 3)))
+        .colour(ColourChannel::Channel(7), Colour::from_rgb((4, 5, 6)))
+        .build()
+        .unwrap();
+
+    assert_eq!(level.identity.binary_version, 47);
+    assert!(level.content.is_platformer);
+    assert_eq!(level.content.custom_song_id, Some(1_000_001));
+    let Some(GDLevelState::Decrypted(data)) = &level.content.data else {
+        panic!("level data should be decrypted");
+    };
+    let headers = &data.headers;
+    assert_eq!(
+        headers.get_property(ids::level_header::GAMEMODE),
+        Some(&HeaderValue::Gamemode(Gamemode::Robot))
+    );
+    assert_eq!(
+        headers.get_property(ids::level_header::PLAFORMER_MODE),
+        Some(&HeaderValue::Bool(true))
+    );
+    assert_eq!(
+        headers.get_property(ids::level_header::BACKGROUND_TEXTURE_ID),
+        Some(&HeaderValue::Int(12))
+    );
+    let Some(HeaderValue::ColourString(colours)) = headers.get_property(ids::level_header::COLOURS)
+    else {
+        panic!("level should have colours");
+    };
+    // both the colour a channel starts as and the one it changes to are set
+    let colour_of = |idx| {
+        let c = colours.iter().find(|c| c.colour_ch_idx == idx).unwrap();
+        (c.from, c.to)
+    };
+    let (bg, ch7) = (Colour::from_rgb((1, 2, 3)), Colour::from_rgb((4, 5, 6)));
+    assert_eq!(colour_of(1000), (bg, bg));
+    assert_eq!(colour_of(7), (ch7, ch7));
+
+    // the level survives serialisation
+    let mut parsed = GDLevel::from_dict(&level.to_dict()).unwrap();
+    assert_eq!(
+        parsed.identity.description.as_deref(),
+        Some("made by a builder")
+    );
+    assert_eq!(
+        parsed.get_decrypted_data_ref().unwrap().unwrap().headers,
+        *headers
+    );
+    // colour strings are written like GD, with a separator after the last segment
+    let colours = "kS38,1_0_2_0_3_0_4_-1_6_1000_11_255_12_255_13_255|1_1_2_2_3_3_4_-1_6_7_11_255_12_255_13_255|";
+    assert_eq!(GDLevelHeader::parse(colours).unwrap().to_string(), colours);
+
+    let invalid = [
+        GDLevelBuilder::new(""),
+        GDLevelBuilder::new("a name that is far too long"),
+        GDLevelBuilder::new("x")
+            .official_song(OfficialSong::Jumper)
+            .custom_song(5),
+        GDLevelBuilder::new("x").ground(23),
+        GDLevelBuilder::new("x").colour(ColourChannel::Object, Colour::default()),
+    ];
+    let paths = invalid
+        .into_iter()
+        .map(|b| b.build().unwrap_err().path().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["k2", "k2", "k45", "k4/kA7", "k4/kS38"]);
+}
+
+#[test]
+fn typed_level_header() {
+    let header = LevelHeaderBuilder::new()
+        .gamemode(Gamemode::Swing)
+        .speed(Speed::X0Point5)
+        .mini_mode(true)
+        .song_offset(1.5)
+        .background_texture(7)
+        .fix_robot_jump(false)
+        .colour_page(2)
+        .build();
+
+    assert_eq!(header.gamemode(), Gamemode::Swing);
+    assert_eq!(header.speed(), Speed::X0Point5);
+    assert!(header.mini_mode());
+    assert_eq!(header.song_offset(), 1.5);
+    assert_eq!(header.background_texture(), 7);
+    assert_eq!(header.ka[34], Some(HeaderValue::Bool(false)));
+    assert_eq!(header.ks[39], Some(HeaderValue::Int(2)));
+    // untouched properties keep the values of a new level
+    assert!(header.fix_gravity_bug());
+    assert_eq!(header.colours().map(<[_]>::len), Some(8));
+
+    let parsed = GDLevelHeader::parse(&header.to_string()).unwrap();
+    assert_eq!(parsed, header);
+
+    // missing properties read as what GD assumes
+    let mut empty = GDLevelHeader::parse("").unwrap();
+    assert_eq!(empty.speed(), Speed::X1);
+    assert!(!empty.flip_gravity());
+    assert_eq!(empty.guidelines(), None);
+
+    // kS0 is a kS property, not kA1000
+    empty.set_property(KS_OFFSET, HeaderValue::Int(3));
+    assert_eq!(empty.ks[0], Some(HeaderValue::Int(3)));
+    assert_eq!(empty.get_property(KS_OFFSET), Some(&HeaderValue::Int(3)));
+    empty.del_property(KS_OFFSET);
+    assert_eq!(empty.ks[0], None);
+}
+
+#[test]
+fn colour_palette() {
+    let mut palette = Palette::parse(
+        "1_255_2_0_3_0_11_255_12_255_13_255_6_1000_7_1|1_1_2_2_3_3_11_255_12_255_13_255_6_4_7_0.5|",
+    )
+    .unwrap();
+    assert_eq!(palette.channels().len(), 2);
+    assert_eq!(
+        palette.colour(ColourChannel::Channel(4)),
+        Some(Colour::from_rgb((1, 2, 3)))
+    );
+    assert_eq!(palette.opacity(ColourChannel::Channel(4)), Some(0.5));
+    assert_eq!(palette.colour(ColourChannel::Line), None);
+
+    palette.set_opacity(ColourChannel::Channel(4), 0.25);
+    let channel = palette.channel(ColourChannel::Channel(4)).unwrap();
+    assert_eq!((channel.from_opacity, channel.to_opacity), (0.25, 0.25));
+    palette.set_opacity(ColourChannel::Channel(4), 0.5);
+
+    palette.fill_defaults();
+    let channels = palette
+        .channels()
+        .iter()
+        .map(|c| c.colour_ch_idx)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        channels,
+        [1000, 4, 1001, 1009, 1002, 1013, 1014, 1005, 1006, 1003]
+    );
+    // present channels are not replaced by their defaults
+    assert_eq!(
+        palette.colour(ColourChannel::Background),
+        Some(Colour::from_rgb((255, 0, 0)))
+    );
+    assert_eq!(palette.blending(ColourChannel::Line), Some(true));
+
+    let hsv = HSVColour {
+        hue_shift: 90,
+        saturation_mult: 1.0,
+        brightness_mult: 0.5,
+        static_sat_scalar: false,
+        static_bright_scalar: false,
+    };
+    palette.set_player_colour(ColourChannel::Channel(4), PlayerColour::Second);
+    palette.set_copied_colour(
+        ColourChannel::Channel(5),
+        ColourChannel::Channel(4),
+        Some(hsv.clone()),
+    );
+    palette.set_blending(ColourChannel::Channel(5), true);
+    assert_eq!(
+        palette.copied_colour(ColourChannel::Channel(5)),
+        Some((ColourChannel::Channel(4), Some(&hsv)))
+    );
+    assert_eq!(
+        palette.player_colour(ColourChannel::Channel(5)),
+        Some(PlayerColour::None)
+    );
+
+    // round trip through the header
+    let mut header = LevelHeaderBuilder::new().build();
+    palette.write_to(&mut header);
+    let header = GDLevelHeader::parse(&header.to_string()).unwrap();
+    let parsed = Palette::from_header(&header);
+    assert_eq!(parsed, palette);
+    assert_eq!(Palette::parse(&palette.to_string()).unwrap(), palette);
+    assert_eq!(
+        parsed.player_colour(ColourChannel::Channel(4)),
+        Some(PlayerColour::Second)
+    );
+
+    palette.clear_copied_colour(ColourChannel::Channel(5));
+    assert_eq!(palette.copied_colour(ColourChannel::Channel(5)), None);
+    assert!(palette.remove(ColourChannel::Channel(5)).is_some());
+    assert_eq!(palette.channel(ColourChannel::Channel(5)), None);
+}
+
+#[test]
+fn recolour_level() {
+    let red = Colour::from_rgb((255, 0, 0));
+    let blue = Colour::from_rgb((0, 0, 255));
+    let green = Colour::from_rgb((0, 255, 0));
+
+    let mut headers = LevelHeaderBuilder::new().build();
+    let mut palette = Palette::from_header(&headers);
+    palette.set_colour(ColourChannel::Channel(1), red);
+    palette.set_colour(ColourChannel::Channel(2), green);
+    palette.write_to(&mut headers);
+
+    let config = GDObjConfig::default();
+    let trigger_cfg = |channel| ColourTriggerConfig {
+        colour: red,
+        channel,
+        opacity: 1.0,
+        blending: false,
+        use_player_col_1: false,
+        use_player_col_2: false,
+    };
+    let mut data = GDLevelData {
+        headers,
+        objects: vec![
+            colour_trigger(&config, trigger_cfg(ColourChannel::Channel(1)), 0.5, None),
+            // copies channel 2, so its RGB values are unused
+            colour_trigger(
+                &config,
+                trigger_cfg(ColourChannel::Channel(3)),
+                0.5,
+                Some(CopyColourConfig {
+                    original_ch: ColourChannel::Channel(2),
+                    hsv_config: HSVColour::parse("0a1a1a0a0").unwrap(),
+                    use_legacy_hsv: false,
+                    copy_opacity: false,
+                }),
+            ),
+            pulse_trigger(
+                &config,
+                0.0,
+                0.5,
+                0.0,
+                false,
+                &PulseTarget::Channel(PulseChannel { channel_id: 1 }),
+                PulseMode::Colour(red),
+            ),
+            // dark red: channel 1 at half brightness
+            GDObject::parse_str("1,1,2,15,3,15,21,1,41,1,43,0a1a0.5a0a0"),
+        ],
+    };
+
+    let map = Recolour::Map(vec![(red, blue), (Colour::from_rgb((128, 0, 0)), green)]);
+    let dry_run = data.recolour_dry_run(&map);
+    assert_eq!(
+        Palette::from_header(&data.headers).colour(ColourChannel::Channel(1)),
+        Some(red)
+    );
+    let report = data.recolour(&map);
+    assert_eq!(report, dry_run);
+    assert_eq!(report.changes.len(), 4);
+    assert!(
+        report
+            .to_string()
+            .starts_with("colour channel 1: #ff0000 -> #0000ff\n")
+    );
+
+    let palette = Palette::from_header(&data.headers);
+    assert_eq!(palette.colour(ColourChannel::Channel(1)), Some(blue));
+    assert_eq!(palette.colour(ColourChannel::Channel(2)), Some(green));
+    assert_eq!(
+        data.objects[0].get_property(ids::properties::BLUE),
+        Some(GDValue::Int(255))
+    );
+    assert_eq!(
+        data.objects[1].get_property(ids::properties::RED),
+        Some(GDValue::Int(255))
+    );
+    assert_eq!(
+        data.objects[2].get_property(ids::properties::RED),
+        Some(GDValue::Int(0))
+    );
+    // the object still shows the mapped colour on its recoloured channel
+    assert_eq!(
+        data.objects[3].get_property(ids::properties::MAIN_HSV),
+        Some(GDValue::String("-120a1a1a0a0".to_owned()))
+    );
+    assert!(data.recolour_dry_run(&map).is_empty());
+
+    let rotate = Recolour::HSV(HSVColour::parse("120a1a1a0a0").unwrap());
+    let report = data.recolour(&rotate);
+    // HSV overrides are relative to their channel, so they follow a rotation on their own
+    assert!(
+        report
+            .changes
+            .iter()
+            .all(|c| !matches!(c.target, RecolourTarget::HSVOverride { .. }))
+    );
+    let palette = Palette::from_header(&data.headers);
+    assert_eq!(palette.colour(ColourChannel::Channel(1)), Some(red));
+    assert_eq!(palette.colour(ColourChannel::Channel(2)), Some(blue));
+
+    let hsv = HSVColour::parse("10a0.5a1a1a0").unwrap();
+    assert!(hsv.static_sat_scalar && !hsv.static_bright_scalar);
+}
+
+#[test]
+fn guidelines_from_timing() {
+    use GuidelineColour::{Green, Orange, Yellow};
+
+    let guidelines = GuidelineString::from_bpm(120.0, 0.25, 4, 2.5);
+    assert_eq!(
+        guidelines.guidelines,
+        [
+            (0.25, Orange),
+            (0.75, Yellow),
+            (1.25, Yellow),
+            (1.75, Yellow),
+            (2.25, Orange)
+        ]
+    );
+    assert!(
+        GuidelineString::from_bpm(0.0, 0.0, 4, 10.0)
+            .guidelines
+            .is_empty()
+    );
+    // beats before the start of the song still count towards the bar
+    assert_eq!(
+        GuidelineString::from_bpm(60.0, -1.0, 2, 1.0).guidelines,
+        [(0.0, Yellow), (1.0, Orange)]
+    );
+    assert_eq!(
+        GuidelineString::from_bpm(60.0, -1e7, 4, 2.0).guidelines,
+        [(0.0, Orange), (1.0, Yellow), (2.0, Yellow)]
+    );
+    assert!(
+        GuidelineString::from_bpm(120.0, 0.0, 4, f32::INFINITY)
+            .guidelines
+            .is_empty()
+    );
+
+    let subdivided = guidelines.subdivide(2);
+    assert_eq!(subdivided.guidelines.len(), 9);
+    assert_eq!(subdivided.guidelines[1], (0.5, Green));
+
+    let beats = GuidelineString::from_beat_times(&[1.0, 0.5, -1.0, 0.5]);
+    assert_eq!(beats.guidelines, [(0.5, Yellow), (1.0, Yellow)]);
+    assert_eq!(beats.to_string(), "0.5~0.9~1~0.9~");
+    assert_eq!(GuidelineString::parse(&beats.to_string()), Some(beats));
+    assert_eq!(GuidelineString::parse("0.5~1~2"), None);
+
+    // guidelines are shifted to the start of the level
+    let mut header = LevelHeaderBuilder::new().song_offset(1.0).build();
+    header.set_song_guidelines(&guidelines);
+    assert_eq!(
+        header.guidelines().map(|g| g.guidelines.clone()),
+        Some(vec![(0.25, Yellow), (0.75, Yellow), (1.25, Orange)])
+    );
+    let parsed = GDLevelHeader::parse(&header.to_string()).unwrap();
+    assert_eq!(parsed.guidelines(), header.guidelines());
+}
+
+#[test]
+fn speed_portal_timeline() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+    let objects = vec![
+        GDObject::parse_str("1,1,2,0,3,15"),
+        // 1 second at 1x, then 2 seconds at 2x
+        GDObject::parse_str("1,202,2,311.58,3,15"),
+        GDObject::parse_str("1,200,2,1086.42,3,15"),
+        // 7 seconds at 0.5x
+        GDObject::parse_str("1,1,2,2844.54,3,15"),
+    ];
+    let data = GDLevelData {
+        headers: LevelHeaderBuilder::new().build(),
+        objects: objects.clone(),
+    };
+
+    let timeline = Timeline::new(&data);
+    assert!(close(timeline.time_at(311.58), 1.0));
+    assert!(close(timeline.time_at(1086.42), 3.0));
+    assert!(close(timeline.x_at(1.5), 311.58 + 387.42 / 2.0));
+    assert!(close(timeline.x_at(3.0), 1086.42));
+    assert!(close(timeline.time_at(-311.58), -1.0));
+    assert_eq!(timeline.speed_at(500.0), Speed::X2);
+    assert!(close(timeline.playtime(), 10.0));
+    assert_eq!(timeline.length(), Length::Short);
+
+    // portals before a start position are ignored
+    let start_pos = GDObject::parse_str("1,31,2,700,3,15,kA4,3");
+    let from_start = Timeline::from_start_pos(&data, &start_pos).unwrap();
+    assert!(close(from_start.time_at(700.0), 0.0));
+    assert!(close(from_start.time_at(1086.42), 386.42 / 468.0));
+    assert_eq!(Timeline::from_start_pos(&data, &objects[0]), None);
+
+    let mut level = GDLevelBuilder::new("Timeline").build().unwrap();
+    level.add_objects(objects);
+    level.refresh_metadata().unwrap();
+    assert_eq!(level.content.length, Length::Short);
+}